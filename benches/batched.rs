@@ -0,0 +1,127 @@
+// `delta_join_batched` 与 `delta_join` 的延迟/吞吐量对比:
+// - throughput: 把整个数据集分散到很多个时刻输入, 一直运行到结束的总时间
+// - latency: 每个时刻只输入少量订单, 从输入到 join 的输出追上这个时刻的时间
+// `delta_join_batched` 每个时刻只调度一次第二个 half_join, 吞吐量更高, 但是每个时刻的结果要多等一个时刻的 frontier
+
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dd_examples::dataset::Update;
+use dd_examples::delta_join::{delta_join, delta_join_batched, Oid, Order, Province, Uid, User};
+use dd_examples::gen::generate;
+use dd_examples::harness::run_join;
+use dd_examples::source::Inputs;
+
+const SCALE: u64 = 10_000;
+const TIMES: u64 = 100;
+
+#[allow(clippy::type_complexity)]
+fn spread() -> (Vec<Update<Order>>, Vec<Update<User>>, Vec<Update<Province>>) {
+    let dataset = generate(0, SCALE, 0);
+    let times = |i: usize| i as u64 % TIMES;
+    (
+        dataset
+            .orders
+            .into_iter()
+            .enumerate()
+            .map(|(i, o)| (o, times(i), 1))
+            .collect(),
+        dataset
+            .users
+            .into_iter()
+            .enumerate()
+            .map(|(i, u)| (u, times(i), 1))
+            .collect(),
+        dataset.provinces.into_iter().map(|p| (p, 0, 1)).collect(),
+    )
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("throughput");
+    group.sample_size(10);
+    group.bench_function("delta_join", |b| {
+        b.iter_batched(
+            spread,
+            |(o, u, p)| run_join(|o, u, p| delta_join(o, u, p), o, u, p),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("delta_join_batched", |b| {
+        b.iter_batched(
+            spread,
+            |(o, u, p)| run_join(|o, u, p| delta_join_batched(o, u, p), o, u, p),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// 先输入整个数据集, 然后每个时刻插入 `per_time` 个新订单, 返回 `iters` 个时刻的总延迟
+fn latency_of(batched: bool, per_time: u64, iters: u64) -> Duration {
+    timely::execute_directly(move |worker| {
+        let mut inputs = Inputs::new();
+        let probe = worker.dataflow(|scope| {
+            let (o, u, p) = inputs.to_collections(scope);
+            let joined = if batched {
+                delta_join_batched(&o, &u, &p)
+            } else {
+                delta_join(&o, &u, &p)
+            };
+            joined.probe()
+        });
+        let dataset = generate(0, SCALE, 0);
+        dataset
+            .orders
+            .into_iter()
+            .for_each(|o| inputs.order.insert(o));
+        dataset
+            .users
+            .into_iter()
+            .for_each(|u| inputs.user.insert(u));
+        dataset
+            .provinces
+            .into_iter()
+            .for_each(|p| inputs.province.insert(p));
+        inputs.advance_to(1);
+        inputs.flush();
+        worker.step_while(|| probe.less_than(&1));
+
+        let mut total = Duration::ZERO;
+        for i in 0..iters {
+            let time = inputs.time();
+            for j in 0..per_time {
+                inputs.order.insert(Order {
+                    oid: Oid(SCALE * 4 + i * per_time + j),
+                    price: 1,
+                    uid: Uid(j % SCALE),
+                });
+            }
+            inputs.advance_to(time + 1);
+            inputs.flush();
+            let start = Instant::now();
+            worker.step_while(|| probe.less_than(&(time + 1)));
+            total += start.elapsed();
+        }
+        total
+    })
+}
+
+fn latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("latency");
+    for per_time in [1, 100] {
+        group.bench_with_input(
+            BenchmarkId::new("delta_join", per_time),
+            &per_time,
+            |b, n| b.iter_custom(|iters| latency_of(false, *n, iters)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("delta_join_batched", per_time),
+            &per_time,
+            |b, n| b.iter_custom(|iters| latency_of(true, *n, iters)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput, latency);
+criterion_main!(benches);
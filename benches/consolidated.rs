@@ -0,0 +1,58 @@
+// `delta_join_consolidated` 与 `delta_join` 的对比: 每个时刻都有大量同一时刻内相互抵消的订单更新
+// (插入之后又撤回), `delta_join_consolidated` 在进入 half_join 之前把它们合并掉, 不再查找 arrangement
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use dd_examples::dataset::Update;
+use dd_examples::delta_join::{delta_join, delta_join_consolidated, Order, Province, User};
+use dd_examples::gen::generate;
+use dd_examples::harness::run_join;
+
+const SCALE: u64 = 10_000;
+const TIMES: u64 = 10;
+
+// 每个订单在时刻 i % TIMES 插入, 其中 `churn` 比例的订单在同一时刻又被撤回
+#[allow(clippy::type_complexity)]
+fn updates(churn: f64) -> (Vec<Update<Order>>, Vec<Update<User>>, Vec<Update<Province>>) {
+    let dataset = generate(0, SCALE, 0);
+    let churned = (dataset.orders.len() as f64 * churn) as usize;
+    let mut orders = Vec::new();
+    for (i, o) in dataset.orders.into_iter().enumerate() {
+        let time = i as u64 % TIMES;
+        if i < churned {
+            orders.push((o.clone(), time, -1));
+        }
+        orders.push((o, time, 1));
+    }
+    let users = dataset.users.into_iter().map(|u| (u, 0, 1)).collect();
+    let provinces = dataset.provinces.into_iter().map(|p| (p, 0, 1)).collect();
+    (orders, users, provinces)
+}
+
+fn consolidated(c: &mut Criterion) {
+    let mut group = c.benchmark_group("consolidated");
+    group.sample_size(10);
+    for churn in [0.0, 0.5, 0.9] {
+        group.bench_with_input(BenchmarkId::new("delta_join", churn), &churn, |b, churn| {
+            b.iter_batched(
+                || updates(*churn),
+                |(o, u, p)| run_join(|o, u, p| delta_join(o, u, p), o, u, p),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(
+            BenchmarkId::new("delta_join_consolidated", churn),
+            &churn,
+            |b, churn| {
+                b.iter_batched(
+                    || updates(*churn),
+                    |(o, u, p)| run_join(|o, u, p| delta_join_consolidated(o, u, p), o, u, p),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, consolidated);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+// 冷启动时构建初始 arrangement 的耗时: 从输入第一条数据开始, 到 join 的输出 frontier 越过第一个时刻为止。
+// 对每个 scale 依次测量 `regular_join_core`, `delta_join` 和 `delta_join_late_materialization`,
+// 用来观察 delta join 额外的 arrangement 在启动时的代价
+
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dd_examples::dataset::Dataset;
+use dd_examples::delta_join::Variant;
+use dd_examples::demo::PADDING;
+use dd_examples::gen;
+use dd_examples::source::Inputs;
+
+const SCALES: [u64; 3] = [1_000, 10_000, 100_000];
+
+fn build_once(variant: Variant, dataset: Dataset) -> Duration {
+    timely::execute_directly(move |worker| {
+        let mut inputs = Inputs::new();
+        let probe = worker.dataflow(|scope| {
+            let (order, user, province) = inputs.to_collections(scope);
+            variant.join(&order, &user, &province).probe()
+        });
+
+        let start = Instant::now();
+        dataset
+            .orders
+            .into_iter()
+            .for_each(|o| inputs.order.insert(o));
+        dataset
+            .users
+            .into_iter()
+            .for_each(|u| inputs.user.insert(u));
+        dataset
+            .provinces
+            .into_iter()
+            .for_each(|p| inputs.province.insert(p));
+        inputs.advance_to(1);
+        inputs.flush();
+        worker.step_while(|| probe.less_than(&1));
+        start.elapsed()
+    })
+}
+
+fn initial_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("initial_build");
+    group.sample_size(10);
+    for scale in SCALES {
+        // 每个 scale 只生成一次数据集, 每次迭代使用它的拷贝, 拷贝不计入耗时
+        let dataset = gen::generate(0, scale, PADDING);
+        for variant in [
+            Variant::RegularCore,
+            Variant::Delta,
+            Variant::DeltaLateMaterialization,
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(variant.name(), scale),
+                &dataset,
+                |b, dataset| {
+                    b.iter_custom(|iters| {
+                        (0..iters)
+                            .map(|_| build_once(variant, dataset.clone()))
+                            .sum()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, initial_build);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+// 加载同一份数据集的耗时: `load_csv_dir` 解析 CSV 文本, `from_bincode_mmap` 通过 mmap 反序列化 bincode
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dd_examples::dataset::Dataset;
+use dd_examples::gen::generate;
+use dd_examples::load::{
+    from_bincode_mmap, load_csv_dir, save_bincode, ORDER_HEADER, PROVINCE_HEADER, USER_HEADER,
+};
+
+fn write_csv_dir(dir: &Path, dataset: &Dataset) {
+    let mut orders = format!("{}\n", ORDER_HEADER);
+    for o in &dataset.orders {
+        writeln!(orders, "{},{},{}", o.oid.0, o.price, o.uid.0).unwrap();
+    }
+    let mut users = format!("{}\n", USER_HEADER);
+    for u in &dataset.users {
+        writeln!(users, "{},{},{}", u.uid.0, u.pid.0, u.padding).unwrap();
+    }
+    let mut provinces = format!("{}\n", PROVINCE_HEADER);
+    for p in &dataset.provinces {
+        writeln!(provinces, "{},{}", p.pid.0, p.name).unwrap();
+    }
+    fs::write(dir.join("orders.csv"), orders).unwrap();
+    fs::write(dir.join("users.csv"), users).unwrap();
+    fs::write(dir.join("provinces.csv"), provinces).unwrap();
+}
+
+fn load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    for scale in [10_000, 100_000] {
+        let dir: PathBuf = std::env::temp_dir().join(format!("dd_examples_load_{}", scale));
+        fs::create_dir_all(&dir).unwrap();
+        let dataset = generate(0, scale, 64);
+        write_csv_dir(&dir, &dataset);
+        let bincode = dir.join("dataset.bin");
+        save_bincode(&bincode, &dataset).unwrap();
+        // 两种方式加载的结果相同
+        assert_eq!(load_csv_dir(&dir).unwrap(), dataset);
+        assert_eq!(from_bincode_mmap(&bincode).unwrap(), dataset);
+
+        group.bench_with_input(BenchmarkId::new("csv", scale), &dir, |b, dir| {
+            b.iter(|| load_csv_dir(dir).unwrap())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("bincode_mmap", scale),
+            &bincode,
+            |b, path| b.iter(|| from_bincode_mmap(path).unwrap()),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, load);
+criterion_main!(benches);
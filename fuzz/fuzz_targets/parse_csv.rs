@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// 任意输入都只能返回 `Err`, 不能 panic。输入是原始的字节, 与从文件加载时一样先经过 `decode_utf8`
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = dd_examples::load::decode_utf8(data) {
+        let _ = dd_examples::load::parse_orders(input);
+        let _ = dd_examples::load::parse_users(input);
+        let _ = dd_examples::load::parse_provinces(input);
+    }
+});
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use differential_dataflow::{AsCollection, Collection, Data};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Broadcast, Concat, Map, Operator};
+use timely::dataflow::{Scope, Stream};
+
+use crate::delta_join::{delta_join, regular_join_core, Order, Province, User};
+
+type Row = (Order, User, Province);
+
+// 最终算子的 input, 按照时间汇总之后再决定输出哪一边的结果
+#[derive(Clone, Debug)]
+enum Tagged {
+    Delta(Row, isize),
+    Regular(Row, isize),
+    // 某个 worker 上某个时刻 input 的更新数量
+    Count(usize),
+}
+
+// 每个时刻 input 的更新数量 (|diff| 之和), 每个 worker 每个时刻输出一次
+fn updates_per_time<S, D>(collection: &Collection<S, D>) -> Stream<S, (u64, usize)>
+where
+    S: Scope<Timestamp = u64>,
+    D: Data,
+{
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    collection.inner.unary_notify(
+        Pipeline,
+        "UpdatesPerTime",
+        None,
+        move |input, output, notificator| {
+            input.for_each(|time, data| {
+                for (_, t, r) in data.iter() {
+                    // 每个时刻只在第一次出现时注册一次通知
+                    let count = counts.entry(*t).or_insert_with(|| {
+                        notificator.notify_at(time.delayed(t));
+                        0
+                    });
+                    *count += r.unsigned_abs();
+                }
+            });
+            notificator.for_each(|time, _, _| {
+                if let Some(count) = counts.remove(time.time()) {
+                    output.session(&time).give((*time.time(), count));
+                }
+            });
+        },
+    )
+}
+
+// 根据 input 的更新速率在 `delta_join` 和 `regular_join_core` 之间切换: 某个时刻所有 input 的更新数量
+// (所有 worker 的总和) 不小于 `threshold` 时输出 `delta_join` 在这个时刻的结果, 否则输出 `regular_join_core` 的结果。
+// 两种实现在每个时刻产生的更新累积之后完全相同, 切换只是选择转发哪一边的结果, 所以切换前后结果都是正确的。
+// 这是一个研究性质的示例, 并不会真正节省资源: 为了能够随时切换, 两种实现一直同时运行, 每个更新都会被两边处理,
+// 两边的 arrangement 也都需要一直维护, CPU 和内存的开销都是两者之和, "切换" 只是选择转发哪一边的结果。
+// 按需构建 arrangement 需要从 trace 的历史中补齐切换之前的状态, 这里没有实现。
+// 另外每个时刻的结果要等到这个时刻结束之后才能确定来源, 会增加一个时刻的延迟
+pub fn adaptive_join<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    threshold: usize,
+) -> Collection<S, Row>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let delta = delta_join(order, user, province)
+        .inner
+        .map(|(d, t, r)| (t, Tagged::Delta(d, r)));
+    let regular = regular_join_core(order, user, province)
+        .inner
+        .map(|(d, t, r)| (t, Tagged::Regular(d, r)));
+    // 每个 worker 的更新数量都广播到所有的 worker, 保证所有 worker 对同一时刻做出相同的选择
+    let counts = updates_per_time(order)
+        .concat(&updates_per_time(user))
+        .concat(&updates_per_time(province))
+        .broadcast()
+        .map(|(t, count)| (t, Tagged::Count(count)));
+
+    let mut stash: HashMap<u64, Vec<Tagged>> = HashMap::new();
+    delta
+        .concat(&regular)
+        .concat(&counts)
+        .unary_notify(
+            Pipeline,
+            "AdaptiveJoin",
+            None,
+            move |input, output, notificator| {
+                input.for_each(|time, data| {
+                    for (t, tagged) in data.iter().cloned() {
+                        stash
+                            .entry(t)
+                            .or_insert_with(|| {
+                                notificator.notify_at(time.delayed(&t));
+                                Vec::new()
+                            })
+                            .push(tagged);
+                    }
+                });
+                notificator.for_each(|time, _, _| {
+                    if let Some(tagged) = stash.remove(time.time()) {
+                        let t = *time.time();
+                        let count: usize = tagged
+                            .iter()
+                            .map(|x| match x {
+                                Tagged::Count(c) => *c,
+                                _ => 0,
+                            })
+                            .sum();
+                        let use_delta = count >= threshold;
+                        output
+                            .session(&time)
+                            .give_iterator(tagged.into_iter().filter_map(|x| match x {
+                                Tagged::Delta(d, r) if use_delta => Some((d, t, r)),
+                                Tagged::Regular(d, r) if !use_delta => Some((d, t, r)),
+                                _ => None,
+                            }));
+                    }
+                });
+            },
+        )
+        .as_collection()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta_join::{Pid, Variant};
+    use crate::gen::{generate, PROVINCES};
+    use crate::harness::{self, run_join};
+
+    #[test]
+    fn output_is_correct_across_burst_and_idle() {
+        let dataset = generate(0, 20, 0);
+        let provinces: Vec<_> = dataset
+            .provinces
+            .iter()
+            .map(|p| (p.clone(), 0, 1))
+            .collect();
+        let users: Vec<_> = dataset.users.iter().map(|u| (u.clone(), 0, 1)).collect();
+        // 时刻 0 一次输入大部分订单, 之后每个时刻只有少量的更新
+        let (burst, idle) = dataset.orders.split_at(60);
+        let mut orders: Vec<_> = burst.iter().map(|o| (o.clone(), 0, 1)).collect();
+        let mut users_later = Vec::new();
+        for (i, o) in idle.iter().take(5).enumerate() {
+            let t = i as u64 + 1;
+            orders.push((o.clone(), t, 1));
+            orders.push((burst[i].clone(), t, -1));
+            let u = &dataset.users[i];
+            users_later.push((u.clone(), t, -1));
+            users_later.push((
+                User {
+                    pid: Pid((u.pid.0 + 1) % PROVINCES),
+                    ..u.clone()
+                },
+                t,
+                1,
+            ));
+        }
+        let users: Vec<_> = users.into_iter().chain(users_later).collect();
+
+        let expected = harness::run(
+            Variant::Regular,
+            orders.clone(),
+            users.clone(),
+            provinces.clone(),
+        );
+        assert!(!expected.is_empty());
+        let adaptive = run_join(
+            |o, u, p| adaptive_join(o, u, p, 20),
+            orders,
+            users,
+            provinces,
+        );
+        assert_eq!(adaptive, expected);
+    }
+}
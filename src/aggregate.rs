@@ -0,0 +1,1401 @@
+use std::collections::BTreeMap;
+
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::{Count, Join, Reduce, Threshold};
+use differential_dataflow::{AsCollection, Collection};
+use timely::dataflow::operators::{Filter, Map};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{delta_join, Oid, Order, Pid, Province, SignedOrder, Uid, User};
+use crate::util::OrdF64;
+
+// 所有订单价格的中位数, 整个 collection 只有一个 group, 每次有订单变化都会重新排序计算。
+// 订单数为偶数时取中间两个价格的平均值 (向下取整), 没有订单时不输出任何数据。
+// 累计的 multiplicity 为负数的价格会被忽略
+pub fn global_median_price<S>(order: &Collection<S, Order>) -> Collection<S, u64>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order
+        .map(|o| ((), o.price))
+        .reduce(|_, input, output| {
+            // input 已经按照价格排好序。撤回多于插入的价格 (r <= 0) 没有对应的订单, 直接忽略
+            let positive: Vec<_> = input.iter().filter(|(_, r)| *r > 0).collect();
+            let count: isize = positive.iter().map(|(_, r)| *r).sum();
+            let nth = |n: isize| {
+                let mut seen = 0;
+                positive.iter().find_map(|(price, r)| {
+                    seen += *r;
+                    (seen > n).then_some(**price)
+                })
+            };
+            // count 为奇数时两个位置相同
+            let median = match (nth((count - 1) / 2), nth(count / 2)) {
+                (Some(lo), Some(hi)) => lo + (hi - lo) / 2,
+                _ => return,
+            };
+            output.push((median, 1));
+        })
+        .map(|(_, median)| median)
+}
+
+// 每个用户在各个省份的订单总价, 相当于把省份 "pivot" 成列: (uid, {pid: total})。
+// 这里使用 BTreeMap 而不是 HashMap, 因为 collection 中的数据需要满足 `Ord`。
+// 某个省份的总价变为 0 (订单都被撤回) 之后, 这个 pid 会从 map 中移除; 用户没有任何订单时不输出
+pub fn pivot_user_province_totals<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+) -> Collection<S, (Uid, BTreeMap<Pid, u64>)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order
+        .map(|o| (o.uid, o.price))
+        .join_map(&user.map(|u| (u.uid, u.pid)), |uid, price, pid| {
+            (*uid, (*pid, *price))
+        })
+        .reduce(|_, input, output| {
+            let mut totals = BTreeMap::new();
+            for ((pid, price), r) in input {
+                *totals.entry(*pid).or_insert(0i128) += *price as i128 * *r as i128;
+            }
+            let totals: BTreeMap<_, _> = totals
+                .into_iter()
+                .filter(|(_, total)| *total > 0)
+                .map(|(pid, total)| (pid, total as u64))
+                .collect();
+            if !totals.is_empty() {
+                output.push((totals, 1));
+            }
+        })
+}
+
+// 每个省份的订单总价, 只统计能够 join 到用户和省份的订单
+pub fn total_price_per_province<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, u64)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    province_price_totals(&order.map(|o| (o.uid, o.price as i128)), user, province)
+        .map(|(pid, total)| (pid, total as u64))
+}
+
+// 与 `total_price_per_province` 相同, 但是价格可以为负数 (退款), 所以总价也可能为负数或者 0。
+// 只要省份中还有订单, 即使总价为 0 也会保留这一行; 所有订单都被撤回之后这一行才会消失
+pub fn signed_total_price_per_province<S>(
+    order: &Collection<S, SignedOrder>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, i64)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    province_price_totals(&order.map(|o| (o.uid, o.price as i128)), user, province)
+        .map(|(pid, total)| (pid, total as i64))
+}
+
+fn province_price_totals<S>(
+    prices: &Collection<S, (Uid, i128)>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, i128)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    prices
+        .join_map(&user.map(|u| (u.uid, u.pid)), |_, price, pid| {
+            (*pid, *price)
+        })
+        .semijoin(&province.map(|p| p.pid).distinct())
+        .reduce(|_, input, output| {
+            let count: isize = input.iter().map(|(_, r)| *r).sum();
+            let total: i128 = input.iter().map(|(price, r)| **price * *r as i128).sum();
+            if count > 0 {
+                output.push((total, 1));
+            }
+        })
+}
+
+// join 结果的总行数 (multiplicity 之和), 随着 input 的变化持续更新, 可以用来做监控。
+// 所有的行都被撤回之后, `count` 不会输出 0, 而是直接撤回之前的计数, 也就是说 collection 变为空
+pub fn join_cardinality<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, isize>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(order, user, province)
+        .map(|_| ())
+        .count()
+        .map(|(_, count)| count)
+}
+
+// 订单总价超过 `budget` 的省份, 用于告警。基于 `total_price_per_province` 做 filter,
+// 当订单被撤回导致总价回落到 `budget` 及以下时, 这个省份会被撤回
+pub fn provinces_over_budget<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    budget: u64,
+) -> Collection<S, (Pid, u64)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    total_price_per_province(order, user, province).filter(move |(_, total)| *total > budget)
+}
+
+// 每个用户的订单所在的省份名称, 已排序并且去重。
+// 用户在新的省份下单会增加一个名称, 某个省份的最后一个订单被撤回之后对应的名称会被移除
+pub fn province_names_per_user<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Uid, Vec<String>)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(order, user, province)
+        .map(|(_, u, p)| (u.uid, p.name))
+        .reduce(|_, input, output| {
+            // input 已经按照名称排序, 并且每个名称只出现一次
+            let names: Vec<String> = input
+                .iter()
+                .filter(|(_, r)| *r > 0)
+                .map(|(name, _)| (*name).clone())
+                .collect();
+            if !names.is_empty() {
+                output.push((names, 1));
+            }
+        })
+}
+
+// 每个省份订单价格的直方图: 按照 `price / bucket` 分桶, 输出 (pid, 桶的编号, 订单数), 没有订单的桶不输出
+pub fn order_count_histogram<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    bucket: u64,
+) -> Collection<S, (Pid, u64, isize)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    assert!(bucket > 0, "bucket must be positive");
+    delta_join(order, user, province)
+        .map(move |(o, _, p)| (p.pid, o.price / bucket))
+        .count()
+        .map(|((pid, b), count)| (pid, b, count))
+}
+
+// 每个用户的第一个订单 (oid 最小的订单), 用于 cohort 分析。
+// 当前的第一个订单被撤回之后, oid 次小的订单会成为新的第一个订单
+pub fn first_order_per_user<S>(order: &Collection<S, Order>) -> Collection<S, (Uid, Order)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order.map(|o| (o.uid, o)).reduce(|_, input, output| {
+        // `Order` 首先按照 oid 排序, 所以 input 中的第一个就是 oid 最小的订单
+        if let Some((o, _)) = input.iter().find(|(_, r)| *r > 0) {
+            output.push(((*o).clone(), 1));
+        }
+    })
+}
+
+// 按照价格的十分位数统计每个省份 join 到的订单数, 输出 (decile, (pid, 订单数)), decile 的范围是 0 到 9。
+// 十分位数根据所有 join 到的订单的价格分布计算: 价格从小到大排序后, 排名为 rank (从 0 开始) 的订单
+// 属于第 `rank * 10 / n` 个十分位, 相同价格的订单属于同一个十分位 (取其中第一个的排名)。
+// 价格分布是一个全局的 reduce, 任何订单的变化都可能改变其他价格所在的十分位, 进而导致大量输出的更新
+pub fn orders_by_price_decile<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (u8, (Pid, usize))>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let joined = delta_join(order, user, province);
+    let deciles = joined
+        .map(|(o, _, _)| ((), o.price))
+        .reduce(|_, input, output| {
+            // input 已经按照价格排好序
+            let count: isize = input.iter().map(|(_, r)| *r).sum();
+            if count <= 0 {
+                return;
+            }
+            let mut rank = 0;
+            for (price, r) in input {
+                if *r <= 0 {
+                    continue;
+                }
+                let decile = (rank as i128 * 10 / count as i128) as u8;
+                output.push(((**price, decile), 1));
+                rank += *r;
+            }
+        })
+        .map(|(_, price_decile)| price_decile);
+    joined
+        .map(|(o, _, p)| (o.price, p.pid))
+        .join_map(&deciles, |_, pid, decile| (*decile, *pid))
+        .count()
+        .map(|((decile, pid), count)| (decile, (pid, count as usize)))
+}
+
+// 按照 oid 顺序的累计价格: 每个订单对应所有 oid 小于等于它的订单价格之和, 用于流水账视图。
+// 这是一个全局的前缀和, 在中间插入或者撤回一个订单时, 所有 oid 更大的订单的累计值都会改变,
+// 所以每次变化都会重新计算整个前缀和, 并更新其后所有订单的输出
+pub fn running_total_by_oid<S>(order: &Collection<S, Order>) -> Collection<S, (Oid, u64)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order
+        .map(|o| ((), (o.oid, o.price)))
+        .reduce(|_, input, output| {
+            // input 已经按照 oid 排好序
+            let mut total: i128 = 0;
+            for ((oid, price), r) in input {
+                if *r <= 0 {
+                    continue;
+                }
+                total += *price as i128 * *r as i128;
+                output.push(((*oid, total as u64), 1));
+            }
+        })
+        .map(|(_, running)| running)
+}
+
+// 订单数最多的省份, 输出 (pid, 订单数), 只统计能够 join 到用户和省份的订单。
+// 订单数相同时取 pid 最小的省份; 没有任何订单时不输出
+pub fn most_active_province<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, usize)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(order, user, province)
+        .map(|(_, _, p)| p.pid)
+        .count()
+        .map(|(pid, count)| ((), (pid, count)))
+        .reduce(|_, input, output| {
+            // input 按照 pid 排序, 只有订单数严格更大时才替换, 所以相同订单数时保留 pid 最小的省份
+            let mut best: Option<(Pid, isize)> = None;
+            for ((pid, count), r) in input {
+                if *r > 0 && best.is_none_or(|(_, c)| *count > c) {
+                    best = Some((*pid, *count));
+                }
+            }
+            if let Some((pid, count)) = best {
+                output.push(((pid, count as usize), 1));
+            }
+        })
+        .map(|(_, mode)| mode)
+}
+
+// 每个省份订单价格的基尼系数, 只统计能够 join 到用户和省份的订单。
+// 价格从小到大排序为 x_1..x_n 之后, G = 2 * sum(i * x_i) / (n * sum(x_i)) - (n + 1) / n。
+// 只有一个订单 (或者所有价格都为 0) 时基尼系数为 0, 没有订单的省份不输出。
+// 每次省份中的订单变化都会重新排序计算, f64 不满足 `Ord`, 所以输出使用 `OrdF64`
+pub fn price_gini_per_province<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, OrdF64)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(order, user, province)
+        .map(|(o, _, p)| (p.pid, o.price))
+        .reduce(|_, input, output| {
+            // input 已经按照价格排好序, multiplicity 为 r 的价格相当于 r 个相同的价格
+            let (mut n, mut sum, mut weighted) = (0f64, 0f64, 0f64);
+            for (price, r) in input {
+                for _ in 0..(*r).max(0) {
+                    n += 1.0;
+                    sum += **price as f64;
+                    weighted += n * **price as f64;
+                }
+            }
+            if n == 0.0 {
+                return;
+            }
+            let gini = if sum == 0.0 {
+                0.0
+            } else {
+                2.0 * weighted / (n * sum) - (n + 1.0) / n
+            };
+            output.push((OrdF64(gini), 1));
+        })
+}
+
+// 每个用户相邻两个订单 (按照 oid 排序) 之间 oid 差值的最大值, 用于发现异常。
+// 在中间插入订单会缩小最大间隔, 撤回订单则可能扩大它; 少于两个订单的用户不输出
+pub fn max_oid_gap_per_user<S>(order: &Collection<S, Order>) -> Collection<S, (Uid, u64)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order.map(|o| (o.uid, o.oid)).reduce(|_, input, output| {
+        // input 已经按照 oid 排好序
+        let oids = input.iter().filter(|(_, r)| *r > 0).map(|(oid, _)| oid.0);
+        let gap = oids
+            .clone()
+            .zip(oids.skip(1))
+            .map(|(prev, next)| next - prev)
+            .max();
+        if let Some(gap) = gap {
+            output.push((gap, 1));
+        }
+    })
+}
+
+// 每个省份中出现过的所有不同的订单价格, 从小到大排列, 只统计能够 join 到用户和省份的订单。
+// 某个价格的最后一个订单被撤回之后, 这个价格会从列表中移除; 没有订单的省份不输出
+pub fn distinct_prices_per_province<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, Vec<u64>)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(order, user, province)
+        .map(|(o, _, p)| (p.pid, o.price))
+        .reduce(|_, input, output| {
+            // input 已经按照价格排好序, 并且相同的价格已经合并
+            let prices: Vec<u64> = input
+                .iter()
+                .filter(|(_, r)| *r > 0)
+                .map(|(price, _)| **price)
+                .collect();
+            if !prices.is_empty() {
+                output.push((prices, 1));
+            }
+        })
+}
+
+// 每个用户的消费总额, 用户没有订单时不输出
+pub fn user_spend<S>(order: &Collection<S, Order>) -> Collection<S, (Uid, u64)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    user_spend_rounded(order, 1)
+}
+
+// 与 `user_spend` 相同, 但是总额四舍五入到 `unit` 的整数倍, 例如 `unit` 为 100 时 149 变为 100, 150 变为 200。
+// 四舍五入作用于累积之后的总额, 而不是每个订单, 所以撤回订单之后总额会重新计算并重新四舍五入。
+// 向上取整会超出 u64 的范围时改为向下取整
+pub fn user_spend_rounded<S>(order: &Collection<S, Order>, unit: u64) -> Collection<S, (Uid, u64)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    assert!(unit > 0, "unit must be positive");
+    order
+        .map(|o| (o.uid, o.price))
+        .reduce(move |_, input, output| {
+            let count: isize = input.iter().map(|(_, r)| *r).sum();
+            if count <= 0 {
+                return;
+            }
+            let total: i128 = input.iter().map(|(p, r)| **p as i128 * *r as i128).sum();
+            let total = u64::try_from(total.max(0)).unwrap_or(u64::MAX);
+            // 使用商和余数计算, 避免 `total + unit / 2` 溢出
+            let (q, rem) = (total / unit, total % unit);
+            let rounded = if rem >= unit - unit / 2 {
+                (q + 1).checked_mul(unit).unwrap_or(q * unit)
+            } else {
+                q * unit
+            };
+            output.push((rounded, 1));
+        })
+}
+
+// 每个省份的订单总价占所有省份总价的百分比, 所有省份的百分比之和为 100。
+// 百分比在一个全局的 reduce 中计算, 任何订单的变化都会改变总价, 所以所有省份的百分比都会被重新计算;
+// 总价为 0 时不输出
+pub fn province_revenue_share<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, OrdF64)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    total_price_per_province(order, user, province)
+        .map(|total| ((), total))
+        .reduce(|_, input, output| {
+            let global: u128 = input
+                .iter()
+                .filter(|(_, r)| *r > 0)
+                .map(|((_, total), _)| *total as u128)
+                .sum();
+            if global == 0 {
+                return;
+            }
+            for ((pid, total), r) in input {
+                if *r > 0 {
+                    let share = *total as f64 * 100.0 / global as f64;
+                    output.push(((*pid, OrdF64(share)), 1));
+                }
+            }
+        })
+        .map(|(_, share)| share)
+}
+
+// 每个用户的省份忠诚度: 用户订单最多的省份中的订单数占用户所有订单数的比例, 只统计省份存在的订单。
+// 每个订单的省份是下单时用户所在的省份, 用户之后更换省份不会改变已有订单的省份, 所以用户在 A 下了 3 单,
+// 搬到 B 之后又下了 1 单时忠诚度为 0.75。为此需要把时间当作数据:
+// - 每个订单第一次出现的时刻 (下单时刻), 只记录插入, 订单被撤回时下单时刻依然保留, 撤回之后再插入同一个 oid
+//   时沿用最早的下单时刻。撤回的订单通过与订单本身的 join 被去掉
+// - 每个用户的省份历史 (时刻, pid), 每次插入用户记录都会追加一条, 删除用户记录不会撤回历史, 历史只增不减
+// 然后对每个订单取下单时刻之前最后一次的省份 (用户晚于订单出现时取用户最早的省份), 再按照 (uid, pid) 计数,
+// 对每个用户取最大值并除以总数。省份历史会一直增长, 这里假设用户更换省份的次数不多。
+// 没有订单的用户不输出, f64 不满足 `Ord`, 所以输出使用 `OrdF64`
+pub fn user_loyalty<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Uid, OrdF64)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    // 每个订单的下单时刻, 同一个 oid 多次插入时取最早的一次
+    let placed_at = order
+        .inner
+        .filter(|(_, _, r)| *r > 0)
+        .map(|(o, t, _)| ((o.oid, t), t, 1))
+        .as_collection()
+        .reduce(|_, input, output| output.push((*input[0].0, 1)));
+    let placed = order
+        .map(|o| (o.oid, o.uid))
+        .join_map(&placed_at, |oid, uid, placed| (*uid, (*oid, *placed)));
+
+    // (uid, (时刻, pid)) 形式的省份历史
+    let history = user
+        .inner
+        .filter(|(_, _, r)| *r > 0)
+        .map(|(u, t, _)| ((u.uid, (t, u.pid)), t, 1))
+        .as_collection()
+        .distinct();
+
+    placed
+        .join_map(&history, |uid, (oid, placed), history| {
+            ((*oid, *uid, *placed), *history)
+        })
+        .reduce(|(_, _, placed), input, output| {
+            // input 按照时刻排序
+            let (_, pid) = input
+                .iter()
+                .take_while(|(h, _)| h.0 <= *placed)
+                .last()
+                .unwrap_or(&input[0])
+                .0;
+            output.push((*pid, 1));
+        })
+        .map(|((_, uid, _), pid)| (pid, uid))
+        .semijoin(&province.map(|p| p.pid).distinct())
+        .map(|(pid, uid)| (uid, pid))
+        .count()
+        .map(|((uid, pid), count)| (uid, (pid, count)))
+        .reduce(|_, input, output| {
+            let counts = input.iter().filter(|(_, r)| *r > 0).map(|((_, c), _)| *c);
+            let total: isize = counts.clone().sum();
+            let top = counts.max().unwrap_or(0);
+            if total > 0 {
+                output.push((OrdF64(top as f64 / total as f64), 1));
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+
+    fn order(oid: u64) -> Order {
+        Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(1),
+        }
+    }
+
+    #[test]
+    fn global_median_price_odd_then_even() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut o = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                global_median_price(&order).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                o
+            });
+            let priced = |oid, price| Order {
+                price,
+                ..order(oid)
+            };
+            // 时刻 0: 10, 20, 30, 中位数为 20
+            for (oid, price) in [(1, 10), (2, 20), (3, 30)] {
+                o.insert(priced(oid, price));
+            }
+            // 时刻 1: 加入 40, 订单数为偶数, 取 20 和 30 的平均值
+            o.advance_to(1);
+            o.insert(priced(4, 40));
+            // 时刻 2: 所有订单都被撤回, 不再有中位数
+            o.advance_to(2);
+            for (oid, price) in [(1, 10), (2, 20), (3, 30), (4, 40)] {
+                o.remove(priced(oid, price));
+            }
+            o.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![(20, 0, 1), (20, 1, -1), (25, 1, 1), (25, 2, -1)]
+        );
+    }
+
+    #[test]
+    fn global_median_price_ignores_negative_multiplicities() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut o = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                global_median_price(&order).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                o
+            });
+            let priced = |oid, price| Order {
+                price,
+                ..order(oid)
+            };
+            // 时刻 0: 只有撤回, 没有任何中位数
+            o.remove(priced(1, 20));
+            o.advance_to(1);
+            // 时刻 1: 10, 30, 30 以及一个多余的撤回 20
+            o.insert(priced(2, 10));
+            o.insert(priced(3, 30));
+            o.insert(priced(4, 30));
+            o.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(output, vec![(30, 1, 1)]);
+    }
+
+    #[test]
+    fn user_spend_rounded_near_u64_max() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut o = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                user_spend_rounded(&order, 1000)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                o
+            });
+            o.insert(Order {
+                price: u64::MAX,
+                ..order(1)
+            });
+            o.advance_to(1);
+            o.remove(Order {
+                price: u64::MAX,
+                ..order(1)
+            });
+            o.insert(order(2));
+            o.insert(order(3));
+            o.insert(Order {
+                price: 480,
+                ..order(4)
+            });
+            o.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Uid(1), 1000), 1, 1),
+                ((Uid(1), u64::MAX / 1000 * 1000), 0, 1),
+                ((Uid(1), u64::MAX / 1000 * 1000), 1, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn user_loyalty_counts_province_at_order_time() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                user_loyalty(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            let user = |pid| User {
+                uid: Uid(1),
+                pid: Pid(pid),
+                padding: String::new(),
+            };
+            for pid in [1, 2] {
+                p.insert(Province {
+                    pid: Pid(pid),
+                    name: format!("p{}", pid),
+                });
+            }
+            // 在省份 1 下了 3 单
+            u.insert(user(1));
+            (0..3).for_each(|i| o.insert(order(i)));
+            // 搬到省份 2 之后下了 1 单
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            u.remove(user(1));
+            u.insert(user(2));
+            o.insert(order(3));
+            // 在省份 2 又下了 1 单
+            o.advance_to(2);
+            u.advance_to(2);
+            p.advance_to(2);
+            o.insert(order(4));
+            // 撤回省份 1 的一单, 两个省份各 2 单
+            o.advance_to(3);
+            u.advance_to(3);
+            p.advance_to(3);
+            o.remove(order(0));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Uid(1), OrdF64(0.5)), 3, 1),
+                ((Uid(1), OrdF64(0.6)), 2, 1),
+                ((Uid(1), OrdF64(0.6)), 3, -1),
+                ((Uid(1), OrdF64(0.75)), 1, 1),
+                ((Uid(1), OrdF64(0.75)), 2, -1),
+                ((Uid(1), OrdF64(1.0)), 0, 1),
+                ((Uid(1), OrdF64(1.0)), 1, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn pivot_user_province_totals_grows_and_shrinks() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                pivot_user_province_totals(&order, &user)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u)
+            });
+            let user = |pid| User {
+                uid: Uid(1),
+                pid: Pid(pid),
+                padding: String::new(),
+            };
+            // 时刻 0: 用户在省份 1 下了 1 单
+            u.insert(user(1));
+            o.insert(order(1));
+            // 时刻 1: 用户同时出现在省份 2, map 中多了一个 pid
+            o.advance_to(1);
+            u.advance_to(1);
+            u.insert(user(2));
+            // 时刻 2: 新的订单计入所有省份
+            o.advance_to(2);
+            u.advance_to(2);
+            o.insert(Order {
+                price: 5,
+                ..order(2)
+            });
+            // 时刻 3: 用户离开省份 1, 这个 pid 从 map 中移除
+            o.advance_to(3);
+            u.advance_to(3);
+            u.remove(user(1));
+            // 时刻 4: 订单全部撤回, 不再输出
+            o.advance_to(4);
+            u.advance_to(4);
+            o.remove(order(1));
+            o.remove(Order {
+                price: 5,
+                ..order(2)
+            });
+            o.close();
+            u.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        let totals = |entries: &[(u64, u64)]| {
+            let totals: BTreeMap<_, _> = entries.iter().map(|(pid, t)| (Pid(*pid), *t)).collect();
+            (Uid(1), totals)
+        };
+        assert_eq!(
+            output,
+            vec![
+                (totals(&[(1, 10)]), 0, 1),
+                (totals(&[(1, 10)]), 1, -1),
+                (totals(&[(1, 10), (2, 10)]), 1, 1),
+                (totals(&[(1, 10), (2, 10)]), 2, -1),
+                (totals(&[(1, 15), (2, 15)]), 2, 1),
+                (totals(&[(1, 15), (2, 15)]), 3, -1),
+                (totals(&[(2, 15)]), 3, 1),
+                (totals(&[(2, 15)]), 4, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn signed_totals_keep_zero_net_rows() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                signed_total_price_per_province(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            let signed = |oid, price, uid| SignedOrder {
+                oid: Oid(oid),
+                price,
+                uid: Uid(uid),
+            };
+            for id in [1, 2] {
+                u.insert(User {
+                    uid: Uid(id),
+                    pid: Pid(id),
+                    padding: String::new(),
+                });
+                p.insert(Province {
+                    pid: Pid(id),
+                    name: format!("p{}", id),
+                });
+            }
+            // 省份 1: 10 - 4 = 6; 省份 2: 5 - 5 = 0, 但是仍然有订单, 这一行会保留
+            o.insert(signed(1, 10, 1));
+            o.insert(signed(2, -4, 1));
+            o.insert(signed(3, 5, 2));
+            o.insert(signed(4, -5, 2));
+            // 时刻 1: 省份 2 的订单全部撤回, 这一行才消失
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.remove(signed(3, 5, 2));
+            o.remove(signed(4, -5, 2));
+            // 时刻 2: 省份 1 只剩下退款, 总价为负数
+            o.advance_to(2);
+            u.advance_to(2);
+            p.advance_to(2);
+            o.remove(signed(1, 10, 1));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Pid(1), -4), 2, 1),
+                ((Pid(1), 6), 0, 1),
+                ((Pid(1), 6), 2, -1),
+                ((Pid(2), 0), 0, 1),
+                ((Pid(2), 0), 1, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn join_cardinality_tracks_inserts_and_retractions() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                join_cardinality(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            u.insert(User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            });
+            p.insert(Province {
+                pid: Pid(1),
+                name: "p1".to_string(),
+            });
+            (1..4).for_each(|i| o.insert(order(i)));
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.remove(order(1));
+            // 时刻 2: 所有的行都被撤回, 不会输出 0
+            o.advance_to(2);
+            u.advance_to(2);
+            p.advance_to(2);
+            (2..4).for_each(|i| o.remove(order(i)));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(output, vec![(2, 1, 1), (2, 2, -1), (3, 0, 1), (3, 1, -1)]);
+    }
+
+    #[test]
+    fn province_crosses_budget_and_drops_back() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                provinces_over_budget(&order, &user, &province, 25)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            u.insert(User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            });
+            p.insert(Province {
+                pid: Pid(1),
+                name: "p1".to_string(),
+            });
+            // 时刻 0: 总价 20, 没有超过预算
+            (1..3).for_each(|i| o.insert(order(i)));
+            // 时刻 1: 总价 30, 超过预算
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.insert(order(3));
+            // 时刻 2: 总价 35
+            o.advance_to(2);
+            u.advance_to(2);
+            p.advance_to(2);
+            o.insert(Order {
+                price: 5,
+                ..order(4)
+            });
+            // 时刻 3: 撤回两单, 总价回落到 15
+            o.advance_to(3);
+            u.advance_to(3);
+            p.advance_to(3);
+            (1..3).for_each(|i| o.remove(order(i)));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Pid(1), 30), 1, 1),
+                ((Pid(1), 30), 2, -1),
+                ((Pid(1), 35), 2, 1),
+                ((Pid(1), 35), 3, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn province_names_per_user_sorted_and_updated() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                province_names_per_user(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            let user = |pid| User {
+                uid: Uid(1),
+                pid: Pid(pid),
+                padding: String::new(),
+            };
+            // 名称的顺序与 pid 的顺序相反
+            for (pid, name) in [(1, "xian"), (2, "beijing")] {
+                p.insert(Province {
+                    pid: Pid(pid),
+                    name: name.to_string(),
+                });
+            }
+            u.insert(user(1));
+            o.insert(order(1));
+            // 时刻 1: 用户同时出现在省份 2, 订单到达了两个省份
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            u.insert(user(2));
+            // 时刻 2: 用户离开省份 1
+            o.advance_to(2);
+            u.advance_to(2);
+            p.advance_to(2);
+            u.remove(user(1));
+            // 时刻 3: 最后一个订单被撤回
+            o.advance_to(3);
+            u.advance_to(3);
+            p.advance_to(3);
+            o.remove(order(1));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        let names = |names: &[&str]| (Uid(1), names.iter().map(|n| n.to_string()).collect());
+        assert_eq!(
+            output,
+            vec![
+                (names(&["beijing"]), 2, 1),
+                (names(&["beijing"]), 3, -1),
+                (names(&["beijing", "xian"]), 1, 1),
+                (names(&["beijing", "xian"]), 2, -1),
+                (names(&["xian"]), 0, 1),
+                (names(&["xian"]), 1, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn order_count_histogram_buckets_by_price() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                order_count_histogram(&order, &user, &province, 10)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            let priced = |oid, price| Order {
+                price,
+                ..order(oid)
+            };
+            u.insert(User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            });
+            p.insert(Province {
+                pid: Pid(1),
+                name: "p1".to_string(),
+            });
+            // 时刻 0: 三个桶各有一个订单
+            for (oid, price) in [(1, 5), (2, 15), (3, 25)] {
+                o.insert(priced(oid, price));
+            }
+            // 时刻 1: 桶 1 多了一个订单
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.insert(priced(4, 12));
+            // 时刻 2: 桶 2 的订单被撤回, 这个桶不再输出
+            o.advance_to(2);
+            u.advance_to(2);
+            p.advance_to(2);
+            o.remove(priced(3, 25));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Pid(1), 0, 1), 0, 1),
+                ((Pid(1), 1, 1), 0, 1),
+                ((Pid(1), 1, 1), 1, -1),
+                ((Pid(1), 1, 2), 1, 1),
+                ((Pid(1), 2, 1), 0, 1),
+                ((Pid(1), 2, 1), 2, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn first_order_per_user_promotes_next_lowest_oid() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut o = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                first_order_per_user(&order).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                o
+            });
+            o.insert(order(5));
+            // 时刻 1: oid 更小的订单成为第一个订单
+            o.advance_to(1);
+            o.insert(order(2));
+            // 时刻 2: 撤回之后回到 oid 5
+            o.advance_to(2);
+            o.remove(order(2));
+            o.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Uid(1), order(2)), 1, 1),
+                ((Uid(1), order(2)), 2, -1),
+                ((Uid(1), order(5)), 0, 1),
+                ((Uid(1), order(5)), 1, -1),
+                ((Uid(1), order(5)), 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_by_price_decile_with_ties() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                orders_by_price_decile(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            for id in [1, 2] {
+                u.insert(User {
+                    uid: Uid(id),
+                    pid: Pid(id),
+                    padding: String::new(),
+                });
+                p.insert(Province {
+                    pid: Pid(id),
+                    name: format!("p{}", id),
+                });
+            }
+            // 价格 1 到 10 各有两个订单, 分别属于两个省份
+            for oid in 1..=20 {
+                o.insert(Order {
+                    oid: Oid(oid),
+                    price: (oid + 1) / 2,
+                    uid: Uid(2 - oid % 2),
+                });
+            }
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        // 价格 p 的排名为 2 * (p - 1), 所以属于第 p - 1 个十分位, 每个十分位在每个省份都有一个订单
+        let expected: Vec<_> = (0..10)
+            .flat_map(|decile| [((decile, (Pid(1), 1)), 0, 1), ((decile, (Pid(2), 1)), 0, 1)])
+            .collect();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn running_total_shifts_after_middle_insert() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut o = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                running_total_by_oid(&order).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                o
+            });
+            let priced = |oid| Order {
+                price: oid * 10,
+                ..order(oid)
+            };
+            o.insert(priced(1));
+            o.insert(priced(3));
+            // 时刻 1: 在中间插入 oid 2, oid 3 的累计值随之改变
+            o.advance_to(1);
+            o.insert(priced(2));
+            o.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Oid(1), 10), 0, 1),
+                ((Oid(2), 30), 1, 1),
+                ((Oid(3), 40), 0, 1),
+                ((Oid(3), 40), 1, -1),
+                ((Oid(3), 60), 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn price_gini_per_province_known_values() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                price_gini_per_province(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            // 省份 3 没有订单
+            for id in [1, 2, 3] {
+                u.insert(User {
+                    uid: Uid(id),
+                    pid: Pid(id),
+                    padding: String::new(),
+                });
+                p.insert(Province {
+                    pid: Pid(id),
+                    name: format!("p{}", id),
+                });
+            }
+            // 省份 1: 10, 20, 30, 40; 省份 2 只有一个订单
+            for (oid, price, uid) in [(1, 10, 1), (2, 20, 1), (3, 30, 1), (4, 40, 1), (5, 50, 2)] {
+                o.insert(Order {
+                    oid: Oid(oid),
+                    price,
+                    uid: Uid(uid),
+                });
+            }
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        let gini: Vec<_> = output
+            .iter()
+            .map(|((pid, g), t, r)| (*pid, g.0, *t, *r))
+            .collect();
+        assert_eq!(gini.len(), 2);
+        // G = 2 * 300 / (4 * 100) - 5 / 4 = 0.25
+        assert_eq!((gini[0].0, gini[0].2, gini[0].3), (Pid(1), 0, 1));
+        assert!((gini[0].1 - 0.25).abs() < 1e-9);
+        assert_eq!(gini[1], (Pid(2), 0.0, 0, 1));
+    }
+
+    #[test]
+    fn max_oid_gap_shrinks_and_grows() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut o = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                max_oid_gap_per_user(&order).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                o
+            });
+            // 时刻 0: oid 1, 5, 10, 最大间隔为 5
+            [1, 5, 10].into_iter().for_each(|oid| o.insert(order(oid)));
+            // 时刻 1: 插入 oid 3, 间隔 5 到 10 不受影响
+            o.advance_to(1);
+            o.insert(order(3));
+            // 时刻 2: 插入 oid 7, 最大间隔缩小为 4
+            o.advance_to(2);
+            o.insert(order(7));
+            // 时刻 3: 撤回 oid 7, 最大间隔恢复为 5
+            o.advance_to(3);
+            o.remove(order(7));
+            o.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Uid(1), 4), 2, 1),
+                ((Uid(1), 4), 3, -1),
+                ((Uid(1), 5), 0, 1),
+                ((Uid(1), 5), 2, -1),
+                ((Uid(1), 5), 3, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn distinct_prices_drop_when_last_order_retracts() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                distinct_prices_per_province(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            u.insert(User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            });
+            p.insert(Province {
+                pid: Pid(1),
+                name: "p1".to_string(),
+            });
+            let priced = |oid, price| Order {
+                price,
+                ..order(oid)
+            };
+            for (oid, price) in [(1, 10), (2, 10), (3, 20)] {
+                o.insert(priced(oid, price));
+            }
+            // 时刻 1: 撤回唯一的 20
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.remove(priced(3, 20));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Pid(1), vec![10]), 1, 1),
+                ((Pid(1), vec![10, 20]), 0, 1),
+                ((Pid(1), vec![10, 20]), 1, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn revenue_shares_sum_to_100_and_follow_moved_order() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                province_revenue_share(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            for id in [1, 2] {
+                u.insert(User {
+                    uid: Uid(id),
+                    pid: Pid(id),
+                    padding: String::new(),
+                });
+                p.insert(Province {
+                    pid: Pid(id),
+                    name: format!("p{}", id),
+                });
+            }
+            let order = |oid, price, uid| Order {
+                oid: Oid(oid),
+                price,
+                uid: Uid(uid),
+            };
+            // 时刻 0: 省份 1 为 30 + 10, 省份 2 为 10
+            o.insert(order(1, 30, 1));
+            o.insert(order(2, 10, 2));
+            o.insert(order(3, 10, 1));
+            // 时刻 1: 订单 3 从省份 1 移到省份 2
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.remove(order(3, 10, 1));
+            o.insert(order(3, 10, 2));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        let shares: Vec<_> = output
+            .into_iter()
+            .map(|((pid, share), t, r)| (pid, share.0, t, r))
+            .collect();
+        assert_eq!(
+            shares,
+            vec![
+                (Pid(1), 60.0, 1, 1),
+                (Pid(1), 80.0, 0, 1),
+                (Pid(1), 80.0, 1, -1),
+                (Pid(2), 20.0, 0, 1),
+                (Pid(2), 20.0, 1, -1),
+                (Pid(2), 40.0, 1, 1),
+            ]
+        );
+        // 每个时刻所有省份的百分比之和为 100
+        for t in 0..2 {
+            let total: f64 = shares
+                .iter()
+                .filter(|(_, _, time, _)| *time <= t)
+                .map(|(_, share, _, r)| share * *r as f64)
+                .sum();
+            assert_eq!(total, 100.0);
+        }
+    }
+}
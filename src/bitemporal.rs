@@ -0,0 +1,118 @@
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::Join;
+use differential_dataflow::Collection;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{Order, Province, User};
+
+/// 带有业务有效时间的记录, 有效区间为 `[valid_from, valid_to)`。
+/// dataflow 自身的时间戳表示系统时间 (记录什么时候被系统知道), 这里的区间表示有效时间 (记录在现实中什么时候成立)
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Valid<T> {
+    pub data: T,
+    pub valid_from: u64,
+    pub valid_to: u64,
+}
+
+impl<T> Valid<T> {
+    pub fn new(data: T, valid_from: u64, valid_to: u64) -> Self {
+        Valid {
+            data,
+            valid_from,
+            valid_to,
+        }
+    }
+
+    // 与另一个区间的交集, 没有交集时返回 None
+    pub fn overlap(&self, from: u64, to: u64) -> Option<(u64, u64)> {
+        let from = from.max(self.valid_from);
+        let to = to.min(self.valid_to);
+        (from < to).then_some((from, to))
+    }
+}
+
+// 双时态 join: 除了 key 相等之外, 还要求三条记录的有效区间有交集, 输出的区间为三者的交集。
+// 系统时间上依然是普通的增量计算, 任何一条记录被修正 (撤回后重新插入不同的区间) 都会更新结果
+pub fn bitemporal_join<S>(
+    order: &Collection<S, Valid<Order>>,
+    user: &Collection<S, Valid<User>>,
+    province: &Collection<S, Valid<Province>>,
+) -> Collection<S, Valid<(Order, User, Province)>>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order
+        .map(|o| (o.data.uid, o))
+        .join(&user.map(|u| (u.data.uid, u)))
+        .flat_map(|(_, (o, u))| {
+            u.overlap(o.valid_from, o.valid_to)
+                .map(|(from, to)| (u.data.pid, Valid::new((o.data, u.data), from, to)))
+        })
+        .join(&province.map(|p| (p.data.pid, p)))
+        .flat_map(|(_, (ou, p))| {
+            p.overlap(ou.valid_from, ou.valid_to)
+                .map(|(from, to)| Valid::new((ou.data.0, ou.data.1, p.data), from, to))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::{Oid, Pid, Uid};
+
+    #[test]
+    fn only_overlapping_intervals_join() {
+        let order = |oid, uid| Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(uid),
+        };
+        let user = |uid| User {
+            uid: Uid(uid),
+            pid: Pid(1),
+            padding: String::new(),
+        };
+        let province = Province {
+            pid: Pid(1),
+            name: "p1".to_string(),
+        };
+        let p1 = province.clone();
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow::<u64, _, _>(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                bitemporal_join(&orders, &users, &provinces)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            // 三个区间的交集为 [8, 10)
+            o.insert(Valid::new(order(1, 1), 0, 10));
+            u.insert(Valid::new(user(1), 5, 20));
+            p.insert(Valid::new(p1, 8, 30));
+            // 区间首尾相接, 没有交集
+            o.insert(Valid::new(order(2, 2), 0, 5));
+            u.insert(Valid::new(user(2), 5, 10));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![(Valid::new((order(1, 1), user(1), province), 8, 10), 0, 1)]
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+
+use differential_dataflow::input::InputSession;
+use differential_dataflow::operators::arrange::ArrangeByKey;
+use differential_dataflow::operators::Join;
+use differential_dataflow::{AsCollection, Collection};
+use serde::{Deserialize, Serialize};
+use timely::dataflow::operators::Probe;
+use timely::dataflow::{Scope, Stream};
+use timely::progress::frontier::AntichainRef;
+
+use crate::dataset::Update;
+use crate::delta_join::{delta_join, Oid, Order, Pid, Province, Uid, User};
+use crate::peek::state_at;
+use crate::upsert::changes_by_key;
+
+// 找出在 `t1` 到 `t2` 之间实际所属省份发生了变化的订单, 返回 (oid, t1 时的 pid, t2 时的 pid)。
+// join 的结果以 oid 为 key 做 arrange, 并且把 trace 的 compaction 保持在 `t1`, 这样 `t1` 和 `t2` 两个时刻的
+// 快照都可以从 arrangement 的历史中读出来。只在其中一个时刻存在的订单 (新增或者删除) 不算作变化
+pub fn province_changes_between(
+    order: Vec<Update<Order>>,
+    user: Vec<Update<User>>,
+    province: Vec<Update<Province>>,
+    t1: u64,
+    t2: u64,
+) -> Vec<(Oid, Pid, Pid)> {
+    assert!(t1 <= t2, "t1 must not be later than t2");
+    timely::execute_directly(move |worker| {
+        let mut order_input = InputSession::new();
+        let mut user_input = InputSession::new();
+        let mut province_input = InputSession::new();
+
+        let (probe, mut trace) = worker.dataflow(|scope| {
+            let arranged = delta_join(
+                &order_input.to_collection(scope),
+                &user_input.to_collection(scope),
+                &province_input.to_collection(scope),
+            )
+            .map(|(o, _, p)| (o.oid, p.pid))
+            .arrange_by_key();
+            (arranged.stream.probe(), arranged.trace)
+        });
+        // 保留 `t1` 之后的历史
+        trace.set_logical_compaction(AntichainRef::new(&[t1]));
+        trace.set_physical_compaction(AntichainRef::new(&[t1]));
+
+        order
+            .into_iter()
+            .for_each(|(d, t, r)| order_input.update_at(d, t, r));
+        user.into_iter()
+            .for_each(|(d, t, r)| user_input.update_at(d, t, r));
+        province
+            .into_iter()
+            .for_each(|(d, t, r)| province_input.update_at(d, t, r));
+        // `t2` 为 u64::MAX 时没有更晚的时刻可以推进, 直接关闭 input, 运行到结束
+        match t2.checked_add(1) {
+            Some(end) => {
+                order_input.advance_to(end);
+                user_input.advance_to(end);
+                province_input.advance_to(end);
+                order_input.flush();
+                user_input.flush();
+                province_input.flush();
+                worker.step_while(|| probe.less_equal(&t2));
+            }
+            None => {
+                order_input.close();
+                user_input.close();
+                province_input.close();
+                worker.step_while(|| !probe.done());
+            }
+        }
+
+        let before: BTreeMap<_, _> = state_at(&mut trace, t1)
+            .into_iter()
+            .map(|(kv, _)| kv)
+            .collect();
+        state_at(&mut trace, t2)
+            .into_iter()
+            .filter_map(|((oid, new), _)| match before.get(&oid) {
+                Some(old) if *old != new => Some((oid, *old, new)),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+/// join 结果中的一行
+pub type Row = (Order, User, Province);
+
+/// 某个 oid 在某个时刻的变化, 同时包含变化前后的结果。
+/// `before` 为 `None` 表示新增, `after` 为 `None` 表示删除
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+pub struct Change {
+    pub before: Option<Row>,
+    pub after: Option<Row>,
+    pub time: u64,
+}
+
+// 在 `delta_join` 的基础上输出 CDC 风格的变化: 每个时刻每个有变化的 oid 只产生一条 `Change`,
+// 同一时刻的撤回和插入会被配对成一条同时包含 before/after 的记录。
+// 假设每个 oid 在同一时刻最多只有一条有效的 join 结果
+pub fn cdc_join<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Stream<S, Change>
+where
+    S: Scope<Timestamp = u64>,
+{
+    changes_by_key(
+        &delta_join(order, user, province).map(|row| (row.0.oid, row)),
+        "Cdc",
+        |t, by_oid| {
+            by_oid.into_values().map(move |rows| {
+                let mut change = Change {
+                    before: None,
+                    after: None,
+                    time: t,
+                };
+                for (row, diff) in rows {
+                    if diff > 0 {
+                        change.after = Some(row);
+                    } else {
+                        change.before = Some(row);
+                    }
+                }
+                change
+            })
+        },
+    )
+}
+
+// 用户所在省份的名称发生变化时输出一条 (uid, 原来的名称, 新的名称), 变化可能是因为用户换了 pid,
+// 也可能是因为省份改了名字 (这时省份中所有用户都会输出)。
+// 输出是一个只增不减的事件 collection, 每条事件出现在变化发生的时刻。
+// 新增或者删除的用户 (只有变化前或者只有变化后的名称) 不算作变化; 假设每个用户在同一时刻最多只属于一个省份
+pub fn users_province_name_changes<S>(
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Uid, String, String)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let names = user
+        .map(|u| (u.pid, u.uid))
+        .join_map(&province.map(|p| (p.pid, p.name)), |_, uid, name| {
+            (*uid, name.clone())
+        });
+
+    changes_by_key(&names, "ProvinceNameChanges", |t, by_uid| {
+        by_uid.into_iter().filter_map(move |(uid, names)| {
+            let (mut before, mut after) = (None, None);
+            for (name, diff) in names {
+                if diff > 0 {
+                    after = Some(name);
+                } else {
+                    before = Some(name);
+                }
+            }
+            match (before, after) {
+                (Some(before), Some(after)) if before != after => {
+                    Some(((uid, before, after), t, 1))
+                }
+                _ => None,
+            }
+        })
+    })
+    .as_collection()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use timely::dataflow::operators::Inspect;
+
+    use super::*;
+
+    fn user(uid: u64, pid: u64) -> User {
+        User {
+            uid: Uid(uid),
+            pid: Pid(pid),
+            padding: String::new(),
+        }
+    }
+
+    fn province(pid: u64, name: &str) -> Province {
+        Province {
+            pid: Pid(pid),
+            name: name.to_string(),
+        }
+    }
+
+    // 所有的更新在 close 时一起发送, 同一个 batch 中会包含多个时刻的更新
+    fn cdc(
+        order: Vec<Update<Order>>,
+        user: Vec<Update<User>>,
+        province: Vec<Update<Province>>,
+    ) -> Vec<Change> {
+        timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut o = InputSession::new();
+            let mut u = InputSession::new();
+            let mut p = InputSession::new();
+            worker.dataflow(|scope| {
+                cdc_join(
+                    &o.to_collection(scope),
+                    &u.to_collection(scope),
+                    &p.to_collection(scope),
+                )
+                .inspect(move |c| sink.borrow_mut().push(c.clone()));
+            });
+            order
+                .iter()
+                .cloned()
+                .for_each(|(d, t, r)| o.update_at(d, t, r));
+            user.iter()
+                .cloned()
+                .for_each(|(d, t, r)| u.update_at(d, t, r));
+            province
+                .iter()
+                .cloned()
+                .for_each(|(d, t, r)| p.update_at(d, t, r));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            let mut output = output.borrow().clone();
+            output.sort_by_key(|c| c.time);
+            output
+        })
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn name_changes(
+        user: Vec<Update<User>>,
+        province: Vec<Update<Province>>,
+    ) -> Vec<((Uid, String, String), u64, isize)> {
+        timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut u = InputSession::new();
+            let mut p = InputSession::new();
+            worker.dataflow(|scope| {
+                users_province_name_changes(&u.to_collection(scope), &p.to_collection(scope))
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+            });
+            user.iter()
+                .cloned()
+                .for_each(|(d, t, r)| u.update_at(d, t, r));
+            province
+                .iter()
+                .cloned()
+                .for_each(|(d, t, r)| p.update_at(d, t, r));
+            u.close();
+            p.close();
+            while worker.step() {}
+            let mut output = output.borrow().clone();
+            consolidate_updates(&mut output);
+            output
+        })
+    }
+
+    #[test]
+    fn cdc_join_pairs_changes_at_the_same_time() {
+        let (mut o, u, p) = rows();
+        // 订单 1 在时刻 4 被撤回, 时刻 5 重新插入, 两者不在同一时刻, 不能配对成一条变化
+        o.push((o[0].0.clone(), 4, -1));
+        o.push((o[0].0.clone(), 5, 1));
+        let order = |oid| {
+            o.iter()
+                .find(|(x, _, _)| x.oid == Oid(oid))
+                .unwrap()
+                .0
+                .clone()
+        };
+        let row = |oid, pid| {
+            Some((
+                order(oid),
+                user(1, pid),
+                province(pid, &format!("p{}", pid)),
+            ))
+        };
+        let change = |before, after, time| Change {
+            before,
+            after,
+            time,
+        };
+        let expected = vec![
+            change(None, row(1, 1), 0),
+            // 用户换了省份: 同一时刻的撤回和插入合并成一条
+            change(row(1, 1), row(1, 2), 2),
+            // 只有插入的订单
+            change(None, row(2, 2), 3),
+            change(row(1, 2), None, 4),
+            change(None, row(1, 2), 5),
+        ];
+        assert_eq!(cdc(o.clone(), u, p), expected);
+    }
+
+    #[test]
+    fn name_changes_when_user_moves() {
+        let users = vec![
+            (user(1, 1), 0, 1),
+            (user(1, 1), 2, -1),
+            (user(1, 2), 2, 1),
+            (user(2, 1), 3, 1),
+        ];
+        let provinces = vec![(province(1, "p1"), 0, 1), (province(2, "p2"), 0, 1)];
+        assert_eq!(
+            name_changes(users, provinces),
+            vec![((Uid(1), "p1".to_string(), "p2".to_string()), 2, 1)]
+        );
+    }
+
+    #[test]
+    fn name_changes_when_province_is_renamed() {
+        let users = vec![(user(1, 1), 0, 1), (user(2, 2), 0, 1), (user(3, 1), 0, 1)];
+        let provinces = vec![
+            (province(1, "p1"), 0, 1),
+            (province(2, "p2"), 0, 1),
+            (province(1, "p1"), 3, -1),
+            (province(1, "one"), 3, 1),
+        ];
+        let event = |uid| ((Uid(uid), "p1".to_string(), "one".to_string()), 3, 1);
+        assert_eq!(name_changes(users, provinces), vec![event(1), event(3)]);
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn rows() -> (Vec<Update<Order>>, Vec<Update<User>>, Vec<Update<Province>>) {
+        let user = |pid| User {
+            uid: Uid(1),
+            pid: Pid(pid),
+            padding: String::new(),
+        };
+        let orders = vec![
+            (
+                Order {
+                    oid: Oid(1),
+                    price: 1,
+                    uid: Uid(1),
+                },
+                0,
+                1,
+            ),
+            // 只在 t2 存在的订单不算作变化
+            (
+                Order {
+                    oid: Oid(2),
+                    price: 1,
+                    uid: Uid(1),
+                },
+                3,
+                1,
+            ),
+        ];
+        let users = vec![(user(1), 0, 1), (user(1), 2, -1), (user(2), 2, 1)];
+        let provinces = (1..=2)
+            .map(|pid| {
+                (
+                    Province {
+                        pid: Pid(pid),
+                        name: format!("p{}", pid),
+                    },
+                    0,
+                    1,
+                )
+            })
+            .collect();
+        (orders, users, provinces)
+    }
+
+    #[test]
+    fn province_changes_between_times() {
+        let (o, u, p) = rows();
+        assert_eq!(
+            province_changes_between(o, u, p, 1, 3),
+            vec![(Oid(1), Pid(1), Pid(2))]
+        );
+        let (o, u, p) = rows();
+        assert_eq!(province_changes_between(o, u, p, 0, 1), vec![]);
+    }
+
+    #[test]
+    fn province_changes_until_max_time() {
+        let (o, u, p) = rows();
+        assert_eq!(
+            province_changes_between(o, u, p, 0, u64::MAX),
+            vec![(Oid(1), Pid(1), Pid(2))]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use differential_dataflow::consolidation::consolidate;
+use differential_dataflow::{AsCollection, Collection, Data};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::Scope;
+
+use crate::dataset::{split_ops, Update};
+use crate::delta_join::{Order, Province, User, Variant};
+use crate::gen::{generate, with_timestamps, Rng};
+use crate::harness;
+
+// 限制每次调度最多只向下游提交 `max_times_per_tick` 个不同的时刻, 剩下的更新 (连同它们的 capability) 暂存起来,
+// 下一次调度时再继续提交。 这样下游 join 的 frontier 会明显落后于 input, 可以用来演示 backpressure,
+// 但是最终的结果与不限流时完全一致
+pub fn throttle<S, D>(input: &Collection<S, D>, max_times_per_tick: usize) -> Collection<S, D>
+where
+    S: Scope<Timestamp = u64>,
+    D: Data,
+{
+    assert!(
+        max_times_per_tick > 0,
+        "max_times_per_tick must be positive"
+    );
+    let scope = input.scope();
+    input
+        .inner
+        .unary_frontier(Pipeline, "Throttle", move |_, info| {
+            let activator = scope.activator_for(&info.address[..]);
+            let mut stash = BTreeMap::new();
+            move |input, output| {
+                input.for_each(|cap, data| {
+                    stash
+                        .entry(*cap.time())
+                        .or_insert_with(|| (cap.retain(), Vec::new()))
+                        .1
+                        .extend(data.iter().cloned());
+                });
+                for _ in 0..max_times_per_tick {
+                    match stash.pop_first() {
+                        Some((_, (cap, data))) => {
+                            output.session(&cap).give_iterator(data.into_iter());
+                        }
+                        None => break,
+                    }
+                }
+                if !stash.is_empty() {
+                    activator.activate();
+                }
+            }
+        })
+        .as_collection()
+}
+
+// 用 `seed` 确定性地打乱更新的输入顺序, 但是 `key` 相同的更新之间依然保持时间顺序。
+// 用来以不同的顺序向 join 输入数据, 验证结果与输入顺序无关
+pub fn shuffle_updates<D, K, F>(seed: u64, updates: Vec<Update<D>>, key: F) -> Vec<Update<D>>
+where
+    K: Eq + Hash,
+    F: Fn(&D) -> K,
+{
+    let mut rng = Rng::new(seed);
+    let mut updates = updates;
+    for i in (1..updates.len()).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        updates.swap(i, j);
+    }
+
+    // 对于每个 key, 把它的更新按时间排序后依次放回它在打乱之后占据的位置
+    let mut positions: HashMap<K, Vec<usize>> = HashMap::new();
+    for (i, (d, _, _)) in updates.iter().enumerate() {
+        positions.entry(key(d)).or_default().push(i);
+    }
+    let mut slots: Vec<Option<Update<D>>> = updates.into_iter().map(Some).collect();
+    for indices in positions.into_values() {
+        let mut group: Vec<_> = indices.iter().map(|i| slots[*i].take().unwrap()).collect();
+        group.sort_by_key(|(_, t, _)| *t);
+        for (i, update) in indices.into_iter().zip(group) {
+            slots[i] = Some(update);
+        }
+    }
+    slots.into_iter().map(Option::unwrap).collect()
+}
+
+// 用 `dataset_seed` 生成一个数据集, 再分别用 `feed_seed_a` 和 `feed_seed_b` 随机分配插入时间并打乱输入顺序,
+// 以两种不同的方式输入到 delta join 中, 断言两次运行最终的 join 结果 (忽略时间累积之后) 完全相同。
+// 返回最终的结果, 已经 consolidate 并且排序
+pub fn compare_seeds(
+    dataset_seed: u64,
+    feed_seed_a: u64,
+    feed_seed_b: u64,
+) -> Vec<((Order, User, Province), isize)> {
+    let dataset = generate(dataset_seed, 100, 0);
+    let run = |feed_seed: u64| {
+        let (order, user, province) = split_ops(with_timestamps(&dataset, feed_seed, 10));
+        let order = shuffle_updates(feed_seed, order, |o| o.oid);
+        let user = shuffle_updates(feed_seed, user, |u| u.uid);
+        let province = shuffle_updates(feed_seed, province, |p| p.pid);
+        let mut rows: Vec<_> = harness::run(Variant::Delta, order, user, province)
+            .into_iter()
+            .map(|(d, _, r)| (d, r))
+            .collect();
+        consolidate(&mut rows);
+        rows
+    };
+    let a = run(feed_seed_a);
+    let b = run(feed_seed_b);
+    assert_eq!(
+        a, b,
+        "join results differ between feed seeds {} and {} (dataset seed {})",
+        feed_seed_a, feed_seed_b, dataset_seed
+    );
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::delta_join;
+
+    #[test]
+    fn throttled_join_lags_input_but_agrees() {
+        let dataset = generate(0, 50, 0);
+        // 订单分布在 0..10 这 10 个时刻
+        let orders: Vec<_> = dataset
+            .orders
+            .iter()
+            .enumerate()
+            .map(|(i, o)| (o.clone(), (i % 10) as u64, 1))
+            .collect();
+        let users: Vec<_> = dataset.users.iter().map(|u| (u.clone(), 0, 1)).collect();
+        let provinces: Vec<_> = dataset
+            .provinces
+            .iter()
+            .map(|p| (p.clone(), 0, 1))
+            .collect();
+        let expected = harness::run(
+            Variant::Delta,
+            orders.clone(),
+            users.clone(),
+            provinces.clone(),
+        );
+
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p, input_probe, output_probe) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                let input_probe = order.probe();
+                let output_probe = delta_join(&throttle(&order, 1), &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()))
+                    .probe();
+                (o, u, p, input_probe, output_probe)
+            });
+            for (user, _, _) in users {
+                u.insert(user);
+            }
+            for (province, _, _) in provinces {
+                p.insert(province);
+            }
+            let mut orders = orders;
+            orders.sort_by_key(|(_, t, _)| *t);
+            for (order, t, _) in orders {
+                o.advance_to(t);
+                o.insert(order);
+            }
+            o.advance_to(10);
+            u.advance_to(10);
+            p.advance_to(10);
+            o.flush();
+            u.flush();
+            p.flush();
+
+            // input 的 frontier 一步就推进到了 10, 而限流之后每次调度只提交一个时刻,
+            // 此时 join 的 frontier 必然还没有追上
+            while input_probe.less_than(&10) {
+                worker.step();
+            }
+            assert!(output_probe.less_than(&10));
+            while output_probe.less_than(&10) {
+                worker.step();
+            }
+
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn delta_join_is_invariant_under_shuffles() {
+        let (mut order, user, province) = split_ops(with_timestamps(&generate(3, 50, 0), 3, 5));
+        // 一部分订单之后被撤回, 同一个订单的更新必须保持时间顺序
+        let retracted: Vec<_> = order
+            .iter()
+            .step_by(4)
+            .map(|(o, t, _)| (o.clone(), t + 2, -1))
+            .collect();
+        order.extend(retracted);
+        let expected = harness::run(
+            Variant::Delta,
+            order.clone(),
+            user.clone(),
+            province.clone(),
+        );
+        for seed in 0..4 {
+            let shuffled = shuffle_updates(seed, order.clone(), |o| o.oid);
+            assert_ne!(shuffled, order, "seed {}", seed);
+            // 打乱之后同一个订单的更新依然按时间排列
+            let mut last = HashMap::new();
+            for (o, t, _) in shuffled.iter() {
+                assert!(*last.get(&o.oid).unwrap_or(&0) <= *t, "seed {}", seed);
+                last.insert(o.oid, *t);
+            }
+
+            let user = shuffle_updates(seed, user.clone(), |u| u.uid);
+            let province = shuffle_updates(seed, province.clone(), |p| p.pid);
+            assert_eq!(
+                harness::run(Variant::Delta, shuffled, user, province),
+                expected,
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn compare_seeds_agrees_for_several_pairs() {
+        let dataset = generate(8, 100, 0);
+        let (o, u, p) = split_ops(dataset.to_ops(0));
+        let mut expected: Vec<_> = harness::run(Variant::Delta, o, u, p)
+            .into_iter()
+            .map(|(d, _, r)| (d, r))
+            .collect();
+        consolidate(&mut expected);
+        for (a, b) in [(0, 1), (2, 3), (4, 99), (7, 7)] {
+            // 所有外键都有效, 最终的结果就是整个数据集的 join
+            assert_eq!(
+                compare_seeds(8, a, b),
+                expected,
+                "feed seeds {} and {}",
+                a,
+                b
+            );
+        }
+    }
+}
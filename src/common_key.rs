@@ -0,0 +1,215 @@
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::ArrangeByKey;
+use differential_dataflow::operators::JoinCore;
+use differential_dataflow::{AsCollection, Collection};
+use dogsdogsdogs::operators::half_join;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::operators::Map;
+use timely::dataflow::Scope;
+
+use crate::delta_join::{step_back, Oid, Order};
+
+/// 订单的物流信息, 每个订单最多一条
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Shipment {
+    pub oid: Oid,
+    pub carrier: String,
+}
+
+/// 订单的评价, 每个订单最多一条
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Review {
+    pub oid: Oid,
+    pub rating: u8,
+}
+
+// 与星型的 order-user-province 不同, 这里三个关系都以 oid 为 key, inner join 之后得到 (order, shipment, review)。
+// 用二元 join 实现时, 第一个 join 的输出虽然还是以 oid 为 key, 但它是一个新的 collection,
+// 仍然需要额外 arrange 一次才能和 review join
+pub fn join_common_key<S>(
+    order: &Collection<S, Order>,
+    shipment: &Collection<S, Shipment>,
+    review: &Collection<S, Review>,
+) -> Collection<S, (Order, Shipment, Review)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    let order = order.map(|o| (o.oid, o)).arrange_by_key();
+    let shipment = shipment.map(|s| (s.oid, s)).arrange_by_key();
+    let review = review.map(|r| (r.oid, r)).arrange_by_key();
+
+    order
+        .join_core(&shipment, |oid, o, s| Some((*oid, (o.clone(), s.clone()))))
+        .arrange_by_key()
+        .join_core(&review, |_, (o, s), r| {
+            Some((o.clone(), s.clone(), r.clone()))
+        })
+}
+
+// `join_common_key` 的 delta join 版本。所有的 key 都是 oid, 所以每个关系只需要一个 arrangement,
+// 每个关系的更新都直接用同一个 oid 依次查找另外两个 arrangement, 不需要中间结果的 arrangement。
+// 此时 delta join 退化成对同一个 key 的多次查找, 和二元 join 相比只节省了中间结果的那一个 arrangement
+pub fn delta_join_common_key<S>(
+    order: &Collection<S, Order>,
+    shipment: &Collection<S, Shipment>,
+    review: &Collection<S, Review>,
+) -> Collection<S, (Order, Shipment, Review)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let order_arrange = order.map(|o| (o.oid, o)).arrange_by_key();
+    let shipment_arrange = shipment.map(|s| (s.oid, s)).arrange_by_key();
+    let review_arrange = review.map(|r| (r.oid, r)).arrange_by_key();
+
+    let order_change = order
+        .inner
+        .map(|(o, t, r)| ((o.oid, o, t.clone()), t, r))
+        .as_collection();
+    let shipment_change = shipment
+        .inner
+        .map(|(s, t, r)| ((s.oid, s, t.clone()), t, r))
+        .as_collection();
+    let review_change = review
+        .inner
+        .map(|(v, t, r)| ((v.oid, v, t.clone()), t, r))
+        .as_collection();
+
+    let frontier_func = step_back;
+
+    // 优先级为 order < shipment < review
+
+    // 订单更新产生的数据
+    let order_update = half_join(
+        &order_change,
+        shipment_arrange.clone(),
+        frontier_func,
+        |t1, t2| t1 < t2, // P(order) < P(shipment) 不能看到同一时刻的更新
+        |oid, o, s| (*oid, (o.clone(), s.clone())),
+    )
+    .map(|((k, v), t)| (k, v, t));
+    let order_update = half_join(
+        &order_update,
+        review_arrange.clone(),
+        frontier_func,
+        |t1, t2| t1 < t2, // P(order) < P(review) 不能看到同一时刻的更新
+        |_, (o, s), r| (o.clone(), s.clone(), r.clone()),
+    );
+
+    // 物流更新产生的数据
+    let shipment_update = half_join(
+        &shipment_change,
+        order_arrange.clone(),
+        frontier_func,
+        |t1, t2| t1 <= t2, // P(shipment) > P(order) 可以看到同一时刻的更新
+        |oid, s, o| (*oid, (o.clone(), s.clone())),
+    )
+    .map(|((k, v), t)| (k, v, t));
+    let shipment_update = half_join(
+        &shipment_update,
+        review_arrange,
+        frontier_func,
+        |t1, t2| t1 < t2, // P(shipment) < P(review) 不能看到同一时刻的更新
+        |_, (o, s), r| (o.clone(), s.clone(), r.clone()),
+    );
+
+    // 评价更新产生的数据
+    let review_update = half_join(
+        &review_change,
+        order_arrange,
+        frontier_func,
+        |t1, t2| t1 <= t2, // P(review) > P(order) 可以看到同一时刻的更新
+        |oid, r, o| (*oid, (o.clone(), r.clone())),
+    )
+    .map(|((k, v), t)| (k, v, t));
+    let review_update = half_join(
+        &review_update,
+        shipment_arrange,
+        frontier_func,
+        |t1, t2| t1 <= t2, // P(review) > P(shipment) 可以看到同一时刻的更新
+        |_, (o, r), s| (o.clone(), s.clone(), r.clone()),
+    );
+
+    // 汇聚所有更新的数据
+    order_update
+        .concat(&shipment_update)
+        .concat(&review_update)
+        .inner
+        .map(|((d, t), _, r)| (d, t, r))
+        .as_collection()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::Uid;
+
+    fn order(oid: u64) -> Order {
+        Order {
+            oid: Oid(oid),
+            price: oid * 10,
+            uid: Uid(1),
+        }
+    }
+
+    fn shipment(oid: u64) -> Shipment {
+        Shipment {
+            oid: Oid(oid),
+            carrier: format!("c{}", oid),
+        }
+    }
+
+    fn review(oid: u64) -> Review {
+        Review {
+            oid: Oid(oid),
+            rating: 5,
+        }
+    }
+
+    // 订单 1 的物流和评价都存在, 订单 2 只有物流没有评价
+    fn run(delta: bool) -> Vec<((Order, Shipment, Review), u64, isize)> {
+        timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let out = output.clone();
+            let (mut order_input, mut shipment_input, mut review_input) =
+                worker.dataflow(|scope| {
+                    let (order_input, order) = scope.new_collection::<Order, isize>();
+                    let (shipment_input, shipment) = scope.new_collection::<Shipment, isize>();
+                    let (review_input, review) = scope.new_collection::<Review, isize>();
+                    let joined = if delta {
+                        delta_join_common_key(&order, &shipment, &review)
+                    } else {
+                        join_common_key(&order, &shipment, &review)
+                    };
+                    joined.inspect(move |x| out.borrow_mut().push(x.clone()));
+                    (order_input, shipment_input, review_input)
+                });
+            for oid in [1, 2] {
+                order_input.insert(order(oid));
+                shipment_input.insert(shipment(oid));
+            }
+            review_input.insert(review(1));
+            order_input.close();
+            shipment_input.close();
+            review_input.close();
+            while worker.step() {}
+
+            let mut output = output.borrow().clone();
+            consolidate_updates(&mut output);
+            output
+        })
+    }
+
+    #[test]
+    fn only_oids_present_in_all_three_are_joined() {
+        let expected = vec![((order(1), shipment(1), review(1)), 0, 1)];
+        assert_eq!(run(false), expected);
+        assert_eq!(run(true), expected);
+    }
+}
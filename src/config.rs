@@ -0,0 +1,227 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::builder::{DeltaJoinBuilder, GraphError, Relation};
+use crate::dataset::{split_ops, Update};
+use crate::delta_join::{Order, Province, User, Variant};
+use crate::harness;
+use crate::load::{self, LoadError};
+use crate::sink;
+
+/// input 的来源
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InputConfig {
+    /// 三个 CSV 文件, 所有数据都在时刻 0 输入
+    Csv {
+        orders: PathBuf,
+        users: PathBuf,
+        provinces: PathBuf,
+    },
+    /// 更新流文件, 格式参考 `load::parse_diffs`
+    Diffs(PathBuf),
+}
+
+/// 输出的格式
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    Ndjson,
+    Changelog,
+}
+
+/// 输出的配置
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct OutputConfig {
+    pub path: PathBuf,
+    pub format: OutputFormat,
+    /// 为 true 时把时间戳当作秒数, 格式化为 RFC3339
+    #[serde(default)]
+    pub rfc3339: bool,
+}
+
+/// 整个 join pipeline 的配置, 相对路径都是相对于配置文件所在的目录。参考 `fixtures/config.json`
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    pub input: InputConfig,
+    pub variant: Variant,
+    /// 关系的优先级, 从低到高。所有的实现都使用 order < user < province, 这里只用来检查配置是否与之一致
+    #[serde(default)]
+    pub priority: Option<Vec<Relation>>,
+    pub output: OutputConfig,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Load(LoadError),
+    Graph(GraphError),
+    /// 配置中的优先级与 join graph 推导出来的不一致
+    Priority {
+        expected: Vec<Relation>,
+        found: Vec<Relation>,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "io error: {}", e),
+            ConfigError::Json(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Load(e) => write!(f, "failed to load input: {}", e),
+            ConfigError::Graph(e) => write!(f, "invalid join graph: {:?}", e),
+            ConfigError::Priority { expected, found } => write!(
+                f,
+                "priority {:?} does not match the join graph, expected {:?}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        ConfigError::Json(e)
+    }
+}
+
+impl From<LoadError> for ConfigError {
+    fn from(e: LoadError) -> Self {
+        ConfigError::Load(e)
+    }
+}
+
+impl From<GraphError> for ConfigError {
+    fn from(e: GraphError) -> Self {
+        ConfigError::Graph(e)
+    }
+}
+
+// 读取 JSON 配置, 加载 input, 运行指定的 join 实现, 并把结果写到配置的输出中。返回 join 输出的所有更新
+pub fn run_from_config(
+    path: impl AsRef<Path>,
+) -> Result<Vec<Update<(Order, User, Province)>>, ConfigError> {
+    let path = path.as_ref();
+    let config: Config = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    run(&config, base)
+}
+
+// 按照 `config` 运行 pipeline, 相对路径相对于 `base`
+pub fn run(
+    config: &Config,
+    base: &Path,
+) -> Result<Vec<Update<(Order, User, Province)>>, ConfigError> {
+    if let Some(found) = &config.priority {
+        let expected = DeltaJoinBuilder::new()
+            .edge(Relation::Order, "uid", Relation::User)
+            .edge(Relation::User, "pid", Relation::Province)
+            .priority()?;
+        if *found != expected {
+            return Err(ConfigError::Priority {
+                expected,
+                found: found.clone(),
+            });
+        }
+    }
+
+    let (orders, users, provinces) = match &config.input {
+        InputConfig::Csv {
+            orders,
+            users,
+            provinces,
+        } => {
+            let read = |p: &PathBuf| fs::read_to_string(base.join(p)).map_err(LoadError::Io);
+            (
+                load::parse_orders(&read(orders)?)
+                    .map_err(LoadError::Parse)?
+                    .into_iter()
+                    .map(|d| (d, 0, 1))
+                    .collect(),
+                load::parse_users(&read(users)?)
+                    .map_err(LoadError::Parse)?
+                    .into_iter()
+                    .map(|d| (d, 0, 1))
+                    .collect(),
+                load::parse_provinces(&read(provinces)?)
+                    .map_err(LoadError::Parse)?
+                    .into_iter()
+                    .map(|d| (d, 0, 1))
+                    .collect(),
+            )
+        }
+        InputConfig::Diffs(diffs) => split_ops(load::read_diffs(base.join(diffs))?),
+    };
+
+    let output = harness::run(config.variant, orders, users, provinces);
+
+    let mut writer = BufWriter::new(File::create(base.join(&config.output.path))?);
+    let formatter: Option<&dyn Fn(u64) -> String> = if config.output.rfc3339 {
+        Some(&sink::rfc3339)
+    } else {
+        None
+    };
+    match config.output.format {
+        OutputFormat::Ndjson => sink::write_ndjson(&mut writer, &output, formatter)?,
+        OutputFormat::Changelog => sink::write_changelog(&mut writer, &output, formatter)?,
+    }
+    writer.flush()?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn runs_fixture_config_end_to_end() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let output = run_from_config(dir.join("config.json")).unwrap();
+        assert_eq!(output.len(), 4);
+
+        // 输出的文件与返回的更新一一对应
+        let written = fs::read_to_string(dir.join("output.ndjson")).unwrap();
+        let lines: Vec<Value> = written
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let rows: Vec<_> = lines
+            .iter()
+            .map(|v| {
+                assert_eq!(v["time"], 0);
+                assert_eq!(v["diff"], 1);
+                (
+                    v["order"]["oid"].as_u64().unwrap(),
+                    v["user"]["uid"].as_u64().unwrap(),
+                    v["province"]["name"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        let expected: Vec<_> = [
+            (1, 1, "beijing"),
+            (2, 1, "beijing"),
+            (3, 2, "shanghai"),
+            (4, 3, "beijing"),
+        ]
+        .into_iter()
+        .map(|(oid, uid, name)| (oid, uid, name.to_string()))
+        .collect();
+        assert_eq!(rows, expected);
+    }
+}
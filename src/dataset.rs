@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::delta_join::{Order, Province, User};
+
+/// 一份完整的数据集, 包含 join 的三个 input
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Dataset {
+    pub orders: Vec<Order>,
+    pub users: Vec<User>,
+    pub provinces: Vec<Province>,
+}
+
+/// 带时间戳的更新 `(data, time, diff)`
+pub type Update<D> = (D, u64, isize);
+
+/// 对某个 input 的一次更新
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum InputOp {
+    Order(Order, u64, isize),
+    User(User, u64, isize),
+    Province(Province, u64, isize),
+}
+
+impl InputOp {
+    pub fn time(&self) -> u64 {
+        match self {
+            InputOp::Order(_, t, _) | InputOp::User(_, t, _) | InputOp::Province(_, t, _) => *t,
+        }
+    }
+}
+
+impl Dataset {
+    // 在时刻 `time` 插入整个数据集的所有更新
+    pub fn to_ops(&self, time: u64) -> Vec<InputOp> {
+        let orders = self
+            .orders
+            .iter()
+            .map(|o| InputOp::Order(o.clone(), time, 1));
+        let users = self.users.iter().map(|u| InputOp::User(u.clone(), time, 1));
+        let provinces = self
+            .provinces
+            .iter()
+            .map(|p| InputOp::Province(p.clone(), time, 1));
+        orders.chain(users).chain(provinces).collect()
+    }
+}
+
+// 按照 input 拆分更新
+pub fn split_ops(
+    ops: impl IntoIterator<Item = InputOp>,
+) -> (Vec<Update<Order>>, Vec<Update<User>>, Vec<Update<Province>>) {
+    let mut orders = Vec::new();
+    let mut users = Vec::new();
+    let mut provinces = Vec::new();
+    for op in ops {
+        match op {
+            InputOp::Order(d, t, r) => orders.push((d, t, r)),
+            InputOp::User(d, t, r) => users.push((d, t, r)),
+            InputOp::Province(d, t, r) => provinces.push((d, t, r)),
+        }
+    }
+    (orders, users, provinces)
+}
+
+// 计算把 `old` 变成 `new` 所需的最少更新, 所有更新都发生在时刻 `time`。
+// 数据集被当作多重集处理, 两边都存在的记录不会产生任何更新
+pub fn diff_datasets(old: &Dataset, new: &Dataset, time: u64) -> Vec<InputOp> {
+    fn diff<T: Ord + Clone>(old: &[T], new: &[T]) -> Vec<(T, isize)> {
+        let mut counts = BTreeMap::new();
+        for d in old {
+            *counts.entry(d.clone()).or_insert(0) -= 1;
+        }
+        for d in new {
+            *counts.entry(d.clone()).or_insert(0) += 1;
+        }
+        counts.into_iter().filter(|(_, r)| *r != 0).collect()
+    }
+
+    let orders = diff(&old.orders, &new.orders)
+        .into_iter()
+        .map(|(d, r)| InputOp::Order(d, time, r));
+    let users = diff(&old.users, &new.users)
+        .into_iter()
+        .map(|(d, r)| InputOp::User(d, time, r));
+    let provinces = diff(&old.provinces, &new.provinces)
+        .into_iter()
+        .map(|(d, r)| InputOp::Province(d, time, r));
+    orders.chain(users).chain(provinces).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta_join::{Oid, Pid, Variant};
+    use crate::gen::{generate, PROVINCES};
+    use crate::harness;
+
+    // 按照 input 拆分之后运行 delta join, 忽略时间把结果累积起来
+    fn final_rows(ops: Vec<InputOp>) -> Vec<((Order, User, Province), isize)> {
+        let (o, u, p) = split_ops(ops);
+        let mut rows: Vec<_> = harness::run(Variant::Delta, o, u, p)
+            .into_iter()
+            .map(|(d, _, r)| (d, r))
+            .collect();
+        differential_dataflow::consolidation::consolidate(&mut rows);
+        rows
+    }
+
+    #[test]
+    fn applying_the_diff_matches_loading_new() {
+        let old = generate(1, 50, 0);
+        let mut new = old.clone();
+        // 删除 10 个订单, 一个用户更换省份, 增加一个订单
+        new.orders.drain(..10);
+        new.users[0].pid = Pid((new.users[0].pid.0 + 1) % PROVINCES);
+        new.orders.push(Order {
+            oid: Oid(u64::MAX),
+            price: 1,
+            uid: new.users[1].uid,
+        });
+
+        assert!(diff_datasets(&old, &old, 1).is_empty());
+        let diff = diff_datasets(&old, &new, 1);
+        assert_eq!(diff.len(), 10 + 2 + 1);
+        assert!(diff.iter().all(|op| op.time() == 1));
+
+        let mut ops = old.to_ops(0);
+        ops.extend(diff);
+        let rows = final_rows(ops);
+        assert!(!rows.is_empty());
+        assert_eq!(rows, final_rows(new.to_ops(0)));
+    }
+}
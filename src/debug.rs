@@ -0,0 +1,182 @@
+use std::fmt::Debug;
+
+use differential_dataflow::trace::cursor::Cursor;
+use differential_dataflow::trace::TraceReader;
+use differential_dataflow::{Collection, Data, ExchangeData};
+use timely::dataflow::Scope;
+
+use crate::delta_join::Trace;
+
+/// trace 中的一条记录
+pub type Entry<K, V> = (K, V, u64, isize);
+
+// trace 中当前所有已经封存 (时间早于 trace 的 upper frontier) 的 (key, value, time, diff), 按照 key, value 排序,
+// 不做累积。被 compaction 合并过的时间会显示为合并之后的时间
+pub fn trace_entries<K, V>(trace: &mut Trace<K, V>) -> Vec<Entry<K, V>>
+where
+    K: ExchangeData,
+    V: ExchangeData,
+{
+    let mut entries = Vec::new();
+    let (mut cursor, storage) = trace.cursor();
+    while cursor.key_valid(&storage) {
+        while cursor.val_valid(&storage) {
+            let key = cursor.key(&storage);
+            let val = cursor.val(&storage);
+            cursor.map_times(&storage, |t, r| {
+                entries.push((key.clone(), val.clone(), *t, *r));
+            });
+            cursor.step_val(&storage);
+        }
+        cursor.step_key(&storage);
+    }
+    entries
+}
+
+// 把 entries 格式化成一个表格, 每行一条记录
+pub fn format_entries<K: Debug, V: Debug>(name: &str, entries: &[Entry<K, V>]) -> String {
+    let rows: Vec<_> = entries
+        .iter()
+        .map(|(k, v, t, r)| (format!("{:?}", k), format!("{:?}", v), t, r))
+        .collect();
+    let key_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max(3);
+    let val_width = rows.iter().map(|r| r.1.len()).max().unwrap_or(0).max(5);
+    let mut table = format!("{} ({} entries)\n", name, rows.len());
+    table += &format!(
+        "{:<kw$} | {:<vw$} | {:>6} | {:>5}\n",
+        "key",
+        "value",
+        "time",
+        "diff",
+        kw = key_width,
+        vw = val_width
+    );
+    for (k, v, t, r) in rows {
+        table += &format!(
+            "{:<kw$} | {:<vw$} | {:>6} | {:>5}\n",
+            k,
+            v,
+            t,
+            r,
+            kw = key_width,
+            vw = val_width
+        );
+    }
+    table
+}
+
+// 打印 trace 中的所有记录, 用于 join 结果不符合预期时检查 arrangement 的内容, 例如
+// `dump_trace("user_by_pid", &mut traces.user_by_pid)`。返回打印的记录
+pub fn dump_trace<K, V>(name: &str, trace: &mut Trace<K, V>) -> Vec<Entry<K, V>>
+where
+    K: ExchangeData,
+    V: ExchangeData,
+{
+    let entries = trace_entries(trace);
+    print!("{}", format_entries(name, &entries));
+    entries
+}
+
+// 给每一行加上产生它的 worker 的编号, 用来检查数据的分布, 例如验证 `partition::delta_join_with_hash` 的分区方式。
+// 编号是在这个算子所在的 worker 上添加的, 所以需要直接作用于要检查的算子的输出, 中间不能有重新分区的算子
+pub fn with_worker_index<S, D>(collection: &Collection<S, D>) -> Collection<S, (D, usize)>
+where
+    S: Scope,
+    D: Data,
+{
+    let index = collection.scope().index();
+    collection.map(move |d| (d, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::{Pid, Uid, User};
+    use crate::relation::UserByPid;
+
+    #[test]
+    fn dump_user_by_pid_matches_fed_data() {
+        let user = |uid, pid| User {
+            uid: Uid(uid),
+            pid: Pid(pid),
+            padding: String::new(),
+        };
+        let mut entries = timely::execute_directly(move |worker| {
+            let (mut u, mut trace) = worker.dataflow(|scope| {
+                let (u, users) = scope.new_collection();
+                (u, UserByPid::arrange(&users).trace)
+            });
+            u.insert(user(1, 2));
+            u.insert(user(2, 1));
+            u.advance_to(1);
+            u.remove(user(1, 2));
+            u.close();
+            while worker.step() {}
+            dump_trace("user_by_pid", &mut trace)
+        });
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                (Pid(1), user(2, 1), 0, 1),
+                (Pid(2), user(1, 2), 0, 1),
+                (Pid(2), user(1, 2), 1, -1),
+            ]
+        );
+        let table = format_entries("user_by_pid", &entries);
+        assert!(table.starts_with("user_by_pid (3 entries)\n"));
+        assert_eq!(table.lines().count(), 5);
+    }
+
+    #[test]
+    fn rows_of_a_key_carry_the_same_worker_index() {
+        use std::collections::{BTreeMap, BTreeSet};
+        use std::sync::{Arc, Mutex};
+
+        use differential_dataflow::input::InputSession;
+        use differential_dataflow::operators::arrange::ArrangeByKey;
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let shared = output.clone();
+        timely::execute(timely::Config::process(4), move |worker| {
+            let index = worker.index();
+            let output = shared.clone();
+            let mut input = InputSession::new();
+            worker.dataflow::<u64, _, _>(|scope| {
+                let arranged = input
+                    .to_collection(scope)
+                    .map(|u: User| (u.uid, u))
+                    .arrange_by_key()
+                    .as_collection(|_, u| u.clone());
+                // 直接作用于 arrangement 的输出, 中间没有重新分区
+                with_worker_index(&arranged).inspect(move |((u, w), _, _)| {
+                    output.lock().unwrap().push((u.uid, *w));
+                });
+            });
+            // 所有的数据都从 worker 0 输入, 每个 uid 有三行
+            if index == 0 {
+                for uid in 0..16 {
+                    for pid in 0..3 {
+                        input.insert(User {
+                            uid: Uid(uid),
+                            pid: Pid(pid),
+                            padding: String::new(),
+                        });
+                    }
+                }
+            }
+        })
+        .unwrap();
+
+        let mut workers: BTreeMap<Uid, BTreeSet<usize>> = BTreeMap::new();
+        for (uid, w) in output.lock().unwrap().iter() {
+            workers.entry(*uid).or_default().insert(*w);
+        }
+        assert_eq!(workers.len(), 16);
+        assert!(workers.values().all(|w| w.len() == 1));
+        let used: BTreeSet<_> = workers.values().flatten().collect();
+        assert!(used.len() > 1);
+    }
+}
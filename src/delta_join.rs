@@ -1,45 +1,310 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use differential_dataflow::hashable::Hashable;
 use differential_dataflow::lattice::Lattice;
-use differential_dataflow::operators::arrange::ArrangeByKey;
-use differential_dataflow::operators::Join;
-use differential_dataflow::{AsCollection, Collection};
+use differential_dataflow::operators::arrange::{ArrangeByKey, Arranged, TraceAgent};
+use differential_dataflow::operators::{Join, Threshold};
+use differential_dataflow::trace::implementations::ValSpine;
+use differential_dataflow::trace::TraceReader;
+use differential_dataflow::{AsCollection, Collection, ExchangeData};
 use dogsdogsdogs::operators::half_join;
 use serde::{Deserialize, Serialize};
-use timely::dataflow::operators::Map;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Map, Operator};
 use timely::dataflow::Scope;
+use timely::order::Product;
+use timely::progress::frontier::AntichainRef;
 use timely::progress::Antichain;
 
+use crate::relation::{OrderByUid, ProvinceByPid, UserByPid, UserByUid};
+
 /// 用户 ID
 #[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Copy)]
-pub struct Uid(u64);
+pub struct Uid(pub u64);
 /// 订单 ID
 #[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Copy)]
-pub struct Oid(u64);
+pub struct Oid(pub u64);
 /// 省份 ID
 #[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Copy)]
-pub struct Pid(u64);
+pub struct Pid(pub u64);
 
 /// 订单
-#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct Order {
     pub oid: Oid,
     pub price: u64,
     pub uid: Uid,
 }
 
+/// 价格可以为负数的订单, 例如退款
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SignedOrder {
+    pub oid: Oid,
+    pub price: i64,
+    pub uid: Uid,
+}
+
+impl From<Order> for SignedOrder {
+    fn from(o: Order) -> Self {
+        SignedOrder {
+            oid: o.oid,
+            price: o.price as i64,
+            uid: o.uid,
+        }
+    }
+}
+
 /// 用户
-#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct User {
     pub uid: Uid,
     pub pid: Pid,
+    /// 用来模拟 User 中其他占用空间较大的 column, 参考 `demo::memory_comparison`
+    pub padding: String,
 }
 
 /// 省份
-#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct Province {
     pub pid: Pid,
     pub name: String,
 }
 
+impl Pid {
+    /// 占位省份使用的 pid, 保留给 `Province::unknown`, 真实的省份 (包括 `gen::generate` 生成的) 不会使用它
+    pub const UNKNOWN: Pid = Pid(u64::MAX);
+}
+
+impl Province {
+    // 省份还没有加载时使用的占位省份, 参考 `delta_join_with_unknown_province`。
+    // pid 为保留的 `Pid::UNKNOWN`, 不会与真实的省份 (例如 `Pid(0)`) 混淆
+    pub fn unknown() -> Self {
+        Province {
+            pid: Pid::UNKNOWN,
+            name: "UNKNOWN".to_string(),
+        }
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.pid == Pid::UNKNOWN
+    }
+}
+
+/// join 的一行结果, 与 `(Order, User, Province)` 相同, 但是字段有名字
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct JoinedRow {
+    pub order: Order,
+    pub user: User,
+    pub province: Province,
+}
+
+impl JoinedRow {
+    pub fn oid(&self) -> Oid {
+        self.order.oid
+    }
+
+    pub fn uid(&self) -> Uid {
+        self.user.uid
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.province.pid
+    }
+
+    pub fn price(&self) -> u64 {
+        self.order.price
+    }
+
+    pub fn province_name(&self) -> &str {
+        &self.province.name
+    }
+
+    pub fn into_tuple(self) -> (Order, User, Province) {
+        (self.order, self.user, self.province)
+    }
+}
+
+impl From<(Order, User, Province)> for JoinedRow {
+    fn from((order, user, province): (Order, User, Province)) -> Self {
+        JoinedRow {
+            order,
+            user,
+            province,
+        }
+    }
+}
+
+/// 以 `K` 为 key, `V` 为 value 的 trace
+pub type Trace<K, V> = TraceAgent<ValSpine<K, V, u64, isize>>;
+
+/// 以 `K` 为 key, `V` 为 value 的 arrangement
+pub type Arrangement<S, K, V> = Arranged<S, Trace<K, V>>;
+
+/// delta join 用到的所有 arrangement。
+/// `P` 是以 pid 为 key 的 user arrangement 的 value 类型: `delta_join` 中为 `User`, `delta_join_late_materialization` 中为 `Uid`
+pub struct DeltaArrangements<S: Scope<Timestamp = u64>, P: ExchangeData> {
+    pub order_by_uid: Arrangement<S, Uid, Order>,
+    pub user_by_uid: Arrangement<S, Uid, User>,
+    pub user_by_pid: Arrangement<S, Pid, P>,
+    pub province_by_pid: Arrangement<S, Pid, Province>,
+}
+
+/// `DeltaArrangements` 对应的 trace, 与 arrangement 不同, trace 可以在 dataflow 之外使用
+pub struct DeltaTraces<P: ExchangeData> {
+    pub order_by_uid: Trace<Uid, Order>,
+    pub user_by_uid: Trace<Uid, User>,
+    pub user_by_pid: Trace<Pid, P>,
+    pub province_by_pid: Trace<Pid, Province>,
+}
+
+impl<S: Scope<Timestamp = u64>, P: ExchangeData> DeltaArrangements<S, P> {
+    // 获取所有 arrangement 的 trace, 持有 trace 会阻止 arrangement 的 compaction, 不再需要时应该及时 drop
+    pub fn traces(&self) -> DeltaTraces<P> {
+        DeltaTraces {
+            order_by_uid: self.order_by_uid.trace.clone(),
+            user_by_uid: self.user_by_uid.trace.clone(),
+            user_by_pid: self.user_by_pid.trace.clone(),
+            province_by_pid: self.province_by_pid.trace.clone(),
+        }
+    }
+}
+
+impl<S: Scope<Timestamp = u64>> DeltaArrangements<S, User> {
+    // `delta_join` 使用的 arrangement
+    pub fn new(
+        order: &Collection<S, Order>,
+        user: &Collection<S, User>,
+        province: &Collection<S, Province>,
+    ) -> Self {
+        DeltaArrangements {
+            order_by_uid: OrderByUid::arrange(order),
+            // 这里 user 被 arrange 了两次，分别以 uid, pid 为 key
+            user_by_uid: UserByUid::arrange(user),
+            user_by_pid: UserByPid::arrange(user),
+            province_by_pid: ProvinceByPid::arrange(province),
+        }
+    }
+}
+
+impl<S: Scope<Timestamp = u64>> DeltaArrangements<S, Uid> {
+    // `delta_join_late_materialization` 使用的 arrangement
+    pub fn late_materialization(
+        order: &Collection<S, Order>,
+        user: &Collection<S, User>,
+        province: &Collection<S, Province>,
+    ) -> Self {
+        DeltaArrangements {
+            order_by_uid: OrderByUid::arrange(order),
+            user_by_uid: UserByUid::arrange(user),
+            // 与 `delta_join` 不同， 这里的 value 从 User 变成了 Uid, 避免了拷贝整个 User。
+            // 这是 secondary index 而不是完整的关系, 所以不使用 `relation!` 生成的 `UserByPid`
+            user_by_pid: user.map(|u| (u.pid, u.uid)).arrange_by_key(),
+            province_by_pid: ProvinceByPid::arrange(province),
+        }
+    }
+}
+
+/// join 的各种实现, 方便在运行时选择
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Variant {
+    Regular,
+    RegularCore,
+    Delta,
+    DeltaLateMaterialization,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 4] = [
+        Variant::Regular,
+        Variant::RegularCore,
+        Variant::Delta,
+        Variant::DeltaLateMaterialization,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Regular => "regular_join",
+            Variant::RegularCore => "regular_join_core",
+            Variant::Delta => "delta_join",
+            Variant::DeltaLateMaterialization => "delta_join_late_materialization",
+        }
+    }
+
+    pub fn join<S>(
+        &self,
+        order: &Collection<S, Order>,
+        user: &Collection<S, User>,
+        province: &Collection<S, Province>,
+    ) -> Collection<S, (Order, User, Province)>
+    where
+        S: Scope<Timestamp = u64>,
+    {
+        match self {
+            Variant::Regular => regular_join(order, user, province),
+            Variant::RegularCore => regular_join_core(order, user, province),
+            Variant::Delta => delta_join(order, user, province),
+            Variant::DeltaLateMaterialization => {
+                delta_join_late_materialization(order, user, province)
+            }
+        }
+    }
+
+    // 与 `join` 相同, 只是输出的每一行是 `JoinedRow`
+    pub fn join_rows<S>(
+        &self,
+        order: &Collection<S, Order>,
+        user: &Collection<S, User>,
+        province: &Collection<S, Province>,
+    ) -> Collection<S, JoinedRow>
+    where
+        S: Scope<Timestamp = u64>,
+    {
+        self.join(order, user, province).map(JoinedRow::from)
+    }
+
+    // 与 `join` 相同, 如果指定了 `region_name` 会在对应名字的 region 中构建 join,
+    // 这样在比较大的 dataflow 中, join 的所有算子会被归到一组, 方便从算子图中辨认
+    pub fn join_in_region<S>(
+        &self,
+        region_name: Option<&str>,
+        order: &Collection<S, Order>,
+        user: &Collection<S, User>,
+        province: &Collection<S, Province>,
+    ) -> Collection<S, (Order, User, Province)>
+    where
+        S: Scope<Timestamp = u64>,
+    {
+        match region_name {
+            None => self.join(order, user, province),
+            Some(name) => order.scope().region_named(name, |inner| {
+                self.join(
+                    &order.enter_region(inner),
+                    &user.enter_region(inner),
+                    &province.enter_region(inner),
+                )
+                .leave_region()
+            }),
+        }
+    }
+}
+
+// half_join 的 `frontier_func`: 返回严格小于 `time` 的最大的时间 (time 为 0 时返回 0),
+// 所有早于这个时间的更新都已经可以与 arrangement 中的状态比较, 参考 `validate::check_frontier_func`
+pub fn step_back(time: &u64, antichain: &mut Antichain<u64>) {
+    antichain.insert(time.saturating_sub(1));
+}
+
+// 时间为 `Product<u64, u64>` (例如在 iterate 的嵌套 scope 中) 时的 `step_back`: 两个维度分别后退一步,
+// 某个维度已经为 0 时保持为 0, 两个维度都为 0 时返回时间本身
+pub fn step_back_product(time: &Product<u64, u64>, antichain: &mut Antichain<Product<u64, u64>>) {
+    antichain.insert(Product::new(
+        time.outer.saturating_sub(1),
+        time.inner.saturating_sub(1),
+    ));
+}
+
 // 普通 join
 pub fn regular_join<S>(
     order: &Collection<S, Order>,
@@ -98,12 +363,36 @@ where
     // 这里指定时间类型为 u64, 主要为为了方便实现 `frontier_func`，事实上任意 S::Timestamp: Lattice + Clone
     // 外加 [`step_back`](https://github.com/MaterializeInc/materialize/blob/4567acf28cfc56f515db87c49bc8d78cd00897e2/src/compute/src/render/mod.rs#L1098-L1100) 都可以满足要求，
     S: Scope<Timestamp = u64>,
+{
+    let arrangements = DeltaArrangements::new(order, user, province);
+    delta_join_arranged(order, user, province, &arrangements)
+}
+
+// 只有订单和用户两个关系的普通 join
+pub fn join_orders_users<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+) -> Collection<S, (Order, User)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order
+        .map(|o| (o.uid, o))
+        .join_map(&user.map(|u| (u.uid, u)), |_, o, u| (o.clone(), u.clone()))
+}
+
+// 最简单的 delta join: 只有两个关系, 都以 uid 为 key, 所以只需要两个 arrangement,
+// 每一方的更新只需要一个 half_join 去查找另一方的 arrangement
+pub fn delta_join_orders_users<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+) -> Collection<S, (Order, User)>
+where
+    S: Scope<Timestamp = u64>,
 {
     let order_arrange = order.map(|o| (o.uid, o)).arrange_by_key();
-    // 这里 user 被 arrange 了两次，分别以 uid, pid 为 key
-    let user_uid_arrange = user.map(|u| (u.uid, u)).arrange_by_key();
-    let user_pid_arrange = user.map(|u| (u.pid, u)).arrange_by_key();
-    let province_arrange = province.map(|p| (p.pid, p)).arrange_by_key();
+    let user_arrange = user.map(|u| (u.uid, u)).arrange_by_key();
 
     let order_change = order
         .inner
@@ -113,61 +402,181 @@ where
         .inner
         .map(|(u, t, r)| ((u.uid, u, t.clone()), t, r))
         .as_collection();
-    let province_change = province
-        .inner
-        .map(|(p, t, r)| ((p.pid, p, t.clone()), t, r))
-        .as_collection();
 
-    let frontier_func = |time: &u64, antichain: &mut Antichain<u64>| {
-        antichain.insert(time.saturating_sub(1));
-    };
+    let frontier_func = step_back;
 
-    // delta join 逻辑上需要定义 join 的对象的优先级, 优先级高的可以看到其他对象同一时刻的更新
-    // 这里我们定义优先级为 order < user < province
+    // 优先级为 order < user
 
     // 订单更新产生的数据
     let order_update = half_join(
         &order_change,
-        user_uid_arrange,
+        user_arrange,
         frontier_func,
         |t1, t2| t1 < t2, // P(order) < P(user) 不能看到同一时刻的更新
-        |_, o, u| (u.pid, (o.clone(), u.clone())),
-    )
-    .map(|((k, v), t)| (k, v, t));
-    let order_update = half_join(
-        &order_update,
-        province_arrange.clone(),
-        frontier_func,
-        |t1, t2| t1 < t2, // P(order) < P(province) 不能看到同一时刻的更新
-        |_, (o, u), p| (o.clone(), u.clone(), p.clone()),
+        |_, o, u| (o.clone(), u.clone()),
     );
 
     // 用户更新产生的数据
     let user_update = half_join(
         &user_change,
-        order_arrange.clone(),
+        order_arrange,
         frontier_func,
         |t1, t2| t1 <= t2, // P(user) > P(order) 可以看到同一时刻的更新
-        |_, u, o| (u.pid, (o.clone(), u.clone())),
+        |_, u, o| (o.clone(), u.clone()),
+    );
+
+    // 汇聚所有更新的数据
+    order_update
+        .concat(&user_update)
+        .inner
+        .map(|((d, t), _, r)| (d, t, r))
+        .as_collection()
+}
+
+// 与 `delta_join` 相同, 只是输出的每一行是 `JoinedRow`
+pub fn delta_join_rows<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, JoinedRow>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(order, user, province).map(JoinedRow::from)
+}
+
+// 与 `delta_join` 相同, 只是 arrangement 由外部传入, 方便在多个 dataflow 之间共享或者观察 arrangement 的状态
+pub fn delta_join_arranged<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    arrangements: &DeltaArrangements<S, User>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join_chain(
+        order,
+        user,
+        province,
+        arrangements.order_by_uid.clone(),
+        arrangements.user_by_uid.clone(),
+        arrangements.user_by_pid.clone(),
+        arrangements.province_by_pid.clone(),
+        OrderByUid::key,
+        UserByUid::key,
+        UserByPid::key,
+        ProvinceByPid::key,
     )
-    .map(|((k, v), t)| (k, v, t));
-    let user_update = half_join(
-        &user_update,
+}
+
+// `delta_join_arranged` 中的 half_join 链, 记录和 key 的类型都是泛型, `generic::delta_join` 与
+// `partition::delta_join_with_hash` 也使用这段代码。`order_uid`, `user_uid`, `user_pid`, `province_pid`
+// 从记录中取出 join 的 key, 必须与对应的 arrangement 的 key 一致
+#[allow(clippy::too_many_arguments)]
+pub fn delta_join_chain<S, O, U, P, K1, K2, FO, FU1, FU2, FP>(
+    order: &Collection<S, O>,
+    user: &Collection<S, U>,
+    province: &Collection<S, P>,
+    order_arrange: Arrangement<S, K1, O>,
+    user_uid_arrange: Arrangement<S, K1, U>,
+    user_pid_arrange: Arrangement<S, K2, U>,
+    province_arrange: Arrangement<S, K2, P>,
+    order_uid: FO,
+    user_uid: FU1,
+    user_pid: FU2,
+    province_pid: FP,
+) -> Collection<S, (O, U, P)>
+where
+    S: Scope<Timestamp = u64>,
+    O: ExchangeData,
+    U: ExchangeData,
+    P: ExchangeData,
+    K1: ExchangeData + Hashable,
+    K2: ExchangeData + Hashable,
+    FO: Fn(&O) -> K1 + 'static,
+    FU1: Fn(&U) -> K1 + Clone + 'static,
+    FU2: Fn(&U) -> K2 + Clone + 'static,
+    FP: Fn(&P) -> K2 + 'static,
+{
+    delta_join_chain_with(
+        order,
+        user,
+        province,
+        order_arrange,
+        user_uid_arrange,
+        user_pid_arrange,
         province_arrange,
-        frontier_func,
-        |t1, t2| t1 < t2, // P(user) < P(province) 不能看到同一时刻的更新
-        |_, (o, u), p| (o.clone(), u.clone(), p.clone()),
+        order_uid,
+        user_uid,
+        user_pid,
+        province_pid,
+        false,
+    )
+}
+
+// `delta_join_chain` 的实现, `batched` 为 true 时在每条路径的两个 half_join 之间插入 `batch_by_time`,
+// 对应 `delta_join_batched`
+#[allow(clippy::too_many_arguments)]
+fn delta_join_chain_with<S, O, U, P, K1, K2, FO, FU1, FU2, FP>(
+    order: &Collection<S, O>,
+    user: &Collection<S, U>,
+    province: &Collection<S, P>,
+    order_arrange: Arrangement<S, K1, O>,
+    user_uid_arrange: Arrangement<S, K1, U>,
+    user_pid_arrange: Arrangement<S, K2, U>,
+    province_arrange: Arrangement<S, K2, P>,
+    order_uid: FO,
+    user_uid: FU1,
+    user_pid: FU2,
+    province_pid: FP,
+    batched: bool,
+) -> Collection<S, (O, U, P)>
+where
+    S: Scope<Timestamp = u64>,
+    O: ExchangeData,
+    U: ExchangeData,
+    P: ExchangeData,
+    K1: ExchangeData + Hashable,
+    K2: ExchangeData + Hashable,
+    FO: Fn(&O) -> K1 + 'static,
+    FU1: Fn(&U) -> K1 + Clone + 'static,
+    FU2: Fn(&U) -> K2 + Clone + 'static,
+    FP: Fn(&P) -> K2 + 'static,
+{
+    let updates = order_user_updates(
+        order,
+        user,
+        order_arrange.clone(),
+        user_uid_arrange,
+        province_arrange,
+        order_uid,
+        user_uid.clone(),
+        user_pid,
+        batched,
     );
+    let province_change = province
+        .inner
+        .map(move |(p, t, r)| ((province_pid(&p), p, t), t, r))
+        .as_collection();
+
+    let frontier_func = step_back;
 
     // 省份更新产生的数据
+    let key = user_uid;
     let province_update = half_join(
         &province_change,
         user_pid_arrange,
         frontier_func,
         |t1, t2| t1 <= t2, // P(province) > P(user) 可以看到同一时刻的更新
-        |_, p, u| (u.uid, (u.clone(), p.clone())),
+        move |_, p, u| (key(u), (u.clone(), p.clone())),
     )
     .map(|((k, v), t)| (k, v, t));
+    let province_update = if batched {
+        batch_by_time(&province_update)
+    } else {
+        province_update
+    };
     let province_update = half_join(
         &province_update,
         order_arrange,
@@ -177,64 +586,67 @@ where
     );
 
     // 汇聚所有更新的数据
-    order_update
-        .concat(&user_update)
+    updates
         .concat(&province_update)
         .inner
         .map(|((d, t), _, r)| (d, t, r))
         .as_collection()
 }
 
-// 使用 secondary key 的 delta join.
-// 在 `delta_join` 中，User 表创建了两个 arrangement,分别以 uid,pid 为 key。这样就有可能出现一个问题，如果 User 表
-// 有很多 column, 这回导致这些 column 占用的空间都被 double 了。这里使用 [Late Materialization](https://github.com/frankmcsherry/blog/blob/master/posts/2020-11-18.md#joins-in-materialize-late-materialization)
-// 来减少内存占用， 主要原理是通过创建 secondary index 来避免拷贝整个对象，以最开始提到的问题为例子：
-// 两个 arrangement 的元素分别是 (uid, user), (pid, user), 使用 secondary index 后会改变成 (uid, user), (pid, uid)，
-// 可以看到第二个 arrangement 中使用 uid 替换了 user，这样就避免了拷贝 user 中的其他 column
-// 缺点是 secondary index 需要多一次 half_join 来关联到完整的数据。换句话说：通过增加计算开销来较少内存占用。
-pub fn delta_join_late_materialization<S>(
-    order: &Collection<S, Order>,
-    user: &Collection<S, User>,
-    province: &Collection<S, Province>,
-) -> Collection<S, (Order, User, Province)>
+// delta join 中由订单和用户的更新产生的数据, 每个结果带有它的时间。late materialization 只有省份的路径不同,
+// 所以 `delta_join_chain` 与 `delta_join_late_materialization_arranged` 共用这一部分
+#[allow(clippy::too_many_arguments)]
+fn order_user_updates<S, O, U, P, K1, K2, FO, FU1, FU2>(
+    order: &Collection<S, O>,
+    user: &Collection<S, U>,
+    order_arrange: Arrangement<S, K1, O>,
+    user_uid_arrange: Arrangement<S, K1, U>,
+    province_arrange: Arrangement<S, K2, P>,
+    order_uid: FO,
+    user_uid: FU1,
+    user_pid: FU2,
+    batched: bool,
+) -> Collection<S, ((O, U, P), u64)>
 where
     S: Scope<Timestamp = u64>,
+    O: ExchangeData,
+    U: ExchangeData,
+    P: ExchangeData,
+    K1: ExchangeData + Hashable,
+    K2: ExchangeData + Hashable,
+    FO: Fn(&O) -> K1 + 'static,
+    FU1: Fn(&U) -> K1 + 'static,
+    FU2: Fn(&U) -> K2 + Clone + 'static,
 {
-    let order_arrange = order.map(|o| (o.uid, o)).arrange_by_key();
-    let user_uid_arrange = user.map(|u| (u.uid, u)).arrange_by_key();
-    // 与 `delta_join` 不同， 这里的 value 从 User 变成了 Uid, 避免了拷贝整个 User
-    let user_pid_arrange = user.map(|u| (u.pid, u.uid)).arrange_by_key();
-    let province_arrange = province.map(|p| (p.pid, p)).arrange_by_key();
-
     let order_change = order
         .inner
-        .map(|(o, t, r)| ((o.uid, o, t.clone()), t, r))
+        .map(move |(o, t, r)| ((order_uid(&o), o, t), t, r))
         .as_collection();
     let user_change = user
         .inner
-        .map(|(u, t, r)| ((u.uid, u, t.clone()), t, r))
-        .as_collection();
-    let province_change = province
-        .inner
-        .map(|(p, t, r)| ((p.pid, p, t.clone()), t, r))
+        .map(move |(u, t, r)| ((user_uid(&u), u, t), t, r))
         .as_collection();
 
-    let frontier_func = |time: &u64, antichain: &mut Antichain<u64>| {
-        antichain.insert(time.saturating_sub(1));
-    };
+    let frontier_func = step_back;
 
     // delta join 逻辑上需要定义 join 的对象的优先级, 优先级高的可以看到其他对象同一时刻的更新
     // 这里我们定义优先级为 order < user < province
 
     // 订单更新产生的数据
+    let key = user_pid.clone();
     let order_update = half_join(
         &order_change,
-        user_uid_arrange.clone(),
+        user_uid_arrange,
         frontier_func,
         |t1, t2| t1 < t2, // P(order) < P(user) 不能看到同一时刻的更新
-        |_, o, u| (u.pid, (o.clone(), u.clone())),
+        move |_, o, u| (key(u), (o.clone(), u.clone())),
     )
     .map(|((k, v), t)| (k, v, t));
+    let order_update = if batched {
+        batch_by_time(&order_update)
+    } else {
+        order_update
+    };
     let order_update = half_join(
         &order_update,
         province_arrange.clone(),
@@ -244,14 +656,20 @@ where
     );
 
     // 用户更新产生的数据
+    let key = user_pid;
     let user_update = half_join(
         &user_change,
-        order_arrange.clone(),
+        order_arrange,
         frontier_func,
         |t1, t2| t1 <= t2, // P(user) > P(order) 可以看到同一时刻的更新
-        |_, u, o| (u.pid, (o.clone(), u.clone())),
+        move |_, u, o| (key(u), (o.clone(), u.clone())),
     )
     .map(|((k, v), t)| (k, v, t));
+    let user_update = if batched {
+        batch_by_time(&user_update)
+    } else {
+        user_update
+    };
     let user_update = half_join(
         &user_update,
         province_arrange,
@@ -260,10 +678,60 @@ where
         |_, (o, u), p| (o.clone(), u.clone(), p.clone()),
     );
 
+    order_update.concat(&user_update)
+}
+
+// 使用 secondary key 的 delta join.
+// 在 `delta_join` 中，User 表创建了两个 arrangement,分别以 uid,pid 为 key。这样就有可能出现一个问题，如果 User 表
+// 有很多 column, 这回导致这些 column 占用的空间都被 double 了。这里使用 [Late Materialization](https://github.com/frankmcsherry/blog/blob/master/posts/2020-11-18.md#joins-in-materialize-late-materialization)
+// 来减少内存占用， 主要原理是通过创建 secondary index 来避免拷贝整个对象，以最开始提到的问题为例子：
+// 两个 arrangement 的元素分别是 (uid, user), (pid, user), 使用 secondary index 后会改变成 (uid, user), (pid, uid)，
+// 可以看到第二个 arrangement 中使用 uid 替换了 user，这样就避免了拷贝 user 中的其他 column
+// 缺点是 secondary index 需要多一次 half_join 来关联到完整的数据。换句话说：通过增加计算开销来较少内存占用。
+pub fn delta_join_late_materialization<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let arrangements = DeltaArrangements::late_materialization(order, user, province);
+    delta_join_late_materialization_arranged(order, user, province, &arrangements)
+}
+
+// 与 `delta_join_late_materialization` 相同, 只是 arrangement 由外部传入
+pub fn delta_join_late_materialization_arranged<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    arrangements: &DeltaArrangements<S, Uid>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let updates = order_user_updates(
+        order,
+        user,
+        arrangements.order_by_uid.clone(),
+        arrangements.user_by_uid.clone(),
+        arrangements.province_by_pid.clone(),
+        OrderByUid::key,
+        UserByUid::key,
+        UserByPid::key,
+        false,
+    );
+    let province_change = province
+        .inner
+        .map(|(p, t, r)| ((p.pid, p, t.clone()), t, r))
+        .as_collection();
+
+    let frontier_func = step_back;
+
     // 省份更新产生的数据
     let province_update = half_join(
         &province_change,
-        user_pid_arrange,
+        arrangements.user_by_pid.clone(),
         frontier_func,
         |t1, t2| t1 <= t2, // P(province) > P(user) 可以看到同一时刻的更新
         |_, p, uid| (*uid, p.clone()),
@@ -272,7 +740,7 @@ where
     // 这是相比 `delta_join` 多的一步，这里需要通过 secondary key 重新关联到 user
     let province_update = half_join(
         &province_update,
-        user_uid_arrange,
+        arrangements.user_by_uid.clone(),
         frontier_func,
         |t1, t2| t1 <= t2, // P(province) > P(user) 可以看到同一时刻的更新
         |_, p, u| (u.uid, (u.clone(), p.clone())),
@@ -280,17 +748,807 @@ where
     .map(|((k, v), t)| (k, v, t));
     let province_update = half_join(
         &province_update,
-        order_arrange,
+        arrangements.order_by_uid.clone(),
         frontier_func,
         |t1, t2| t1 <= t2, // P(province) > P(order) 可以看到同一时刻的更新
         |_, (u, p), o| (o.clone(), u.clone(), p.clone()),
     );
 
     // 汇聚所有更新的数据
-    order_update
-        .concat(&user_update)
+    updates
         .concat(&province_update)
         .inner
         .map(|((d, t), _, r)| (d, t, r))
         .as_collection()
 }
+
+// 对每个 input 先做 `distinct`, 使 join 满足集合语义: 完全相同的 `Order` (oid/price/uid 都一样) 即使 multiplicity 为 2,
+// 也只会产生一行 join 结果。 输出中每一行的 multiplicity 最多为 1。
+// 代价是每个 input 多了一个 `distinct` 算子, 它内部会额外创建以整条记录为 key 的 arrangement, 所以内存占用会增加。
+// 如果上游已经保证了主键唯一，直接使用 `delta_join` 即可
+pub fn delta_join_distinct<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(&order.distinct(), &user.distinct(), &province.distinct())
+}
+
+// 在进入 half_join 之前对 order_change/user_change/province_change 做 `consolidate`,
+// 同一时刻内相互抵消的更新 (例如插入之后又撤回) 不会再去查找 arrangement, 减少 half_join 的调用和中间输出。
+// arrangement 仍然建立在原始的 input 上, 两者累积之后的内容相同, 所以结果与 `delta_join` 一致;
+// 代价是 `consolidate` 要等到 frontier 越过某个时刻才输出, 每个 input 多了一次 exchange 和一个时刻的延迟
+pub fn delta_join_consolidated<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let arrangements = DeltaArrangements::new(order, user, province);
+    delta_join_arranged(
+        &order.consolidate(),
+        &user.consolidate(),
+        &province.consolidate(),
+        &arrangements,
+    )
+}
+
+// 在 `delta_join` 的基础上额外输出一个 dead-letter collection, 包含所有找不到对应 user 的订单
+// (比如 `Uid(u64::MAX)` 这样的哨兵值), 而不是让它们在 join 中被悄悄丢弃。
+// dead-letter 是通过 antijoin 持续维护的: 如果之后对应的 user 出现了, 订单会从 dead-letter 中被撤回,
+// 所以只有当 frontier 推进到某个时刻之后, 该时刻的 dead-letter 才是确定的
+pub fn delta_join_with_deadletter<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> (Collection<S, (Order, User, Province)>, Collection<S, Order>)
+where
+    S: Scope<Timestamp = u64>,
+{
+    let joined = delta_join(order, user, province);
+    let dead_letter = order
+        .map(|o| (o.uid, o))
+        .antijoin(&user.map(|u| u.uid).distinct())
+        .map(|(_, o)| o);
+    (joined, dead_letter)
+}
+
+// 与 `delta_join` 相同, 但是用户的省份还没有加载时, 这个用户的订单不会被丢弃, 而是与占位省份 `Province::unknown()` join,
+// 占位省份的 pid 是保留的 `Pid::UNKNOWN`, 可以通过 `Province::is_unknown` 与真实的省份区分。
+// 占位的结果是通过 antijoin 持续维护的: 对应的省份在某个时刻出现时, 占位的结果会在这个时刻被撤回,
+// 同时 `delta_join` 在这个时刻输出真实省份的结果; 省份被删除时则反过来
+pub fn delta_join_with_unknown_province<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let joined = delta_join(order, user, province);
+    let unknown = delta_join_orders_users(order, user)
+        .map(|(o, u)| (u.pid, (o, u)))
+        .antijoin(&province.map(|p| p.pid).distinct())
+        .map(|(_, (o, u))| (o, u, Province::unknown()));
+    joined.concat(&unknown)
+}
+
+// 把 collection 中的更新按照时间缓存起来, 等到 frontier 越过某个时刻之后再把这个时刻的所有更新一次性发送给下游。
+// 这样下游算子每个时刻只需要被调度一次, 代价是增加了一个时刻的延迟
+pub fn batch_by_time<S, D>(collection: &Collection<S, D>) -> Collection<S, D>
+where
+    S: Scope<Timestamp = u64>,
+    D: ExchangeData,
+{
+    let mut stash: HashMap<u64, Vec<(D, u64, isize)>> = HashMap::new();
+    collection
+        .inner
+        .unary_notify(
+            Pipeline,
+            "BatchByTime",
+            None,
+            move |input, output, notificator| {
+                input.for_each(|time, data| {
+                    stash
+                        .entry(*time.time())
+                        .or_default()
+                        .extend(data.iter().cloned());
+                    notificator.notify_at(time.retain());
+                });
+                notificator.for_each(|time, _, _| {
+                    if let Some(batch) = stash.remove(time.time()) {
+                        output.session(&time).give_iterator(batch.into_iter());
+                    }
+                });
+            },
+        )
+        .as_collection()
+}
+
+// 与 `delta_join` 相同, 只是在两个 half_join 之间插入了 `batch_by_time`, 第一个 half_join 的输出每个时刻只会向第二个
+// half_join 发送一次, 减少了算子调度的开销。输出与 `delta_join` 完全一致
+pub fn delta_join_batched<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let arrangements = DeltaArrangements::new(order, user, province);
+    delta_join_chain_with(
+        order,
+        user,
+        province,
+        arrangements.order_by_uid,
+        arrangements.user_by_uid,
+        arrangements.user_by_pid,
+        arrangements.province_by_pid,
+        OrderByUid::key,
+        UserByUid::key,
+        UserByPid::key,
+        ProvinceByPid::key,
+        true,
+    )
+}
+
+/// 以 pid 为 key 的 user arrangement 中存储的内容
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Materialization {
+    /// 存储完整的 User, 计算更快, 占用内存更多, 对应 `delta_join`
+    Full,
+    /// 只存储 Uid, 占用内存更少, 但是需要多一次 half_join, 对应 `delta_join_late_materialization`
+    Late,
+}
+
+// 在运行时选择 user 的 secondary index 存储的内容, 两种方式的输出完全相同
+pub fn delta_join_materialization<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    mode: Materialization,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    match mode {
+        Materialization::Full => delta_join(order, user, province),
+        Materialization::Late => delta_join_late_materialization(order, user, province),
+    }
+}
+
+// 只为 `hot_uids` 中的用户维护 join 结果, 订单和用户先与 hot set 做 semijoin 再进行 delta join。
+// `hot_uids` 本身也是一个 collection: 加入一个新的 uid 时, 这个用户之前的订单会在加入的时刻被补全到结果中;
+// 移除 uid 时, 对应的结果会被撤回
+pub fn delta_join_hot<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    hot_uids: &Collection<S, Uid>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let hot_uids = hot_uids.distinct();
+    let order = order
+        .map(|o| (o.uid, o))
+        .semijoin(&hot_uids)
+        .map(|(_, o)| o);
+    let user = user.map(|u| (u.uid, u)).semijoin(&hot_uids).map(|(_, u)| u);
+    delta_join(&order, &user, province)
+}
+
+// 用 `user_asof` 时刻的用户数据与当前的订单和省份做 join, 例如 "按照昨天的用户信息" 来关联订单。
+// 用户的 arrangement 中保存了完整的更新历史, 这里持有它的一个 trace handle, 把 logical compaction 保持在 `user_asof`,
+// 这样 `user_asof` 及之前的更新的时间最多被合并到 `user_asof`, 之后的更新依然可以与之区分。
+// 订单通过 half_join 读取 arrangement, 比较函数只接受时间不超过 `user_asof` 的用户更新;
+// `frontier_func` 保证订单会等到用户的 arrangement 越过 `user_asof` 之后才去查找, 所以总是能看到完整的快照。
+// 用户的更新本身不会产生输出: 之前的更新已经被订单看到, 之后的更新被忽略。省份使用最新的数据, 通过普通的 join 关联。
+// 输出结束之后释放 trace handle, 用户的 arrangement 恢复正常的 compaction
+pub fn join_orders_with_past_users<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    user_asof: u64,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let user_by_uid = UserByUid::arrange(user);
+    let mut retained = user_by_uid.trace.clone();
+    retained.set_logical_compaction(AntichainRef::new(&[user_asof]));
+    retained.set_physical_compaction(AntichainRef::new(&[user_asof]));
+
+    let order_change = order
+        .inner
+        .map(|(o, t, r)| ((o.uid, o, t.clone()), t, r))
+        .as_collection();
+    let order_user = half_join(
+        &order_change,
+        user_by_uid,
+        move |time: &u64, antichain: &mut Antichain<u64>| {
+            antichain.insert(time.saturating_sub(1).max(user_asof));
+        },
+        move |t1: &u64, _: &u64| *t1 <= user_asof, // 只能看到 `user_asof` 及之前的用户更新
+        |_, o, u| (u.pid, (o.clone(), u.clone())),
+    )
+    .inner
+    .map(|((d, t), _, r)| (d, t, r))
+    .as_collection();
+    let joined = order_user.join_map(&province.map(|p| (p.pid, p)), |_, (o, u), p| {
+        (o.clone(), u.clone(), p.clone())
+    });
+
+    let mut retained = Some(retained);
+    joined
+        .inner
+        .unary_frontier(Pipeline, "RetainUsers", move |_, _| {
+            move |input, output| {
+                input.for_each(|time, data| {
+                    output.session(&time).give_iterator(data.iter().cloned());
+                });
+                if input.frontier().is_empty() {
+                    retained = None;
+                }
+            }
+        })
+        .as_collection()
+}
+
+// 与 `delta_join` 相同, 但是 arrangement 会保留输出 frontier 之前 `lag` 个时刻的历史:
+// compaction frontier 始终为 `frontier - lag`, 在这之后的时刻可以通过返回的 trace 准确地读出来, 更早的时刻已经被合并。
+// 输出结束之后 compaction 停留在最后一个非空的 frontier 减去 `lag`, 所以运行结束之后依然可以查询最后 `lag` 个时刻。
+// `lag` 越小占用的内存越少, 能查询的历史也越短。返回的 trace 由内部的算子负责推进, 使用者不需要也不应该修改它的 compaction
+pub fn delta_join_compaction_lag<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    lag: u64,
+) -> (
+    Collection<S, (Order, User, Province)>,
+    Rc<RefCell<DeltaTraces<User>>>,
+)
+where
+    S: Scope<Timestamp = u64>,
+{
+    let arrangements = DeltaArrangements::new(order, user, province);
+    let traces = Rc::new(RefCell::new(arrangements.traces()));
+    let joined = delta_join_arranged(order, user, province, &arrangements);
+
+    let handle = traces.clone();
+    let joined = joined
+        .inner
+        .unary_frontier(Pipeline, "CompactionLag", move |_, _| {
+            move |input, output| {
+                input.for_each(|time, data| {
+                    output.session(&time).give_iterator(data.iter().cloned());
+                });
+                // 输出结束之后 frontier 为空, 这时保持最后一次的 compaction, 保留的历史依然可以读取
+                let Some(time) = input.frontier().frontier().first().copied() else {
+                    return;
+                };
+                let compaction = [time.saturating_sub(lag)];
+                let frontier = AntichainRef::new(&compaction);
+                let mut traces = handle.borrow_mut();
+                traces.order_by_uid.set_logical_compaction(frontier);
+                traces.order_by_uid.set_physical_compaction(frontier);
+                traces.user_by_uid.set_logical_compaction(frontier);
+                traces.user_by_uid.set_physical_compaction(frontier);
+                traces.user_by_pid.set_logical_compaction(frontier);
+                traces.user_by_pid.set_physical_compaction(frontier);
+                traces.province_by_pid.set_logical_compaction(frontier);
+                traces.province_by_pid.set_physical_compaction(frontier);
+            }
+        })
+        .as_collection();
+    (joined, traces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Update;
+    use crate::gen::generate;
+    use crate::harness::{self, run_join};
+
+    // 生成分布在多个时刻的更新, 包括撤回和重新插入, 用来比较不同的 join 实现
+    #[allow(clippy::type_complexity)]
+    fn updates(seed: u64) -> (Vec<Update<Order>>, Vec<Update<User>>, Vec<Update<Province>>) {
+        let dataset = generate(seed, 50, 0);
+        let mut orders: Vec<_> = dataset
+            .orders
+            .iter()
+            .enumerate()
+            .map(|(i, o)| (o.clone(), (i % 5) as u64, 1))
+            .collect();
+        // 撤回一部分订单
+        for (i, o) in dataset.orders.iter().enumerate().step_by(7) {
+            orders.push((o.clone(), (i % 5) as u64 + 2, -1));
+        }
+        let mut users: Vec<_> = dataset
+            .users
+            .iter()
+            .enumerate()
+            .map(|(i, u)| (u.clone(), (i % 4) as u64, 1))
+            .collect();
+        // 一部分用户更换了省份
+        for (i, u) in dataset.users.iter().enumerate().step_by(5) {
+            let moved = User {
+                pid: Pid((u.pid.0 + 1) % crate::gen::PROVINCES),
+                ..u.clone()
+            };
+            users.push((u.clone(), (i % 4) as u64 + 3, -1));
+            users.push((moved, (i % 4) as u64 + 3, 1));
+        }
+        let provinces = dataset
+            .provinces
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.clone(), (i % 3) as u64, 1))
+            .collect();
+        (orders, users, provinces)
+    }
+
+    fn expected(seed: u64) -> Vec<Update<(Order, User, Province)>> {
+        let (o, u, p) = updates(seed);
+        harness::run(Variant::Delta, o, u, p)
+    }
+
+    #[test]
+    fn batched_matches_delta_join() {
+        let (o, u, p) = updates(1);
+        let batched = run_join(|o, u, p| delta_join_batched(o, u, p), o, u, p);
+        assert!(!batched.is_empty());
+        assert_eq!(batched, expected(1));
+    }
+
+    #[test]
+    fn consolidated_matches_delta_join() {
+        let (o, u, p) = updates(2);
+        let consolidated = run_join(|o, u, p| delta_join_consolidated(o, u, p), o, u, p);
+        assert_eq!(consolidated, expected(2));
+    }
+
+    #[test]
+    fn join_in_region_matches_delta_join() {
+        for variant in Variant::ALL {
+            let (o, u, p) = updates(3);
+            let in_region = run_join(
+                move |o, u, p| variant.join_in_region(Some("join"), o, u, p),
+                o,
+                u,
+                p,
+            );
+            assert_eq!(in_region, expected(3), "{}", variant.name());
+        }
+    }
+
+    #[test]
+    fn materialization_modes_agree() {
+        for mode in [Materialization::Full, Materialization::Late] {
+            let (o, u, p) = updates(4);
+            let joined = run_join(
+                move |o, u, p| delta_join_materialization(o, u, p, mode),
+                o,
+                u,
+                p,
+            );
+            assert!(!joined.is_empty(), "{:?}", mode);
+            assert_eq!(joined, expected(4), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn distinct_collapses_duplicate_orders() {
+        let order = Order {
+            oid: Oid(1),
+            price: 10,
+            uid: Uid(1),
+        };
+        let user = User {
+            uid: Uid(1),
+            pid: Pid(1),
+            padding: String::new(),
+        };
+        let province = Province {
+            pid: Pid(1),
+            name: "p1".to_string(),
+        };
+        // 同一个订单被输入了两次
+        let updates = || {
+            (
+                vec![(order.clone(), 0, 1), (order.clone(), 0, 1)],
+                vec![(user.clone(), 0, 1)],
+                vec![(province.clone(), 0, 1)],
+            )
+        };
+        let row = (order.clone(), user.clone(), province.clone());
+
+        let (o, u, p) = updates();
+        let distinct = run_join(|o, u, p| delta_join_distinct(o, u, p), o, u, p);
+        assert_eq!(distinct, vec![(row.clone(), 0, 1)]);
+
+        let (o, u, p) = updates();
+        assert_eq!(harness::run(Variant::Delta, o, u, p), vec![(row, 0, 2)]);
+    }
+
+    #[test]
+    fn past_users_keep_old_province() {
+        use differential_dataflow::input::Input;
+
+        let user = |pid| User {
+            uid: Uid(1),
+            pid: Pid(pid),
+            padding: String::new(),
+        };
+        let province = |pid, name: &str| Province {
+            pid: Pid(pid),
+            name: name.to_string(),
+        };
+        let order = |oid| Order {
+            oid: Oid(oid),
+            price: 1,
+            uid: Uid(1),
+        };
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                join_orders_with_past_users(&orders, &users, &provinces, 1)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            u.insert(user(1));
+            p.insert(province(1, "a"));
+            p.insert(province(2, "b"));
+            for time in 1..4 {
+                o.advance_to(time);
+                u.advance_to(time);
+                p.advance_to(time);
+                match time {
+                    // 在 `user_asof` 之后用户搬到了省份 2
+                    2 => {
+                        u.remove(user(1));
+                        u.insert(user(2));
+                    }
+                    // 订单依然使用旧的省份, 但是省份本身是最新的
+                    3 => {
+                        o.insert(order(1));
+                        p.remove(province(1, "a"));
+                        p.insert(province(1, "a2"));
+                    }
+                    _ => {}
+                }
+            }
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        differential_dataflow::consolidation::consolidate_updates(&mut output);
+        assert_eq!(output, vec![((order(1), user(1), province(1, "a2")), 3, 1)]);
+    }
+
+    #[test]
+    fn compaction_lag_keeps_recent_history() {
+        use differential_dataflow::input::Input;
+
+        use crate::peek::state_at;
+
+        let province = |t: u64| Province {
+            pid: Pid(1),
+            name: format!("v{}", t),
+        };
+        // 时刻足够多, 保证早期的 batch 已经在合并时被推进到 compaction frontier
+        const END: u64 = 32;
+        let (inside, outside, compaction) = timely::execute_directly(move |worker| {
+            let (mut o, mut u, mut p, probe, traces) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                let (joined, traces) = delta_join_compaction_lag(&order, &user, &province, 2);
+                (o, u, p, joined.probe(), traces)
+            });
+            // 每个时刻修改一次省份的名字
+            p.insert(province(0));
+            for t in 1..END {
+                o.advance_to(t);
+                u.advance_to(t);
+                p.advance_to(t);
+                p.remove(province(t - 1));
+                p.insert(province(t));
+            }
+            o.advance_to(END);
+            u.advance_to(END);
+            p.advance_to(END);
+            o.flush();
+            u.flush();
+            p.flush();
+            worker.step_while(|| probe.less_than(&END));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+
+            let mut traces = traces.borrow_mut();
+            let trace = &mut traces.province_by_pid;
+            let compaction = trace.get_logical_compaction().to_vec();
+            let inside: Vec<_> = (END - 2..END).map(|t| state_at(trace, t)).collect();
+            let outside = state_at(trace, 1);
+            (inside, outside, compaction)
+        });
+        // 运行结束之后 compaction 停留在 END - 2, 窗口之外的历史可以被合并
+        assert_eq!(compaction, vec![END - 2]);
+        // 窗口之内的每个时刻都可以准确地读出来
+        for (t, state) in (END - 2..END).zip(inside) {
+            assert_eq!(state, vec![((Pid(1), province(t)), 1)]);
+        }
+        // 窗口之外的历史已经被合并: 时刻 1 的名字读不出来了
+        assert_ne!(outside, vec![((Pid(1), province(1)), 1)]);
+    }
+
+    #[test]
+    fn unknown_province_is_replaced_when_province_arrives() {
+        use differential_dataflow::input::Input;
+
+        let order = Order {
+            oid: Oid(1),
+            price: 1,
+            uid: Uid(1),
+        };
+        let user = User {
+            uid: Uid(1),
+            pid: Pid(0),
+            padding: String::new(),
+        };
+        let province = Province {
+            pid: Pid(0),
+            name: "p0".to_string(),
+        };
+        let (o2, u2, p2) = (order.clone(), user.clone(), province.clone());
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                delta_join_with_unknown_province(&orders, &users, &provinces)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            o.insert(o2);
+            u.insert(u2);
+            // 省份在 t=5 才加载
+            o.advance_to(5);
+            u.advance_to(5);
+            p.advance_to(5);
+            p.insert(p2);
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        differential_dataflow::consolidation::consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((order.clone(), user.clone(), province), 5, 1),
+                ((order.clone(), user.clone(), Province::unknown()), 0, 1),
+                ((order, user, Province::unknown()), 5, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn sentinel_order_lands_in_dead_letter() {
+        use differential_dataflow::input::Input;
+
+        let order = Order {
+            oid: Oid(1),
+            price: 1,
+            uid: Uid(1),
+        };
+        let sentinel = Order {
+            oid: Oid(2),
+            price: 2,
+            uid: Uid(u64::MAX),
+        };
+        let user = User {
+            uid: Uid(1),
+            pid: Pid(0),
+            padding: String::new(),
+        };
+        let province = Province {
+            pid: Pid(0),
+            name: "p0".to_string(),
+        };
+        let (o1, o2, u1, p1) = (
+            order.clone(),
+            sentinel.clone(),
+            user.clone(),
+            province.clone(),
+        );
+        let (mut joined, mut dead_letter) = timely::execute_directly(move |worker| {
+            let joined = Rc::new(RefCell::new(Vec::new()));
+            let dead_letter = Rc::new(RefCell::new(Vec::new()));
+            let (joined_sink, dead_letter_sink) = (joined.clone(), dead_letter.clone());
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                let (j, d) = delta_join_with_deadletter(&orders, &users, &provinces);
+                j.inspect(move |x| joined_sink.borrow_mut().push(x.clone()));
+                d.inspect(move |x| dead_letter_sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            o.insert(o1);
+            o.insert(o2);
+            u.insert(u1);
+            p.insert(p1);
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            (joined.take(), dead_letter.take())
+        });
+        differential_dataflow::consolidation::consolidate_updates(&mut joined);
+        differential_dataflow::consolidation::consolidate_updates(&mut dead_letter);
+        assert_eq!(joined, vec![((order, user, province), 0, 1)]);
+        assert_eq!(dead_letter, vec![(sentinel, 0, 1)]);
+    }
+
+    #[test]
+    fn hot_uid_backfills_prior_orders() {
+        use differential_dataflow::input::Input;
+
+        let order = |oid, uid| Order {
+            oid: Oid(oid),
+            price: 1,
+            uid: Uid(uid),
+        };
+        let user = |uid| User {
+            uid: Uid(uid),
+            pid: Pid(0),
+            padding: String::new(),
+        };
+        let province = Province {
+            pid: Pid(0),
+            name: "p0".to_string(),
+        };
+        let p0 = province.clone();
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p, mut h) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                let (h, hot_uids) = scope.new_collection();
+                delta_join_hot(&orders, &users, &provinces, &hot_uids)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p, h)
+            });
+            for uid in [1, 2] {
+                o.insert(order(uid, uid));
+                u.insert(user(uid));
+            }
+            p.insert(p0);
+            h.insert(Uid(2));
+            // 用户 1 在 t=5 才加入 hot set
+            o.advance_to(5);
+            u.advance_to(5);
+            p.advance_to(5);
+            h.advance_to(5);
+            h.insert(Uid(1));
+            o.close();
+            u.close();
+            p.close();
+            h.close();
+            while worker.step() {}
+            output.take()
+        });
+        differential_dataflow::consolidation::consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((order(1, 1), user(1), province.clone()), 5, 1),
+                ((order(2, 2), user(2), province), 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn joined_row_accessors_and_serde_round_trip() {
+        let row = JoinedRow::from((
+            Order {
+                oid: Oid(1),
+                price: 10,
+                uid: Uid(2),
+            },
+            User {
+                uid: Uid(2),
+                pid: Pid(3),
+                padding: String::new(),
+            },
+            Province {
+                pid: Pid(3),
+                name: "p3".to_string(),
+            },
+        ));
+        assert_eq!(row.oid(), Oid(1));
+        assert_eq!(row.uid(), Uid(2));
+        assert_eq!(row.pid(), Pid(3));
+        assert_eq!(row.price(), 10);
+        assert_eq!(row.province_name(), "p3");
+
+        let json = serde_json::to_string(&row).unwrap();
+        let decoded: JoinedRow = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, row);
+        assert_eq!(JoinedRow::from(decoded.into_tuple()), row);
+    }
+
+    #[test]
+    fn two_way_joins_agree_with_reference() {
+        use differential_dataflow::consolidation::{consolidate, consolidate_updates};
+        use differential_dataflow::input::InputSession;
+
+        fn accumulate<D: Ord>(updates: Vec<Update<D>>) -> Vec<(D, isize)> {
+            let mut rows: Vec<_> = updates.into_iter().map(|(d, _, r)| (d, r)).collect();
+            consolidate(&mut rows);
+            rows
+        }
+
+        let run = |delta: bool| {
+            let (order, user, _) = updates(5);
+            timely::execute_directly(move |worker| {
+                let output = Rc::new(RefCell::new(Vec::new()));
+                let sink = output.clone();
+                let mut o = InputSession::new();
+                let mut u = InputSession::new();
+                worker.dataflow(|scope| {
+                    let (order, user) = (o.to_collection(scope), u.to_collection(scope));
+                    let joined = if delta {
+                        delta_join_orders_users(&order, &user)
+                    } else {
+                        join_orders_users(&order, &user)
+                    };
+                    joined.inspect(move |x| sink.borrow_mut().push(x.clone()));
+                });
+                order.into_iter().for_each(|(d, t, r)| o.update_at(d, t, r));
+                user.into_iter().for_each(|(d, t, r)| u.update_at(d, t, r));
+                o.close();
+                u.close();
+                while worker.step() {}
+                let mut output = output.take();
+                consolidate_updates(&mut output);
+                output
+            })
+        };
+        let regular = run(false);
+        assert_eq!(run(true), regular);
+
+        // 参考实现: 忽略时间累积两个 input, 再用嵌套循环 join
+        let (order, user, _) = updates(5);
+        let (order, user) = (accumulate(order), accumulate(user));
+        let mut reference = Vec::new();
+        for (o, r1) in order.iter() {
+            for (u, r2) in user.iter().filter(|(u, _)| u.uid == o.uid) {
+                reference.push(((o.clone(), u.clone()), r1 * r2));
+            }
+        }
+        consolidate(&mut reference);
+        assert!(!reference.is_empty());
+        assert_eq!(accumulate(regular), reference);
+    }
+}
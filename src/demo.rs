@@ -0,0 +1,119 @@
+use differential_dataflow::input::InputSession;
+
+use crate::delta_join::{
+    delta_join_arranged, delta_join_late_materialization_arranged, DeltaArrangements,
+};
+use crate::gen;
+use crate::metrics::{delta_trace_stats, TraceStats};
+
+/// 每个用户 `padding` 的长度, 模拟 User 中较大的 column
+pub const PADDING: usize = 256;
+
+/// 一个 join 实现中各个 arrangement 的统计信息
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    pub variant: &'static str,
+    pub arrangements: Vec<(&'static str, TraceStats)>,
+}
+
+impl MemoryReport {
+    pub fn get(&self, name: &str) -> Option<TraceStats> {
+        self.arrangements
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, s)| *s)
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.arrangements.iter().map(|(_, s)| s.bytes).sum()
+    }
+}
+
+// 在同一份数据上同时运行 `delta_join` 和 `delta_join_late_materialization`, 运行到稳定状态后统计并打印
+// 各个 arrangement 的 tuple 数量和估计的内存占用。
+// 两者唯一的区别在 `user_by_pid`: 前者存的是完整的 User, 后者只存 Uid, 在 User 比较大的时候差别十分明显
+pub fn memory_comparison(scale: u64) -> (MemoryReport, MemoryReport) {
+    let dataset = gen::generate(0, scale, PADDING);
+    let (delta, late) = timely::execute_directly(move |worker| {
+        let mut order_input = InputSession::new();
+        let mut user_input = InputSession::new();
+        let mut province_input = InputSession::new();
+
+        let (probe, mut delta, mut late) = worker.dataflow(|scope| {
+            let order = order_input.to_collection(scope);
+            let user = user_input.to_collection(scope);
+            let province = province_input.to_collection(scope);
+
+            let delta = DeltaArrangements::new(&order, &user, &province);
+            let late = DeltaArrangements::late_materialization(&order, &user, &province);
+            let probe = delta_join_arranged(&order, &user, &province, &delta)
+                .concat(&delta_join_late_materialization_arranged(
+                    &order, &user, &province, &late,
+                ))
+                .probe();
+            (probe, delta.traces(), late.traces())
+        });
+
+        dataset
+            .orders
+            .into_iter()
+            .for_each(|o| order_input.insert(o));
+        dataset.users.into_iter().for_each(|u| user_input.insert(u));
+        dataset
+            .provinces
+            .into_iter()
+            .for_each(|p| province_input.insert(p));
+        order_input.advance_to(1);
+        user_input.advance_to(1);
+        province_input.advance_to(1);
+        order_input.flush();
+        user_input.flush();
+        province_input.flush();
+        worker.step_while(|| probe.less_than(&1));
+
+        let delta = MemoryReport {
+            variant: "delta_join",
+            arrangements: delta_trace_stats(&mut delta),
+        };
+        let late = MemoryReport {
+            variant: "delta_join_late_materialization",
+            arrangements: delta_trace_stats(&mut late),
+        };
+        (delta, late)
+    });
+
+    for report in [&delta, &late] {
+        println!("{} (total {} bytes)", report.variant, report.total_bytes());
+        for (name, stats) in &report.arrangements {
+            println!(
+                "  {:<16} tuples: {:>10} bytes: {:>12}",
+                name, stats.tuples, stats.bytes
+            );
+        }
+    }
+    (delta, late)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_materialization_user_by_pid_is_smaller() {
+        let (delta, late) = memory_comparison(100);
+        let eager = delta.get("user_by_pid").unwrap();
+        let lazy = late.get("user_by_pid").unwrap();
+        // 两者保存的用户数量相同, 只是 value 从完整的 User 变成了 Uid
+        assert_eq!(eager.tuples, lazy.tuples);
+        assert!(
+            lazy.bytes < eager.bytes,
+            "late materialization uses {} bytes, eager uses {}",
+            lazy.bytes,
+            eager.bytes
+        );
+        // 其余的 arrangement 完全相同
+        for name in ["order_by_uid", "user_by_uid", "province_by_pid"] {
+            assert_eq!(delta.get(name), late.get(name), "{}", name);
+        }
+    }
+}
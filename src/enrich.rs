@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use differential_dataflow::Collection;
+use timely::dataflow::Scope;
+
+use crate::delta_join::{delta_join, Order, Pid, Province, User};
+
+// 在 join 结果上增加折扣价: `discounted_price = price * discounts[pid]`, 结果四舍五入到整数。
+// `discounts` 中存的是折扣系数, 例如打九折为 0.9, 不在 `discounts` 中的省份不打折。
+// 折扣表是静态的, 订单价格变化时对应的折扣价会随着 join 结果一起更新
+pub fn join_with_discount<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    discounts: HashMap<Pid, f64>,
+) -> Collection<S, (Order, Province, u64)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    delta_join(order, user, province).map(move |(o, _, p)| {
+        let factor = discounts.get(&p.pid).copied().unwrap_or(1.0);
+        let discounted = (o.price as f64 * factor).round() as u64;
+        (o, p, discounted)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::{Oid, Uid};
+
+    #[test]
+    fn discounted_price_follows_price_change() {
+        let order = |oid, price, uid| Order {
+            oid: Oid(oid),
+            price,
+            uid: Uid(uid),
+        };
+        let province = |pid| Province {
+            pid: Pid(pid),
+            name: format!("p{}", pid),
+        };
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                // 省份 1 打九折, 省份 2 不打折
+                let discounts = [(Pid(1), 0.9)].into_iter().collect();
+                join_with_discount(&orders, &users, &provinces, discounts)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            for id in [1, 2] {
+                u.insert(User {
+                    uid: Uid(id),
+                    pid: Pid(id),
+                    padding: String::new(),
+                });
+                p.insert(province(id));
+            }
+            o.insert(order(1, 100, 1));
+            o.insert(order(2, 50, 2));
+            // 时刻 1: 订单 1 的价格改为 120
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.remove(order(1, 100, 1));
+            o.insert(order(1, 120, 1));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((order(1, 100, 1), province(1), 90), 0, 1),
+                ((order(1, 100, 1), province(1), 90), 1, -1),
+                ((order(1, 120, 1), province(1), 108), 1, 1),
+                ((order(2, 50, 2), province(2), 50), 0, 1),
+            ]
+        );
+    }
+}
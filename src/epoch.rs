@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use crate::source::Inputs;
+
+/// 业务上的 epoch, 由调用方显式推进, 与 dataflow 的时间戳解耦
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Epoch(pub u64);
+
+/// epoch 与时间戳的对应关系: 每个 epoch 结束于一个时间戳, 包含所有早于这个时间戳的更新
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Epochs {
+    // ends[n] 为第 n 个 epoch 结束的时间戳 (不包含)
+    ends: Vec<u64>,
+}
+
+impl Epochs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 当前还没有结束的 epoch
+    pub fn current(&self) -> Epoch {
+        Epoch(self.ends.len() as u64)
+    }
+
+    // 结束当前的 epoch, 它包含所有时间早于 `end` 的更新, 返回结束的 epoch。`end` 不能早于上一个 epoch 的结束时间
+    pub fn close_at(&mut self, end: u64) -> Epoch {
+        if let Some(last) = self.ends.last() {
+            assert!(
+                *last <= end,
+                "epoch end {} is earlier than the previous end {}",
+                end,
+                last
+            );
+        }
+        let epoch = self.current();
+        self.ends.push(end);
+        epoch
+    }
+
+    // 结束当前的 epoch: 它包含 `inputs` 当前时刻以及之前的所有更新, 然后把 `inputs` 推进到下一个时刻,
+    // 之后输入的更新属于下一个 epoch
+    pub fn advance(&mut self, inputs: &mut Inputs) -> Epoch {
+        let end = inputs.time() + 1;
+        inputs.advance_to(end);
+        inputs.flush();
+        self.close_at(end)
+    }
+
+    // 已经结束的 `epoch` 的结束时间 (不包含), epoch 还没有结束时返回 None
+    pub fn end_of(&self, epoch: Epoch) -> Option<u64> {
+        self.ends.get(epoch.0 as usize).copied()
+    }
+}
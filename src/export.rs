@@ -0,0 +1,206 @@
+#[cfg(feature = "sqlite")]
+use std::path::Path;
+
+#[cfg(feature = "polars")]
+use polars::prelude::*;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+
+use crate::delta_join::{Order, Province, User};
+
+/// join 最终结果的列式表示, 各种导出格式都基于它
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Columns {
+    pub oid: Vec<u64>,
+    pub price: Vec<u64>,
+    pub uid: Vec<u64>,
+    pub pid: Vec<u64>,
+    pub province_name: Vec<String>,
+    pub diff: Vec<i64>,
+}
+
+impl Columns {
+    pub fn len(&self) -> usize {
+        self.oid.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.oid.is_empty()
+    }
+}
+
+// 把收集到的最终结果转换成列式表示, 行的顺序保持不变
+pub fn to_columns(final_rows: &[((Order, User, Province), isize)]) -> Columns {
+    let mut columns = Columns::default();
+    for ((o, u, p), diff) in final_rows {
+        columns.oid.push(o.oid.0);
+        columns.price.push(o.price);
+        columns.uid.push(u.uid.0);
+        columns.pid.push(p.pid.0);
+        columns.province_name.push(p.name.clone());
+        columns.diff.push(*diff as i64);
+    }
+    columns
+}
+
+// 转换成 Polars 的 DataFrame, 列依次为 oid, price, uid, pid, province_name, diff
+#[cfg(feature = "polars")]
+pub fn to_polars(final_rows: Vec<((Order, User, Province), isize)>) -> PolarsResult<DataFrame> {
+    let columns = to_columns(&final_rows);
+    df!(
+        "oid" => columns.oid,
+        "price" => columns.price,
+        "uid" => columns.uid,
+        "pid" => columns.pid,
+        "province_name" => columns.province_name,
+        "diff" => columns.diff,
+    )
+}
+
+// 把最终结果写入 SQLite 数据库 `db_path` 中的表 `table`, 表不存在时会被创建, 列为 oid, price, uid, pid, province_name。
+// 写入之前会先 consolidate, 只写入 multiplicity 为正的行, multiplicity 为 n 的行会写入 n 次, 被撤回的行不会出现在表中。
+// 所有的行在一个事务中写入, 返回写入的行数
+#[cfg(feature = "sqlite")]
+pub fn to_sqlite(
+    final_rows: &[((Order, User, Province), isize)],
+    db_path: impl AsRef<Path>,
+    table: &str,
+) -> rusqlite::Result<usize> {
+    let mut rows = final_rows.to_vec();
+    differential_dataflow::consolidation::consolidate(&mut rows);
+
+    let table = format!("\"{}\"", table.replace('"', "\"\""));
+    let mut conn = Connection::open(db_path)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (oid INTEGER, price INTEGER, uid INTEGER, pid INTEGER, province_name TEXT)",
+            table
+        ),
+        [],
+    )?;
+    let mut count = 0;
+    {
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO {} (oid, price, uid, pid, province_name) VALUES (?1, ?2, ?3, ?4, ?5)",
+            table
+        ))?;
+        for ((o, u, p), diff) in rows.iter().filter(|(_, diff)| *diff > 0) {
+            for _ in 0..*diff {
+                insert.execute(params![
+                    o.oid.0 as i64,
+                    o.price as i64,
+                    u.uid.0 as i64,
+                    p.pid.0 as i64,
+                    p.name
+                ])?;
+                count += 1;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+#[cfg(all(test, any(feature = "polars", feature = "sqlite")))]
+mod tests {
+    use super::*;
+    use crate::delta_join::{Oid, Pid, Uid};
+
+    fn row(oid: u64, uid: u64, pid: u64) -> (Order, User, Province) {
+        (
+            Order {
+                oid: Oid(oid),
+                price: oid * 10,
+                uid: Uid(uid),
+            },
+            User {
+                uid: Uid(uid),
+                pid: Pid(pid),
+                padding: String::new(),
+            },
+            Province {
+                pid: Pid(pid),
+                name: format!("p{}", pid),
+            },
+        )
+    }
+
+    fn rows() -> Vec<((Order, User, Province), isize)> {
+        vec![(row(1, 1, 1), 1), (row(2, 2, 3), 2), (row(3, 1, 1), -1)]
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn to_polars_has_expected_shape_and_values() {
+        let df = to_polars(rows()).unwrap();
+        assert_eq!(df.shape(), (3, 6));
+        assert_eq!(
+            df.get_column_names(),
+            vec!["oid", "price", "uid", "pid", "province_name", "diff"]
+        );
+        let u64s = |name: &str| -> Vec<u64> {
+            df.column(name)
+                .unwrap()
+                .u64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect()
+        };
+        assert_eq!(u64s("oid"), vec![1, 2, 3]);
+        assert_eq!(u64s("price"), vec![10, 20, 30]);
+        assert_eq!(u64s("uid"), vec![1, 2, 1]);
+        assert_eq!(u64s("pid"), vec![1, 3, 1]);
+        let names: Vec<_> = df
+            .column("province_name")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(names, vec!["p1", "p3", "p1"]);
+        let diffs: Vec<_> = df
+            .column("diff")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(diffs, vec![1, 2, -1]);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn to_sqlite_writes_positive_rows() {
+        let path =
+            std::env::temp_dir().join(format!("dd_examples_export_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // 订单 4 插入之后又被撤回
+        let mut final_rows = rows();
+        final_rows.push((row(4, 2, 3), 1));
+        final_rows.push((row(4, 2, 3), -1));
+        assert_eq!(to_sqlite(&final_rows, &path, "joined").unwrap(), 3);
+
+        let conn = Connection::open(&path).unwrap();
+        let mut query = conn
+            .prepare("SELECT oid, price, uid, pid, province_name FROM joined ORDER BY oid")
+            .unwrap();
+        let stored: Vec<(i64, i64, i64, i64, String)> = query
+            .query_map([], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        drop(query);
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+
+        // multiplicity 为 2 的行写入两次, 撤回的行不出现
+        let expected = |oid| (oid, oid * 10, 2, 3, "p3".to_string());
+        assert_eq!(
+            stored,
+            vec![(1, 10, 1, 1, "p1".to_string()), expected(2), expected(2)]
+        );
+    }
+}
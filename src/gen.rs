@@ -0,0 +1,106 @@
+use crate::dataset::{Dataset, InputOp};
+use crate::delta_join::{Oid, Order, Pid, Province, Uid, User};
+
+/// 省份的数量
+pub const PROVINCES: u64 = 34;
+
+/// 简单的伪随机数生成器 (splitmix64), 相同的 seed 总是产生相同的序列, 方便复现
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    // 返回 [0, n) 之间的数, n 必须大于 0
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+// 生成 `scale` 个用户以及 `scale * 4` 个订单, 每个用户的 `padding` 长度为 `padding`。
+// 所有的外键都是有效的, 也就是说每个订单都能 join 到用户和省份
+pub fn generate(seed: u64, scale: u64, padding: usize) -> Dataset {
+    let mut rng = Rng::new(seed);
+    let provinces = (0..PROVINCES)
+        .map(|i| Province {
+            pid: Pid(i),
+            name: format!("province-{}", i),
+        })
+        .collect();
+    let users = (0..scale)
+        .map(|i| User {
+            uid: Uid(i),
+            pid: Pid(rng.below(PROVINCES)),
+            padding: "x".repeat(padding),
+        })
+        .collect();
+    let orders = (0..scale.max(1) * 4)
+        .map(|i| Order {
+            oid: Oid(i),
+            price: rng.below(1000) + 1,
+            uid: Uid(rng.below(scale.max(1))),
+        })
+        .collect();
+    Dataset {
+        orders,
+        users,
+        provinces,
+    }
+}
+
+// 给数据集中的每条数据分配一个 [0, max_time] 之间的插入时间, 相同的 seed 总是分配相同的时间。
+// 返回的更新按照时间排序 (时间相同时保持省份, 用户, 订单的顺序), 可以直接依次输入到 join 中
+pub fn with_timestamps(dataset: &Dataset, seed: u64, max_time: u64) -> Vec<InputOp> {
+    let mut rng = Rng::new(seed);
+    let mut time = || match max_time.checked_add(1) {
+        Some(n) => rng.below(n),
+        None => rng.next_u64(),
+    };
+    let mut ops = Vec::new();
+    for p in &dataset.provinces {
+        ops.push(InputOp::Province(p.clone(), time(), 1));
+    }
+    for u in &dataset.users {
+        ops.push(InputOp::User(u.clone(), time(), 1));
+    }
+    for o in &dataset.orders {
+        ops.push(InputOp::Order(o.clone(), time(), 1));
+    }
+    ops.sort_by_key(InputOp::time);
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_timestamps_is_stable_and_in_range() {
+        let dataset = generate(7, 50, 0);
+        let ops = with_timestamps(&dataset, 3, 9);
+        assert_eq!(ops, with_timestamps(&dataset, 3, 9));
+        assert_ne!(ops, with_timestamps(&dataset, 4, 9));
+
+        // 每条数据恰好出现一次, 时间在 [0, max_time] 之间并且不递减
+        assert_eq!(ops.len(), dataset.to_ops(0).len());
+        assert!(ops.iter().all(|op| op.time() <= 9));
+        assert!(ops.windows(2).all(|w| w[0].time() <= w[1].time()));
+        assert!(ops.iter().any(|op| op.time() > 0));
+
+        assert!(with_timestamps(&dataset, 3, 0)
+            .iter()
+            .all(|op| op.time() == 0));
+        // max_time + 1 溢出时也不会 panic
+        assert_eq!(with_timestamps(&dataset, 3, u64::MAX).len(), ops.len());
+    }
+}
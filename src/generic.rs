@@ -0,0 +1,211 @@
+use std::hash::Hash;
+
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::ArrangeByKey;
+use differential_dataflow::operators::Join;
+use differential_dataflow::{Collection, ExchangeData};
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::delta_join_chain;
+
+/// id 的类型, 例如 u64 或者 uuid
+pub trait Id: ExchangeData + Hash + Copy {}
+
+impl<T: ExchangeData + Hash + Copy> Id for T {}
+
+/// id 类型可以替换的订单
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Order<I> {
+    pub oid: I,
+    pub price: u64,
+    pub uid: I,
+}
+
+/// id 类型可以替换的用户
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct User<I> {
+    pub uid: I,
+    pub pid: I,
+}
+
+/// id 类型可以替换的省份
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Province<I> {
+    pub pid: I,
+    pub name: String,
+}
+
+/// 使用 uuid 作为 id 的订单, 需要开启 `uuid` feature
+#[cfg(feature = "uuid")]
+pub type UuidOrder = Order<uuid::Uuid>;
+/// 使用 uuid 作为 id 的用户, 需要开启 `uuid` feature
+#[cfg(feature = "uuid")]
+pub type UuidUser = User<uuid::Uuid>;
+/// 使用 uuid 作为 id 的省份, 需要开启 `uuid` feature
+#[cfg(feature = "uuid")]
+pub type UuidProvince = Province<uuid::Uuid>;
+
+// 与 `delta_join::regular_join` 相同, 但是 id 的类型是泛型
+pub fn regular_join<S, I>(
+    order: &Collection<S, Order<I>>,
+    user: &Collection<S, User<I>>,
+    province: &Collection<S, Province<I>>,
+) -> Collection<S, (Order<I>, User<I>, Province<I>)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+    I: Id,
+{
+    order
+        .map(|o| (o.uid, o))
+        .join_map(&user.map(|u| (u.uid, u)), |_, o, u| {
+            (u.pid, (o.clone(), u.clone()))
+        })
+        .join_map(&province.map(|p| (p.pid, p)), |_, (o, u), p| {
+            (o.clone(), u.clone(), p.clone())
+        })
+}
+
+// 与 `delta_join::delta_join` 相同, 但是 id 的类型是泛型, 优先级依然为 order < user < province
+pub fn delta_join<S, I>(
+    order: &Collection<S, Order<I>>,
+    user: &Collection<S, User<I>>,
+    province: &Collection<S, Province<I>>,
+) -> Collection<S, (Order<I>, User<I>, Province<I>)>
+where
+    S: Scope<Timestamp = u64>,
+    I: Id,
+{
+    let order_arrange = order.map(|o| (o.uid, o)).arrange_by_key();
+    let user_uid_arrange = user.map(|u| (u.uid, u)).arrange_by_key();
+    let user_pid_arrange = user.map(|u| (u.pid, u)).arrange_by_key();
+    let province_arrange = province.map(|p| (p.pid, p)).arrange_by_key();
+
+    delta_join_chain(
+        order,
+        user,
+        province,
+        order_arrange,
+        user_uid_arrange,
+        user_pid_arrange,
+        province_arrange,
+        |o: &Order<I>| o.uid,
+        |u: &User<I>| u.uid,
+        |u: &User<I>| u.pid,
+        |p: &Province<I>| p.pid,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::InputSession;
+
+    use super::*;
+
+    type Row<I> = (Order<I>, User<I>, Province<I>);
+
+    // 在同一个时刻插入所有数据, 第二个时刻撤回一个用户, 返回 consolidate 之后的输出。
+    // 数据中的 id 都是 u64, 通过 `id` 转换成需要的 id 类型
+    fn run<I, F>(id: fn(u64) -> I, join: F) -> Vec<(Row<I>, u64, isize)>
+    where
+        I: Id,
+        F: for<'a> Fn(
+                &Collection<crate::harness::HarnessScope<'a>, Order<I>>,
+                &Collection<crate::harness::HarnessScope<'a>, User<I>>,
+                &Collection<crate::harness::HarnessScope<'a>, Province<I>>,
+            ) -> Collection<crate::harness::HarnessScope<'a>, Row<I>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let mut o = InputSession::new();
+            let mut u = InputSession::new();
+            let mut p = InputSession::new();
+            let out = output.clone();
+            worker.dataflow(|scope| {
+                join(
+                    &o.to_collection(scope),
+                    &u.to_collection(scope),
+                    &p.to_collection(scope),
+                )
+                .inspect(move |x| out.borrow_mut().push(x.clone()));
+            });
+            for pid in 0..2 {
+                p.insert(Province {
+                    pid: id(pid),
+                    name: format!("p{}", pid),
+                });
+            }
+            for uid in 0..4 {
+                u.insert(User {
+                    uid: id(uid),
+                    pid: id(uid % 2),
+                });
+            }
+            for oid in 0..8 {
+                o.insert(Order {
+                    oid: id(oid),
+                    price: oid * 10,
+                    uid: id(oid % 4),
+                });
+            }
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            u.remove(User {
+                uid: id(1),
+                pid: id(1),
+            });
+            drop((o, u, p));
+            while worker.step() {}
+            let mut output = output.borrow().clone();
+            consolidate_updates(&mut output);
+            output
+        })
+    }
+
+    #[test]
+    fn delta_join_matches_regular_join() {
+        let expected = run(|id| id, |o, u, p| regular_join(o, u, p));
+        assert_eq!(expected.len(), 10);
+        assert_eq!(run(|id| id, |o, u, p| delta_join(o, u, p)), expected);
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn delta_join_with_uuid_ids() {
+        let uuid = |id: u64| uuid::Uuid::from_u128(id as u128);
+        let expected = run(uuid, |o, u, p| regular_join(o, u, p));
+        assert_eq!(expected.len(), 10);
+        assert_eq!(run(uuid, |o, u, p| delta_join(o, u, p)), expected);
+        // 与 u64 id 的结果一一对应
+        let mut converted: Vec<_> = run(|id| id, |o, u, p| regular_join(o, u, p))
+            .into_iter()
+            .map(|((o, u, p), t, r)| {
+                let order = Order {
+                    oid: uuid(o.oid),
+                    price: o.price,
+                    uid: uuid(o.uid),
+                };
+                let user = User {
+                    uid: uuid(u.uid),
+                    pid: uuid(u.pid),
+                };
+                let province = Province {
+                    pid: uuid(p.pid),
+                    name: p.name,
+                };
+                ((order, user, province), t, r)
+            })
+            .collect();
+        consolidate_updates(&mut converted);
+        assert_eq!(converted, expected);
+    }
+}
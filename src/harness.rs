@@ -0,0 +1,242 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use differential_dataflow::consolidation::consolidate_updates;
+use differential_dataflow::input::InputSession;
+use differential_dataflow::logging::DifferentialEvent;
+use differential_dataflow::Collection;
+use timely::communication::allocator::Thread;
+use timely::dataflow::scopes::Child;
+use timely::logging::{TimelyEvent, TimelyProgressEvent};
+use timely::worker::Worker;
+
+use crate::chaos::shuffle_updates;
+use crate::dataset::Update;
+use crate::delta_join::{Order, Province, User, Variant};
+
+/// 设置了这个环境变量后, `run` 会打印 timely 的日志, 方便排查 frontier 不推进的问题
+pub const LOG_ENV: &str = "DD_EXAMPLES_LOG";
+
+pub fn logging_enabled() -> bool {
+    std::env::var_os(LOG_ENV).is_some()
+}
+
+// 单 worker 运行 `variant`, 输入所有的更新之后关闭 input, 一直运行到结束。
+// 返回 join 输出的所有更新, 已经 consolidate 并且按照 (data, time) 排序。
+// 如果设置了环境变量 `DD_EXAMPLES_LOG`, 会打印 timely 的日志
+pub fn run(
+    variant: Variant,
+    order: Vec<Update<Order>>,
+    user: Vec<Update<User>>,
+    province: Vec<Update<Province>>,
+) -> Vec<Update<(Order, User, Province)>> {
+    run_with_logging(variant, order, user, province, logging_enabled())
+}
+
+// 与 `run` 相同, `logging` 为 true 时会把 timely 的算子事件, 进度消息以及每一步之后输出的 frontier 打印到 stderr
+pub fn run_with_logging(
+    variant: Variant,
+    order: Vec<Update<Order>>,
+    user: Vec<Update<User>>,
+    province: Vec<Update<Province>>,
+    logging: bool,
+) -> Vec<Update<(Order, User, Province)>> {
+    let mut output = execute(
+        move |o, u, p| variant.join(o, u, p),
+        order,
+        user,
+        province,
+        logging,
+    );
+    consolidate_updates(&mut output);
+    output
+}
+
+/// `run_join` 中 dataflow 的 scope
+pub type HarnessScope<'a> = Child<'a, Worker<Thread>, u64>;
+
+// 与 `run` 相同, 但是运行的是任意的 join 实现, 例如不在 `Variant` 中的 `delta_join_batched`:
+// ```ignore
+// run_join(|o, u, p| delta_join_batched(o, u, p), order, user, province)
+// ```
+pub fn run_join<F>(
+    join: F,
+    order: Vec<Update<Order>>,
+    user: Vec<Update<User>>,
+    province: Vec<Update<Province>>,
+) -> Vec<Update<(Order, User, Province)>>
+where
+    F: for<'a> Fn(
+            &Collection<HarnessScope<'a>, Order>,
+            &Collection<HarnessScope<'a>, User>,
+            &Collection<HarnessScope<'a>, Province>,
+        ) -> Collection<HarnessScope<'a>, (Order, User, Province)>
+        + Send
+        + Sync
+        + 'static,
+{
+    let mut output = execute(join, order, user, province, logging_enabled());
+    consolidate_updates(&mut output);
+    output
+}
+
+// 所有 `run_*` 共用的部分: 单 worker 运行 `join`, 输入所有的更新之后关闭 input, 一直运行到结束,
+// 返回 join 按照产生顺序输出的原始更新
+fn execute<F>(
+    join: F,
+    order: Vec<Update<Order>>,
+    user: Vec<Update<User>>,
+    province: Vec<Update<Province>>,
+    logging: bool,
+) -> Vec<Update<(Order, User, Province)>>
+where
+    F: for<'a> Fn(
+            &Collection<HarnessScope<'a>, Order>,
+            &Collection<HarnessScope<'a>, User>,
+            &Collection<HarnessScope<'a>, Province>,
+        ) -> Collection<HarnessScope<'a>, (Order, User, Province)>
+        + Send
+        + Sync
+        + 'static,
+{
+    timely::execute_directly(move |worker| {
+        if logging {
+            let index = worker.index();
+            worker
+                .log_register()
+                .insert::<TimelyEvent, _>("timely", move |_, data| {
+                    for (time, _, event) in data.iter() {
+                        eprintln!("[worker {}] {:?} timely: {:?}", index, time, event);
+                    }
+                });
+            worker.log_register().insert::<TimelyProgressEvent, _>(
+                "timely/progress",
+                move |_, data| {
+                    for (time, _, event) in data.iter() {
+                        eprintln!("[worker {}] {:?} progress: {:?}", index, time, event);
+                    }
+                },
+            );
+            worker.log_register().insert::<DifferentialEvent, _>(
+                "differential/arrange",
+                move |_, data| {
+                    for (time, _, event) in data.iter() {
+                        eprintln!("[worker {}] {:?} differential: {:?}", index, time, event);
+                    }
+                },
+            );
+        }
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut order_input = InputSession::new();
+        let mut user_input = InputSession::new();
+        let mut province_input = InputSession::new();
+
+        let sink = output.clone();
+        let probe = worker.dataflow(|scope| {
+            join(
+                &order_input.to_collection(scope),
+                &user_input.to_collection(scope),
+                &province_input.to_collection(scope),
+            )
+            .inspect(move |x| sink.borrow_mut().push(x.clone()))
+            .probe()
+        });
+
+        order
+            .into_iter()
+            .for_each(|(d, t, r)| order_input.update_at(d, t, r));
+        user.into_iter()
+            .for_each(|(d, t, r)| user_input.update_at(d, t, r));
+        province
+            .into_iter()
+            .for_each(|(d, t, r)| province_input.update_at(d, t, r));
+        order_input.close();
+        user_input.close();
+        province_input.close();
+
+        while !probe.done() {
+            worker.step();
+            if logging {
+                probe.with_frontier(|f| eprintln!("output frontier: {:?}", f.to_vec()));
+            }
+        }
+        output.take()
+    })
+}
+
+// 与 `run` 相同, 但是返回的是 join 按照产生顺序输出的原始更新, 不做 consolidate, 用于比较完整输出的 golden test。
+// 为了保证每次运行的输出 (包括顺序) 完全相同: 只使用一个 worker (`execute_directly` 中算子的调度顺序是确定的),
+// 并且在输入之前先把每个 input 的更新按照 (time, data, diff) 排序, 使输出与调用方给出更新的顺序无关。
+// 排序之后再用固定的 `seed` 通过 `chaos::shuffle_updates` 决定更新进入 input 的顺序 (同一条记录的更新保持时间顺序),
+// seed 相同时输出完全相同, 换一个 seed 可以得到另一种确定的到达顺序, consolidate 之后的结果不变
+pub fn run_deterministic(
+    variant: Variant,
+    seed: u64,
+    mut order: Vec<Update<Order>>,
+    mut user: Vec<Update<User>>,
+    mut province: Vec<Update<Province>>,
+) -> Vec<Update<(Order, User, Province)>> {
+    order.sort_by(|a, b| (a.1, &a.0, a.2).cmp(&(b.1, &b.0, b.2)));
+    user.sort_by(|a, b| (a.1, &a.0, a.2).cmp(&(b.1, &b.0, b.2)));
+    province.sort_by(|a, b| (a.1, &a.0, a.2).cmp(&(b.1, &b.0, b.2)));
+    let order = shuffle_updates(seed, order, |o| o.oid);
+    let user = shuffle_updates(seed, user, |u| u.uid);
+    let province = shuffle_updates(seed, province, |p| p.pid);
+
+    execute(
+        move |o, u, p| variant.join(o, u, p),
+        order,
+        user,
+        province,
+        false,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::split_ops;
+    use crate::gen::{generate, with_timestamps};
+
+    #[allow(clippy::type_complexity)]
+    fn updates() -> (Vec<Update<Order>>, Vec<Update<User>>, Vec<Update<Province>>) {
+        split_ops(with_timestamps(&generate(11, 30, 0), 11, 5))
+    }
+
+    #[test]
+    fn run_deterministic_is_reproducible() {
+        for variant in Variant::ALL {
+            let (o, u, p) = updates();
+            let first = run_deterministic(variant, 42, o, u, p);
+            // 调用方给出更新的顺序不影响输出
+            let (mut o, mut u, mut p) = updates();
+            o.reverse();
+            u.reverse();
+            p.reverse();
+            let second = run_deterministic(variant, 42, o, u, p);
+            assert_eq!(first, second, "{}", variant.name());
+
+            let mut consolidated = first;
+            consolidate_updates(&mut consolidated);
+            let (o, u, p) = updates();
+            assert_eq!(consolidated, run(variant, o, u, p), "{}", variant.name());
+        }
+    }
+
+    #[test]
+    fn run_with_logging_completes() {
+        for variant in Variant::ALL {
+            let (o, u, p) = updates();
+            let logged = run_with_logging(variant, o, u, p, true);
+            assert!(!logged.is_empty(), "{}", variant.name());
+            let (o, u, p) = updates();
+            assert_eq!(
+                logged,
+                run_with_logging(variant, o, u, p, false),
+                "{}",
+                variant.name()
+            );
+        }
+    }
+}
@@ -0,0 +1,44 @@
+pub mod adaptive;
+pub mod aggregate;
+pub mod bitemporal;
+pub mod builder;
+pub mod cdc;
+pub mod chaos;
+pub mod common_key;
+pub mod config;
+pub mod dataset;
+pub mod debug;
+pub mod delta_join;
+pub mod demo;
+pub mod enrich;
+pub mod epoch;
+pub mod export;
+pub mod gen;
+pub mod generic;
+pub mod harness;
+pub mod load;
+pub mod metrics;
+pub mod migrate;
+pub mod nested;
+pub mod ops;
+pub mod oracle;
+pub mod partition;
+pub mod peek;
+pub mod plan;
+pub mod prefilter;
+pub mod prefix;
+#[cfg(feature = "profile")]
+pub mod profile;
+pub mod query;
+pub mod quick;
+pub mod recover;
+pub mod relation;
+pub mod replay;
+pub mod safe;
+pub mod sink;
+pub mod snapshot;
+pub mod source;
+pub mod time;
+pub mod upsert;
+pub mod util;
+pub mod validate;
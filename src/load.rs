@@ -0,0 +1,454 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::{FromStr, Utf8Error};
+
+use memmap2::Mmap;
+
+use crate::dataset::{Dataset, InputOp};
+use crate::delta_join::{Oid, Order, Pid, Province, Uid, User};
+
+/// 解析错误, `line` 从 1 开始
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Error for ParseError {}
+
+/// 加载文件时的错误
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(ParseError),
+    Bincode(bincode::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "io error: {}", e),
+            LoadError::Parse(e) => write!(f, "parse error: {}", e),
+            LoadError::Bincode(e) => write!(f, "bincode error: {}", e),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(e: ParseError) -> Self {
+        LoadError::Parse(e)
+    }
+}
+
+impl From<bincode::Error> for LoadError {
+    fn from(e: bincode::Error) -> Self {
+        LoadError::Bincode(e)
+    }
+}
+
+pub const ORDER_HEADER: &str = "oid,price,uid";
+pub const USER_HEADER: &str = "uid,pid,padding";
+pub const PROVINCE_HEADER: &str = "pid,name";
+
+// 一行中的字段, 字段之间用 `,` 分隔, 不支持引号, 所以最后一个字段 (name/padding) 可以包含 `,`
+struct Fields<'a> {
+    line: usize,
+    rest: Option<&'a str>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(line: usize, s: &'a str) -> Self {
+        Fields {
+            line,
+            rest: Some(s),
+        }
+    }
+
+    fn error(&self, reason: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            reason: reason.into(),
+        }
+    }
+
+    fn next_str(&mut self, name: &str) -> Result<&'a str, ParseError> {
+        let rest = self
+            .rest
+            .ok_or_else(|| self.error(format!("missing field `{}`", name)))?;
+        match rest.split_once(',') {
+            Some((field, rest)) => {
+                self.rest = Some(rest);
+                Ok(field)
+            }
+            None => {
+                self.rest = None;
+                Ok(rest)
+            }
+        }
+    }
+
+    fn next<T: FromStr>(&mut self, name: &str) -> Result<T, ParseError> {
+        let field = self.next_str(name)?;
+        field
+            .trim()
+            .parse()
+            .map_err(|_| self.error(format!("invalid `{}`: {:?}", name, field)))
+    }
+
+    // 剩下的所有内容作为最后一个字段
+    fn last(&mut self, name: &str) -> Result<&'a str, ParseError> {
+        let rest = self
+            .rest
+            .take()
+            .ok_or_else(|| self.error(format!("missing field `{}`", name)))?;
+        Ok(rest)
+    }
+
+    fn end(&self) -> Result<(), ParseError> {
+        match self.rest {
+            None => Ok(()),
+            Some(rest) => Err(self.error(format!("unexpected trailing fields: {:?}", rest))),
+        }
+    }
+}
+
+fn parse_order(fields: &mut Fields) -> Result<Order, ParseError> {
+    let order = Order {
+        oid: Oid(fields.next("oid")?),
+        price: fields.next("price")?,
+        uid: Uid(fields.next("uid")?),
+    };
+    fields.end()?;
+    Ok(order)
+}
+
+fn parse_user(fields: &mut Fields) -> Result<User, ParseError> {
+    Ok(User {
+        uid: Uid(fields.next("uid")?),
+        pid: Pid(fields.next("pid")?),
+        padding: fields.last("padding")?.to_string(),
+    })
+}
+
+fn parse_province(fields: &mut Fields) -> Result<Province, ParseError> {
+    Ok(Province {
+        pid: Pid(fields.next("pid")?),
+        name: fields.last("name")?.to_string(),
+    })
+}
+
+// 遍历需要解析的行, 跳过空行以及以 `#` 开头的注释, 返回 (行号, 内容)
+pub(crate) fn lines(input: &str) -> impl Iterator<Item = (usize, &str)> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim_end_matches('\r')))
+        .filter(|(_, l)| !l.trim().is_empty() && !l.starts_with('#'))
+}
+
+// 检查第一行是否为表头
+fn check_header<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    header: &str,
+) -> Result<(), ParseError> {
+    match lines.next() {
+        Some((_, h)) if h.trim() == header => Ok(()),
+        Some((line, h)) => Err(ParseError {
+            line,
+            reason: format!("expected header {:?}, found {:?}", header, h),
+        }),
+        None => Err(ParseError {
+            line: 1,
+            reason: format!("missing header {:?}", header),
+        }),
+    }
+}
+
+fn parse_csv<T>(
+    input: &str,
+    header: &str,
+    parse: impl Fn(&mut Fields) -> Result<T, ParseError>,
+) -> Result<Vec<T>, ParseError> {
+    let mut lines = lines(input);
+    check_header(&mut lines, header)?;
+    lines
+        .map(|(line, l)| parse(&mut Fields::new(line, l)))
+        .collect()
+}
+
+fn utf8_error(input: &[u8], e: Utf8Error) -> ParseError {
+    let valid = &input[..e.valid_up_to()];
+    ParseError {
+        line: valid.iter().filter(|b| **b == b'\n').count() + 1,
+        reason: format!("invalid utf-8 at byte {}", e.valid_up_to()),
+    }
+}
+
+// 把原始的字节解码成文本, 不是合法的 UTF-8 时返回第一个非法字节所在的行。
+// 所有从文件加载的函数都先经过这里, 所以非法的编码与其他格式错误一样报告为 `ParseError`
+pub fn decode_utf8(input: &[u8]) -> Result<&str, ParseError> {
+    std::str::from_utf8(input).map_err(|e| utf8_error(input, e))
+}
+
+// 读取整个文件并通过 `decode_utf8` 解码成文本
+fn read_utf8(path: impl AsRef<Path>) -> Result<String, LoadError> {
+    let bytes = fs::read(path)?;
+    Ok(decode_utf8(&bytes)?.to_string())
+}
+
+// 解析订单的 CSV, 格式为 `oid,price,uid`, 第一行必须是表头
+pub fn parse_orders(input: &str) -> Result<Vec<Order>, ParseError> {
+    parse_csv(input, ORDER_HEADER, parse_order)
+}
+
+// 解析用户的 CSV, 格式为 `uid,pid,padding`, 第一行必须是表头
+pub fn parse_users(input: &str) -> Result<Vec<User>, ParseError> {
+    parse_csv(input, USER_HEADER, parse_user)
+}
+
+// 解析省份的 CSV, 格式为 `pid,name`, 第一行必须是表头
+pub fn parse_provinces(input: &str) -> Result<Vec<Province>, ParseError> {
+    parse_csv(input, PROVINCE_HEADER, parse_province)
+}
+
+// 从目录中加载数据集, 目录中需要包含 `orders.csv`, `users.csv`, `provinces.csv`
+pub fn load_csv_dir(dir: impl AsRef<Path>) -> Result<Dataset, LoadError> {
+    let dir = dir.as_ref();
+    Ok(Dataset {
+        orders: parse_orders(&read_utf8(dir.join("orders.csv"))?)?,
+        users: parse_users(&read_utf8(dir.join("users.csv"))?)?,
+        provinces: parse_provinces(&read_utf8(dir.join("provinces.csv"))?)?,
+    })
+}
+
+// 解析一行更新, 格式为 `time,diff,relation,fields...`, 例如 `3,-1,order,1,100,2`,
+// `relation` 为 order/user/province, 后面的字段与对应的 CSV 格式相同
+pub(crate) fn parse_diff_line(line: usize, l: &str) -> Result<InputOp, ParseError> {
+    let mut fields = Fields::new(line, l);
+    let time: u64 = fields.next("time")?;
+    let diff: isize = fields.next("diff")?;
+    match fields.next_str("relation")?.trim() {
+        "order" => Ok(InputOp::Order(parse_order(&mut fields)?, time, diff)),
+        "user" => Ok(InputOp::User(parse_user(&mut fields)?, time, diff)),
+        "province" => Ok(InputOp::Province(parse_province(&mut fields)?, time, diff)),
+        other => Err(fields.error(format!("unknown relation {:?}", other))),
+    }
+}
+
+// 解析更新流, 每行一个更新, 参考 `parse_diff_line`
+pub fn parse_diffs(input: &str) -> Result<Vec<InputOp>, ParseError> {
+    lines(input)
+        .map(|(line, l)| parse_diff_line(line, l))
+        .collect()
+}
+
+pub fn read_diffs(path: impl AsRef<Path>) -> Result<Vec<InputOp>, LoadError> {
+    Ok(parse_diffs(&read_utf8(path)?)?)
+}
+
+// 以 bincode 格式保存数据集, 之后可以用 `from_bincode_mmap` 快速加载
+pub fn save_bincode(path: impl AsRef<Path>, dataset: &Dataset) -> Result<(), LoadError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    bincode::serialize_into(&mut writer, dataset)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// 通过 mmap 加载 `save_bincode` 保存的数据集。省去了把文件读入一个中间 buffer 的拷贝, 文件的页由操作系统在
+// 反序列化读到时换入; 但是 `bincode::deserialize` 会一次性读完整个文件, 构造出完整的 `Dataset`, 返回时所有数据都已经
+// 在内存中了, 并不是惰性加载。主要的收益是不需要像 CSV 那样逐个字段解析文本, 参考 `benches/load.rs`。
+// 反序列化期间文件不能被修改, 否则结果是未定义的
+pub fn from_bincode_mmap(path: impl AsRef<Path>) -> Result<Dataset, LoadError> {
+    let file = File::open(path)?;
+    // SAFETY: 只在反序列化期间读取映射的内存, 要求调用方保证此时文件不会被其他进程修改
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok(bincode::deserialize(&mmap)?)
+}
+
+/// 容错的加载配置: 无法解析的行不会中断加载, 而是连同原因一起写入 `dead_letter`,
+/// 每行的格式为 `来源:行号: 原因: 原始内容`。表头错误仍然会中断加载, 因为这说明整个文件的格式不对
+pub struct LoaderConfig<W: Write> {
+    pub dead_letter: W,
+}
+
+impl<W: Write> LoaderConfig<W> {
+    pub fn new(dead_letter: W) -> Self {
+        LoaderConfig { dead_letter }
+    }
+
+    fn collect<'a, T>(
+        &mut self,
+        source: &str,
+        lines: impl Iterator<Item = (usize, &'a str)>,
+        parse: impl Fn(usize, &'a str) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, LoadError> {
+        let mut rows = Vec::new();
+        for (line, l) in lines {
+            match parse(line, l) {
+                Ok(row) => rows.push(row),
+                Err(e) => writeln!(self.dead_letter, "{}:{}: {}: {}", source, line, e.reason, l)?,
+            }
+        }
+        Ok(rows)
+    }
+
+    fn csv<T>(
+        &mut self,
+        source: &str,
+        input: &str,
+        header: &str,
+        parse: impl Fn(&mut Fields) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, LoadError> {
+        let mut lines = lines(input);
+        check_header(&mut lines, header)?;
+        self.collect(source, lines, |line, l| parse(&mut Fields::new(line, l)))
+    }
+
+    // 与 `parse_orders` 相同, 只是无法解析的行会被写入 dead letter
+    pub fn parse_orders(&mut self, input: &str) -> Result<Vec<Order>, LoadError> {
+        self.csv("orders", input, ORDER_HEADER, parse_order)
+    }
+
+    // 与 `parse_users` 相同, 只是无法解析的行会被写入 dead letter
+    pub fn parse_users(&mut self, input: &str) -> Result<Vec<User>, LoadError> {
+        self.csv("users", input, USER_HEADER, parse_user)
+    }
+
+    // 与 `parse_provinces` 相同, 只是无法解析的行会被写入 dead letter
+    pub fn parse_provinces(&mut self, input: &str) -> Result<Vec<Province>, LoadError> {
+        self.csv("provinces", input, PROVINCE_HEADER, parse_province)
+    }
+
+    // 与 `parse_diffs` 相同, 只是无法解析的行会被写入 dead letter
+    pub fn parse_diffs(&mut self, input: &str) -> Result<Vec<InputOp>, LoadError> {
+        self.collect("diffs", lines(input), parse_diff_line)
+    }
+
+    // 与 `load_csv_dir` 相同, 只是无法解析的行会被写入 dead letter, 来源为对应的文件名
+    pub fn load_csv_dir(&mut self, dir: impl AsRef<Path>) -> Result<Dataset, LoadError> {
+        let dir = dir.as_ref();
+        Ok(Dataset {
+            orders: self.csv(
+                "orders.csv",
+                &read_utf8(dir.join("orders.csv"))?,
+                ORDER_HEADER,
+                parse_order,
+            )?,
+            users: self.csv(
+                "users.csv",
+                &read_utf8(dir.join("users.csv"))?,
+                USER_HEADER,
+                parse_user,
+            )?,
+            provinces: self.csv(
+                "provinces.csv",
+                &read_utf8(dir.join("provinces.csv"))?,
+                PROVINCE_HEADER,
+                parse_province,
+            )?,
+        })
+    }
+
+    // 与 `read_diffs` 相同, 只是无法解析的行会被写入 dead letter
+    pub fn read_diffs(&mut self, path: impl AsRef<Path>) -> Result<Vec<InputOp>, LoadError> {
+        self.parse_diffs(&read_utf8(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_utf8_reports_line() {
+        let input = b"oid,price,uid\n1,10,1\n2,\xff,1\n";
+        assert_eq!(
+            decode_utf8(input),
+            Err(ParseError {
+                line: 3,
+                reason: "invalid utf-8 at byte 23".to_string(),
+            })
+        );
+        let input = b"oid,price,uid\n1,10,1\n";
+        assert_eq!(
+            parse_orders(decode_utf8(input).unwrap()),
+            Ok(vec![Order {
+                oid: Oid(1),
+                price: 10,
+                uid: Uid(1),
+            }])
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_file_reports_line() {
+        let path =
+            std::env::temp_dir().join(format!("dd_examples_load_{}.csv", std::process::id()));
+        fs::write(&path, b"0,1,order,1,10,1\n0,1,order,\xff,10,1\n").unwrap();
+        let result = read_diffs(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(LoadError::Parse(e)) => assert_eq!(
+                e,
+                ParseError {
+                    line: 2,
+                    reason: "invalid utf-8 at byte 27".to_string(),
+                }
+            ),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_rows_go_to_dead_letter() {
+        let order = |oid, price, uid| Order {
+            oid: Oid(oid),
+            price,
+            uid: Uid(uid),
+        };
+        let mut config = LoaderConfig::new(Vec::new());
+        let orders = config
+            .parse_orders("oid,price,uid\n1,10,1\nx,10,1\n3,30,2\n4,40\n")
+            .unwrap();
+        assert_eq!(orders, vec![order(1, 10, 1), order(3, 30, 2)]);
+        let diffs = config
+            .parse_diffs("0,1,order,1,10,1\n0,1,bogus,1\n1,-1,order,1,10,1\n")
+            .unwrap();
+        assert_eq!(
+            diffs,
+            vec![
+                InputOp::Order(order(1, 10, 1), 0, 1),
+                InputOp::Order(order(1, 10, 1), 1, -1),
+            ]
+        );
+
+        let dead_letter = String::from_utf8(config.dead_letter).unwrap();
+        let dead: Vec<_> = dead_letter.lines().collect();
+        assert_eq!(dead.len(), 3, "{}", dead_letter);
+        assert!(dead[0].starts_with("orders:3: ") && dead[0].ends_with(": x,10,1"));
+        assert!(dead[1].starts_with("orders:5: ") && dead[1].ends_with(": 4,40"));
+        assert!(dead[2].starts_with("diffs:2: ") && dead[2].ends_with(": 0,1,bogus,1"));
+
+        // 表头错误说明整个文件的格式不对, 仍然会中断加载
+        assert!(LoaderConfig::new(Vec::new())
+            .parse_orders("id,price,uid\n1,10,1\n")
+            .is_err());
+    }
+}
@@ -1,5 +1,3 @@
-pub mod delta_join;
-
 fn main() {
     println!("Hello, world!");
 }
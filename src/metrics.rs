@@ -0,0 +1,564 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::Count;
+use differential_dataflow::trace::cursor::Cursor;
+use differential_dataflow::trace::TraceReader;
+use differential_dataflow::{AsCollection, Collection, ExchangeData};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Map, Operator};
+use timely::dataflow::{Scope, Stream};
+
+use crate::delta_join::{DeltaTraces, Oid, Order, Pid, Province, Trace, Uid, User};
+use crate::util::OrdF64;
+
+/// 对象在堆上占用的空间, 用来粗略估计 arrangement 的内存占用
+pub trait HeapSize {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for u64 {}
+impl HeapSize for Uid {}
+impl HeapSize for Oid {}
+impl HeapSize for Pid {}
+impl HeapSize for Order {}
+
+impl HeapSize for User {
+    fn heap_size(&self) -> usize {
+        self.padding.capacity()
+    }
+}
+
+impl HeapSize for Province {
+    fn heap_size(&self) -> usize {
+        self.name.capacity()
+    }
+}
+
+/// arrangement 的统计信息
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TraceStats {
+    /// (key, value, time, diff) 的数量
+    pub tuples: usize,
+    /// 估计的字节数, 包括 tuple 本身以及 key, value 在堆上的空间
+    pub bytes: usize,
+}
+
+// 遍历 trace 中当前所有的 tuple, 统计数量和内存占用
+pub fn trace_stats<K, V>(trace: &mut Trace<K, V>) -> TraceStats
+where
+    K: ExchangeData + HeapSize,
+    V: ExchangeData + HeapSize,
+{
+    let mut stats = TraceStats::default();
+    let (mut cursor, storage) = trace.cursor();
+    while cursor.key_valid(&storage) {
+        let key = cursor.key(&storage);
+        while cursor.val_valid(&storage) {
+            let val = cursor.val(&storage);
+            let size = size_of::<((K, V), u64, isize)>() + key.heap_size() + val.heap_size();
+            cursor.map_times(&storage, |_, _| {
+                stats.tuples += 1;
+                stats.bytes += size;
+            });
+            cursor.step_val(&storage);
+        }
+        cursor.step_key(&storage);
+    }
+    stats
+}
+
+// 每个 worker 产生的输出行数 (multiplicity 之和), 用来发现数据倾斜。
+// 行数按照产生它的 worker 统计, 所以需要在数据被重新分区之前调用, 例如直接作用于 join 的输出。
+// 如果某个 worker 明显偏多, 可以使用 `partition::delta_join_with_hash` 自定义 uid 的分区方式来重新平衡
+pub fn per_worker_row_counts<S, D>(collection: &Collection<S, D>) -> Collection<S, (usize, isize)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+    D: ExchangeData,
+{
+    let index = collection.scope().index();
+    collection.map(move |_| index).count()
+}
+
+// `DeltaTraces` 中每个 trace 的统计信息
+pub fn delta_trace_stats<P>(traces: &mut DeltaTraces<P>) -> Vec<(&'static str, TraceStats)>
+where
+    P: ExchangeData + HeapSize,
+{
+    vec![
+        ("order_by_uid", trace_stats(&mut traces.order_by_uid)),
+        ("user_by_uid", trace_stats(&mut traces.user_by_uid)),
+        ("user_by_pid", trace_stats(&mut traces.user_by_pid)),
+        ("province_by_pid", trace_stats(&mut traces.province_by_pid)),
+    ]
+}
+
+// 检查所有的 trace 中都没有任何 tuple, 用于在所有数据都被撤回并且运行结束之后发现 arrangement 的泄漏。
+// 只通过 cursor 读取 batch, 不会修改 trace 的 compaction。被撤回的数据只有在 compaction 之后才会相互抵消,
+// 而 `traces` 这些 handle 本身也会阻止 compaction, 所以调用方应该在拿到 handle 之后立刻把它们的 logical/physical
+// compaction 设为空的 antichain (不需要保留任何历史), 否则这里看到的是被这些 handle 保留下来的历史
+pub fn assert_traces_empty<P>(traces: &mut DeltaTraces<P>)
+where
+    P: ExchangeData + HeapSize,
+{
+    for (name, stats) in delta_trace_stats(traces) {
+        assert_eq!(stats.tuples, 0, "trace {} is not empty: {:?}", name, stats);
+    }
+}
+
+// 记录 collection 在当前 worker 上的 frontier, None 表示已经结束
+fn track_frontier<S, D>(collection: &Collection<S, D>) -> Arc<Mutex<Option<u64>>>
+where
+    S: Scope<Timestamp = u64>,
+    D: ExchangeData,
+{
+    let cell = Arc::new(Mutex::new(Some(0)));
+    let shared = cell.clone();
+    let _: Stream<S, ()> =
+        collection
+            .inner
+            .unary_frontier(Pipeline, "TrackFrontier", move |_, _| {
+                move |input, _output| {
+                    input.for_each(|_, _| {});
+                    *shared.lock().unwrap() = input.frontier().frontier().first().copied();
+                }
+            });
+    cell
+}
+
+/// `stall_watchdog` 的句柄, drop 时停止后台线程
+pub struct Watchdog {
+    stop: Arc<AtomicBool>,
+    stalls: Arc<Mutex<Vec<u64>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    // 到目前为止检测到的停滞的时间戳
+    pub fn stalls(&self) -> Vec<u64> {
+        self.stalls.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// 在后台线程中比较 `input` 和 `join_output` 在当前 worker 上的 frontier: 如果 input 已经越过了某个时刻,
+// 而 join 的输出在 `timeout` 的时间内一直停在这个时刻, 就打印一条警告并记录这个时刻。
+// 用来诊断自定义 half_join 链中的死锁, 例如 comparator 写错导致某个时刻的更新永远无法输出。
+// 每个停滞的时刻只报告一次
+pub fn stall_watchdog<S, D1, D2>(
+    input: &Collection<S, D1>,
+    join_output: &Collection<S, D2>,
+    timeout: Duration,
+) -> Watchdog
+where
+    S: Scope<Timestamp = u64>,
+    D1: ExchangeData,
+    D2: ExchangeData,
+{
+    let input_frontier = track_frontier(input);
+    let output_frontier = track_frontier(join_output);
+    let index = input.scope().index();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stalls = Arc::new(Mutex::new(Vec::new()));
+
+    let thread = {
+        let stop = stop.clone();
+        let stalls = stalls.clone();
+        thread::spawn(move || {
+            let poll = (timeout / 4).max(Duration::from_millis(1));
+            let mut last = None;
+            let mut since = Instant::now();
+            let mut reported = None;
+            while !stop.load(Ordering::SeqCst) {
+                let input = *input_frontier.lock().unwrap();
+                let output = *output_frontier.lock().unwrap();
+                if output != last {
+                    last = output;
+                    since = Instant::now();
+                }
+                if let Some(stalled) = output {
+                    let behind = input.is_none_or(|i| stalled < i);
+                    if behind && since.elapsed() >= timeout && reported != Some(stalled) {
+                        eprintln!(
+                            "worker {}: join output stalled at time {} for {:?} (input frontier {:?})",
+                            index,
+                            stalled,
+                            since.elapsed(),
+                            input
+                        );
+                        stalls.lock().unwrap().push(stalled);
+                        reported = Some(stalled);
+                    }
+                }
+                thread::sleep(poll);
+            }
+        })
+    };
+    Watchdog {
+        stop,
+        stalls,
+        thread: Some(thread),
+    }
+}
+
+// join 输出在最近 `window` 个时刻内的更新速率: 每个时刻 t 输出窗口 (t - window, t] 内所有更新的 |diff| 之和
+// 除以 `window`, 用来发现输出的突增。每个更新在时刻 t 计入窗口, 并在 t + window 时自动移出,
+// 所以突增过去之后, 只要 input 的 frontier 继续推进, 速率就会逐渐回落; 窗口内没有任何更新时不输出
+pub fn diff_rate<S, D>(join_output: &Collection<S, D>, window: u64) -> Collection<S, OrdF64>
+where
+    S: Scope<Timestamp = u64>,
+    D: ExchangeData,
+{
+    assert!(window > 0, "window must be positive");
+    join_output
+        .inner
+        .flat_map(move |(_, t, r)| {
+            let r = r.abs();
+            [((), t, r), ((), t.saturating_add(window), -r)]
+        })
+        .as_collection()
+        .count()
+        .map(move |(_, total)| OrdF64(total as f64 / window as f64))
+}
+
+/// `record_latency` 记录的延迟样本, 在同一个进程的所有 worker 之间共享: 在 `execute` 之前创建, 每个 worker 持有一份 clone
+#[derive(Clone, Debug, Default)]
+pub struct LatencySamples {
+    state: Arc<Mutex<LatencyState>>,
+}
+
+#[derive(Debug, Default)]
+struct LatencyState {
+    // (输出它的 worker, 延迟)
+    samples: Vec<(usize, Duration)>,
+    // 每个时间戳第一次出现在任意一个 worker 的 input 上的时间
+    first_seen: BTreeMap<u64, Instant>,
+    // 每个 worker 的输出 frontier, 已经结束的 worker 为 u64::MAX
+    frontiers: HashMap<usize, u64>,
+}
+
+impl LatencySamples {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 所有的样本 (输出它的 worker, 延迟)
+    pub fn samples(&self) -> Vec<(usize, Duration)> {
+        self.state.lock().unwrap().samples.clone()
+    }
+}
+
+// 记录 join 输出的延迟: 每个输出的更新从它的时间戳第一次出现在任意一个 worker 的 `input` 上开始,
+// 到它从 `join_output` 输出为止的时间, 样本按照输出它的 worker 记录到 `samples` 中。
+// join 中间会按照 key 重新分区, 输出某个时间戳的 worker 不一定在自己的 input 上见过它, 所以第一次出现的时间
+// 在所有 worker 之间共享; 也因此只支持同一个进程中的 worker (`Instant` 无法跨进程比较)。
+// 当所有 worker 的输出 frontier 都越过某个时间戳之后, 它不会再有输出, 对应的记录会被删除, 内存不会一直增长
+pub fn record_latency<S, D1, D2>(
+    input: &Collection<S, D1>,
+    join_output: &Collection<S, D2>,
+    samples: LatencySamples,
+) where
+    S: Scope<Timestamp = u64>,
+    D1: ExchangeData,
+    D2: ExchangeData,
+{
+    let index = input.scope().index();
+    let peers = input.scope().peers();
+
+    let first_seen = samples.clone();
+    let _: Stream<S, ()> = input
+        .inner
+        .unary_frontier(Pipeline, "LatencyInput", move |_, _| {
+            move |input, _output| {
+                input.for_each(|_, data| {
+                    let now = Instant::now();
+                    let mut state = first_seen.state.lock().unwrap();
+                    for (_, t, _) in data.iter() {
+                        state.first_seen.entry(*t).or_insert(now);
+                    }
+                });
+            }
+        });
+    let _: Stream<S, ()> =
+        join_output
+            .inner
+            .unary_frontier(Pipeline, "LatencyOutput", move |_, _| {
+                move |input, _output| {
+                    input.for_each(|_, data| {
+                        let now = Instant::now();
+                        let mut state = samples.state.lock().unwrap();
+                        for (_, t, _) in data.iter() {
+                            if let Some(start) = state.first_seen.get(t).copied() {
+                                state.samples.push((index, now - start));
+                            }
+                        }
+                    });
+
+                    let frontier = input
+                        .frontier()
+                        .frontier()
+                        .first()
+                        .copied()
+                        .unwrap_or(u64::MAX);
+                    let mut state = samples.state.lock().unwrap();
+                    state.frontiers.insert(index, frontier);
+                    if state.frontiers.len() == peers {
+                        let done = *state.frontiers.values().min().unwrap();
+                        state.first_seen = state.first_seen.split_off(&done);
+                    }
+                }
+            });
+}
+
+// 运行结束之后, 根据 `record_latency` 记录的样本计算每个 worker 输出延迟的 p50/p95/p99 (毫秒),
+// 用来发现比其他 worker 慢的 worker。没有样本的 worker 不会出现在结果中。
+// 参数是样本而不是 join 的输出: 输出中只有逻辑时间戳, 没有这个时间戳到达 input 的时刻,
+// 延迟只能在构建 dataflow 时由 `record_latency` 同时观察 input 和输出得到
+pub fn latency_percentiles_per_worker(samples: &LatencySamples) -> HashMap<usize, [f64; 3]> {
+    let mut per_worker: HashMap<usize, Vec<Duration>> = HashMap::new();
+    for (worker, latency) in samples.state.lock().unwrap().samples.iter() {
+        per_worker.entry(*worker).or_default().push(*latency);
+    }
+    per_worker
+        .into_iter()
+        .map(|(worker, mut latencies)| {
+            latencies.sort();
+            // nearest-rank
+            let percentile = |p: f64| {
+                let rank = ((p / 100.0) * latencies.len() as f64).ceil() as usize;
+                latencies[rank.clamp(1, latencies.len()) - 1].as_secs_f64() * 1000.0
+            };
+            (
+                worker,
+                [percentile(50.0), percentile(95.0), percentile(99.0)],
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use differential_dataflow::input::Input;
+    use timely::progress::frontier::AntichainRef;
+
+    use super::*;
+    use crate::delta_join::{delta_join_arranged, DeltaArrangements};
+    use crate::gen::generate;
+
+    #[test]
+    fn watchdog_reports_delayed_operator() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        timely::execute_directly(|worker| {
+            let release = Rc::new(Cell::new(false));
+            let gate = release.clone();
+            let (mut input, watchdog) = worker.dataflow(|scope| {
+                let (input, order) = scope.new_collection::<u64, isize>();
+                // 一直持有时刻 0 的 capability, 直到 `release` 被设置, 模拟卡住的算子
+                let delayed = order
+                    .inner
+                    .unary_frontier(Pipeline, "Delayed", move |cap, _| {
+                        let mut cap = Some(cap);
+                        move |input, output| {
+                            input.for_each(|time, data| {
+                                output.session(&time).give_iterator(data.iter().cloned());
+                            });
+                            if gate.get() {
+                                cap.take();
+                            }
+                        }
+                    })
+                    .as_collection();
+                let watchdog = stall_watchdog(&order, &delayed, Duration::from_millis(20));
+                (input, watchdog)
+            });
+            input.advance_to(1);
+            input.flush();
+            let start = Instant::now();
+            while watchdog.stalls().is_empty() && start.elapsed() < Duration::from_secs(5) {
+                worker.step();
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(watchdog.stalls(), vec![0]);
+            release.set(true);
+            input.close();
+            while worker.step() {}
+        });
+    }
+
+    #[test]
+    fn slow_worker_has_higher_latency_percentiles() {
+        let samples = LatencySamples::new();
+        let shared = samples.clone();
+        timely::execute(timely::Config::process(2), move |worker| {
+            let index = worker.index();
+            let samples = shared.clone();
+            let (mut input, probe) = worker.dataflow(|scope| {
+                let (input, collection) = scope.new_collection::<u64, isize>();
+                // worker 1 每处理一行都要多花 5ms, 模拟一个落后的 worker
+                let slow = collection.inspect(move |_| {
+                    if index == 1 {
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                });
+                record_latency(&collection, &slow, samples);
+                (input, slow.probe())
+            });
+            for time in 0..10 {
+                input.insert(time * 2 + index as u64);
+                input.advance_to(time + 1);
+                input.flush();
+                worker.step_while(|| probe.less_than(&(time + 1)));
+            }
+        })
+        .unwrap()
+        .join();
+
+        let percentiles = latency_percentiles_per_worker(&samples);
+        assert_eq!(percentiles.len(), 2);
+        let (fast, slow) = (percentiles[&0], percentiles[&1]);
+        assert!(slow[0] >= 5.0, "{:?}", percentiles);
+        assert!(slow[0] > fast[0], "{:?}", percentiles);
+        assert!(slow.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn row_counts_reflect_worker_skew() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let shared = output.clone();
+        timely::execute(timely::Config::process(2), move |worker| {
+            let index = worker.index();
+            let sink = shared.clone();
+            let mut input = worker.dataflow(|scope| {
+                let (input, collection) = scope.new_collection::<u64, isize>();
+                per_worker_row_counts(&collection).inspect(move |x| sink.lock().unwrap().push(*x));
+                input
+            });
+            // worker 0 的数据是 worker 1 的 3 倍
+            let rows = if index == 0 { 9 } else { 3 };
+            for i in 0..rows {
+                input.insert(i);
+            }
+            // worker 1 在时刻 1 撤回一行
+            input.advance_to(1);
+            if index == 1 {
+                input.remove(0);
+            }
+        })
+        .unwrap()
+        .join();
+
+        let mut output = output.lock().unwrap().clone();
+        differential_dataflow::consolidation::consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((0, 9), 0, 1),
+                ((1, 2), 1, 1),
+                ((1, 3), 0, 1),
+                ((1, 3), 1, -1)
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_are_empty_after_full_retraction() {
+        timely::execute_directly(|worker| {
+            let (mut o, mut u, mut p, mut traces) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                let arrangements = DeltaArrangements::new(&order, &user, &province);
+                delta_join_arranged(&order, &user, &province, &arrangements);
+                (o, u, p, arrangements.traces())
+            });
+            let empty = AntichainRef::new(&[]);
+            traces.order_by_uid.set_logical_compaction(empty);
+            traces.order_by_uid.set_physical_compaction(empty);
+            traces.user_by_uid.set_logical_compaction(empty);
+            traces.user_by_uid.set_physical_compaction(empty);
+            traces.user_by_pid.set_logical_compaction(empty);
+            traces.user_by_pid.set_physical_compaction(empty);
+            traces.province_by_pid.set_logical_compaction(empty);
+            traces.province_by_pid.set_physical_compaction(empty);
+
+            // 时刻 0 插入所有数据, 时刻 1 全部撤回
+            let dataset = generate(5, 50, 8);
+            dataset.orders.iter().for_each(|d| o.insert(d.clone()));
+            dataset.users.iter().for_each(|d| u.insert(d.clone()));
+            dataset.provinces.iter().for_each(|d| p.insert(d.clone()));
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            dataset.orders.into_iter().for_each(|d| o.remove(d));
+            dataset.users.into_iter().for_each(|d| u.remove(d));
+            dataset.provinces.into_iter().for_each(|d| p.remove(d));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+
+            assert_traces_empty(&mut traces);
+        });
+    }
+
+    #[test]
+    fn diff_rate_spikes_then_decays() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut input = worker.dataflow(|scope| {
+                let (input, collection) = scope.new_collection::<u64, isize>();
+                diff_rate(&collection, 2).inspect(move |x| sink.borrow_mut().push(*x));
+                input
+            });
+            input.insert(0);
+            // 时刻 2 突然出现 6 个更新 (包括撤回)
+            input.advance_to(2);
+            (1..6).for_each(|i| input.insert(i));
+            input.remove(0);
+            // 突增移出窗口之后, 时刻 5 只有一个更新
+            input.advance_to(5);
+            input.insert(6);
+            input.close();
+            while worker.step() {}
+            output.take()
+        });
+        differential_dataflow::consolidation::consolidate_updates(&mut output);
+        let rates: Vec<_> = output
+            .into_iter()
+            .map(|(rate, t, r)| (rate.0, t, r))
+            .collect();
+        assert_eq!(
+            rates,
+            vec![
+                (0.5, 0, 1),
+                (0.5, 2, -1),
+                (0.5, 5, 1),
+                (0.5, 7, -1),
+                (3.0, 2, 1),
+                (3.0, 4, -1),
+            ]
+        );
+    }
+}
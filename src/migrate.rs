@@ -0,0 +1,154 @@
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::Join;
+use differential_dataflow::Collection;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{Order, Pid, Province, Uid, User};
+
+/// 新版本的用户, 增加了可以为空的 `email`。
+/// 反序列化时缺少 `email` 字段会被当作 None, 所以旧版本的 JSON 也可以直接解析成 `UserV2`
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct UserV2 {
+    pub uid: Uid,
+    pub pid: Pid,
+    pub padding: String,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+impl From<User> for UserV2 {
+    fn from(u: User) -> Self {
+        UserV2 {
+            uid: u.uid,
+            pid: u.pid,
+            padding: u.padding,
+            email: None,
+        }
+    }
+}
+
+/// schema 变化期间同时存在的两个版本的用户
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum VersionedUser {
+    V1(User),
+    V2(UserV2),
+}
+
+impl VersionedUser {
+    // 统一转换成新版本, 旧版本的用户没有 email
+    pub fn migrate(self) -> UserV2 {
+        match self {
+            VersionedUser::V1(u) => u.into(),
+            VersionedUser::V2(u) => u,
+        }
+    }
+}
+
+// 在进入 join 之前把两个版本的用户都转换成 `UserV2`。
+// 同一个用户的撤回必须使用与插入时相同的版本, 否则转换之后两者的 email 不同, 无法相互抵消
+pub fn migrate_users<S>(user: &Collection<S, VersionedUser>) -> Collection<S, UserV2>
+where
+    S: Scope,
+{
+    user.map(VersionedUser::migrate)
+}
+
+// 与 `regular_join` 相同, 只是 user 可以是新旧两个版本混合的 collection
+pub fn join_versioned_users<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, VersionedUser>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Order, UserV2, Province)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order
+        .map(|o| (o.uid, o))
+        .join_map(&migrate_users(user).map(|u| (u.uid, u)), |_, o, u| {
+            (u.pid, (o.clone(), u.clone()))
+        })
+        .join_map(&province.map(|p| (p.pid, p)), |_, (o, u), p| {
+            (o.clone(), u.clone(), p.clone())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::Oid;
+
+    #[test]
+    fn v1_and_v2_users_both_join() {
+        let order = |oid| Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(oid),
+        };
+        let v1 = User {
+            uid: Uid(1),
+            pid: Pid(1),
+            padding: String::new(),
+        };
+        let v2 = |uid, email: &str| UserV2 {
+            uid: Uid(uid),
+            pid: Pid(1),
+            padding: String::new(),
+            email: Some(email.to_string()),
+        };
+        let province = Province {
+            pid: Pid(1),
+            name: "p1".to_string(),
+        };
+        // 旧版本的 JSON 可以直接解析成新版本
+        let parsed: UserV2 = serde_json::from_str(&serde_json::to_string(&v1).unwrap()).unwrap();
+        assert_eq!(parsed, UserV2::from(v1.clone()));
+
+        let (u1, p1) = (v1.clone(), province.clone());
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                join_versioned_users(&orders, &users, &provinces)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            o.insert(order(1));
+            o.insert(order(2));
+            u.insert(VersionedUser::V1(u1.clone()));
+            u.insert(VersionedUser::V2(v2(2, "2@example.com")));
+            p.insert(p1);
+            // 时刻 1: 用户 1 升级到新版本, 撤回使用插入时的旧版本
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            u.remove(VersionedUser::V1(u1));
+            u.insert(VersionedUser::V2(v2(1, "1@example.com")));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((order(1), UserV2::from(v1.clone()), province.clone()), 0, 1),
+                ((order(1), UserV2::from(v1), province.clone()), 1, -1),
+                ((order(1), v2(1, "1@example.com"), province.clone()), 1, 1),
+                ((order(2), v2(2, "2@example.com"), province), 0, 1),
+            ]
+        );
+    }
+}
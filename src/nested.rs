@@ -0,0 +1,176 @@
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::Reduce;
+use differential_dataflow::Collection;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{Order, Pid, Province, User};
+
+/// 用户以及他的所有订单
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct UserTree {
+    pub user: User,
+    /// 按照 `Order` 排序, 没有订单时为空
+    pub orders: Vec<Order>,
+}
+
+/// 省份以及它的所有用户
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct ProvinceTree {
+    pub province: Province,
+    /// 按照 `UserTree` 排序, 没有用户时为空
+    pub users: Vec<UserTree>,
+}
+
+// reduce 的 input, 把父节点和子节点放到同一个 group 里
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+enum Node<P, C> {
+    Parent(P),
+    Child(C),
+}
+
+// 把 join 的结果组织成 "省份 -> 用户 -> 订单" 的树, 以 pid 为 key 增量维护, 方便 GraphQL 之类的接口直接返回。
+// 通过两层 reduce 实现: 第一层把订单挂到用户下面, 第二层把用户挂到省份下面。
+// 没有订单的用户和没有用户的省份依然会出现在树中, 只是对应的 Vec 为空; 找不到父节点的子节点 (悬空的外键) 会被丢弃
+pub fn province_tree<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, ProvinceTree)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    let user_tree = user
+        .map(|u| (u.uid, Node::Parent(u)))
+        .concat(&order.map(|o| (o.uid, Node::Child(o))))
+        .reduce(|_, input, output| {
+            let orders: Vec<Order> = input
+                .iter()
+                .flat_map(|(node, r)| match node {
+                    Node::Child(o) => vec![o.clone(); (*r).max(0) as usize],
+                    Node::Parent(_) => vec![],
+                })
+                .collect();
+            for (node, r) in input {
+                if let Node::Parent(u) = node {
+                    let tree = UserTree {
+                        user: u.clone(),
+                        orders: orders.clone(),
+                    };
+                    output.push((tree, *r));
+                }
+            }
+        });
+
+    user_tree
+        .map(|(_, tree)| (tree.user.pid, Node::Child(tree)))
+        .concat(&province.map(|p| (p.pid, Node::Parent(p))))
+        .reduce(|_, input, output| {
+            let users: Vec<UserTree> = input
+                .iter()
+                .flat_map(|(node, r)| match node {
+                    Node::Child(u) => vec![u.clone(); (*r).max(0) as usize],
+                    Node::Parent(_) => vec![],
+                })
+                .collect();
+            for (node, r) in input {
+                if let Node::Parent(p) = node {
+                    let tree = ProvinceTree {
+                        province: p.clone(),
+                        users: users.clone(),
+                    };
+                    output.push((tree, *r));
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::{Oid, Uid};
+
+    #[test]
+    fn tree_keeps_empty_children_and_grows_with_orders() {
+        let order = |oid, uid| Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(uid),
+        };
+        let user = |uid| User {
+            uid: Uid(uid),
+            pid: Pid(1),
+            padding: String::new(),
+        };
+        let province = |pid| Province {
+            pid: Pid(pid),
+            name: format!("p{}", pid),
+        };
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                province_tree(&orders, &users, &provinces)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            // 用户 2 没有订单, 省份 2 没有用户, 订单 9 的用户不存在
+            o.insert(order(1, 1));
+            o.insert(order(9, 9));
+            u.insert(user(1));
+            u.insert(user(2));
+            p.insert(province(1));
+            p.insert(province(2));
+            // 时刻 1: 用户 2 下了第一单
+            o.advance_to(1);
+            u.advance_to(1);
+            p.advance_to(1);
+            o.insert(order(2, 2));
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+
+        let user_tree = |uid, orders| UserTree {
+            user: user(uid),
+            orders,
+        };
+        let before = ProvinceTree {
+            province: province(1),
+            users: vec![user_tree(1, vec![order(1, 1)]), user_tree(2, vec![])],
+        };
+        let after = ProvinceTree {
+            province: province(1),
+            users: vec![
+                user_tree(1, vec![order(1, 1)]),
+                user_tree(2, vec![order(2, 2)]),
+            ],
+        };
+        let empty = ProvinceTree {
+            province: province(2),
+            users: vec![],
+        };
+        assert_eq!(
+            output,
+            vec![
+                ((Pid(1), before.clone()), 0, 1),
+                ((Pid(1), before), 1, -1),
+                ((Pid(1), after), 1, 1),
+                ((Pid(2), empty), 0, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use crate::delta_join::{Pid, Province};
+
+// 修改省份的名称, 返回需要输入到省份 input 的两个更新: 撤回原来的省份, 插入新名称的省份, 例如
+// `rename_province(pid, "Beijing", &old).into_iter().for_each(|(p, r)| province_input.update(p, r))`。
+// pid 保持不变, 两个更新应该在同一时刻输入, 这样下游 join 中这个省份的所有行在这一时刻同时从旧名称变为新名称,
+// 不会出现省份暂时消失的中间状态。`old` 必须是当前的省份, 否则撤回无法与之前的插入抵消
+pub fn rename_province(
+    pid: Pid,
+    new_name: impl Into<String>,
+    old: &Province,
+) -> [(Province, isize); 2] {
+    assert_eq!(old.pid, pid, "province {:?} is not {:?}", old, pid);
+    let new = Province {
+        pid,
+        name: new_name.into(),
+    };
+    [(old.clone(), -1), (new, 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta_join::{Oid, Order, Uid, User, Variant};
+    use crate::harness;
+
+    #[test]
+    fn rename_updates_all_joined_rows_at_once() {
+        let old = Province {
+            pid: Pid(1),
+            name: "peking".to_string(),
+        };
+        let user = User {
+            uid: Uid(1),
+            pid: Pid(1),
+            padding: String::new(),
+        };
+        let order = |oid| Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(1),
+        };
+        let mut provinces = vec![(old.clone(), 0, 1)];
+        provinces.extend(
+            rename_province(Pid(1), "beijing", &old)
+                .into_iter()
+                .map(|(p, r)| (p, 3, r)),
+        );
+        let output = harness::run(
+            Variant::Delta,
+            (1..4).map(|oid| (order(oid), 0, 1)).collect(),
+            vec![(user.clone(), 0, 1)],
+            provinces,
+        );
+
+        let new = Province {
+            pid: Pid(1),
+            name: "beijing".to_string(),
+        };
+        // 三行都在 t=3 从旧名称变为新名称
+        let expected: Vec<_> = (1..4)
+            .flat_map(|oid| {
+                [
+                    ((order(oid), user.clone(), new.clone()), 3, 1),
+                    ((order(oid), user.clone(), old.clone()), 0, 1),
+                    ((order(oid), user.clone(), old.clone()), 3, -1),
+                ]
+            })
+            .collect();
+        assert_eq!(output, expected);
+    }
+}
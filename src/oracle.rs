@@ -0,0 +1,67 @@
+use differential_dataflow::consolidation::consolidate;
+
+use crate::delta_join::{Order, Province, User};
+
+// 不依赖 differential 的参考实现, 用嵌套循环直接计算 join 结果, 作为测试的标准答案。
+// 输入输出都是带 multiplicity 的多重集, 结果中每一行的 multiplicity 为三个输入 multiplicity 的乘积,
+// 输出已经 consolidate (排序并且去掉了 multiplicity 为 0 的行)
+pub fn join_reference(
+    orders: &[(Order, isize)],
+    users: &[(User, isize)],
+    provinces: &[(Province, isize)],
+) -> Vec<((Order, User, Province), isize)> {
+    let mut result = Vec::new();
+    for (o, r1) in orders {
+        for (u, r2) in users.iter().filter(|(u, _)| u.uid == o.uid) {
+            for (p, r3) in provinces.iter().filter(|(p, _)| p.pid == u.pid) {
+                result.push(((o.clone(), u.clone(), p.clone()), r1 * r2 * r3));
+            }
+        }
+    }
+    consolidate(&mut result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::Update;
+    use crate::delta_join::Variant;
+    use crate::gen::generate;
+    use crate::harness;
+
+    fn at_zero<D: Clone>(rows: &[(D, isize)]) -> Vec<Update<D>> {
+        rows.iter().map(|(d, r)| (d.clone(), 0, *r)).collect()
+    }
+
+    #[test]
+    fn reference_matches_regular_join() {
+        for seed in 0..3 {
+            let dataset = generate(seed, 100, 0);
+            // 一部分订单出现两次, 检验 multiplicity 的乘积
+            let orders: Vec<_> = dataset
+                .orders
+                .iter()
+                .enumerate()
+                .map(|(i, o)| (o.clone(), if i % 3 == 0 { 2 } else { 1 }))
+                .collect();
+            let users: Vec<_> = dataset.users.iter().map(|u| (u.clone(), 1)).collect();
+            let provinces: Vec<_> = dataset.provinces.iter().map(|p| (p.clone(), 1)).collect();
+
+            let mut regular: Vec<_> = harness::run(
+                Variant::Regular,
+                at_zero(&orders),
+                at_zero(&users),
+                at_zero(&provinces),
+            )
+            .into_iter()
+            .map(|(d, _, r)| (d, r))
+            .collect();
+            consolidate(&mut regular);
+
+            let reference = join_reference(&orders, &users, &provinces);
+            assert!(!reference.is_empty());
+            assert_eq!(reference, regular, "seed {}", seed);
+        }
+    }
+}
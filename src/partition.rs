@@ -0,0 +1,179 @@
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::operators::arrange::ArrangeByKey;
+use differential_dataflow::Collection;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{delta_join_chain, Order, Province, Uid, User};
+
+/// 带有预先计算好的 hash 的 key。
+/// differential 在 arrange 以及 half_join 的时候都是通过 key 的 `Hashable` 来决定数据发送给哪个 worker,
+/// 所以只需要替换 key 的 hash 就可以同时控制 arrangement 和 half_join 的分区, 两者始终保持一致。
+/// 注意这里故意没有实现 `Hash`, 否则会与 `Hashable` 的 blanket impl 冲突
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+pub struct HashedKey<K> {
+    pub hash: u64,
+    pub key: K,
+}
+
+impl<K> Hashable for HashedKey<K> {
+    type Output = u64;
+
+    fn hashed(&self) -> u64 {
+        self.hash
+    }
+}
+
+// 与 `delta_join` 相同, 但是以 uid 为 key 的 arrangement 使用自定义的 `hash` 来分区, 例如可以把热点 uid 分散到不同的 worker 上。
+// 同一个 uid 的数据依然只会在一个 worker 上, 所以 `hash` 只能改变 key 之间的分布, 无法拆分单个热点 key
+pub fn delta_join_with_hash<S, F>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+    hash: F,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+    F: Fn(&Uid) -> u64 + Clone + 'static,
+{
+    let key = move |uid: Uid| HashedKey {
+        hash: hash(&uid),
+        key: uid,
+    };
+
+    let k = key.clone();
+    let order_arrange = order.map(move |o| (k(o.uid), o)).arrange_by_key();
+    let k = key.clone();
+    let user_uid_arrange = user.map(move |u| (k(u.uid), u)).arrange_by_key();
+    let user_pid_arrange = user.map(|u| (u.pid, u)).arrange_by_key();
+    let province_arrange = province.map(|p| (p.pid, p)).arrange_by_key();
+
+    let k = key.clone();
+    let user_uid = move |u: &User| k(u.uid);
+    delta_join_chain(
+        order,
+        user,
+        province,
+        order_arrange,
+        user_uid_arrange,
+        user_pid_arrange,
+        province_arrange,
+        move |o: &Order| key(o.uid),
+        user_uid,
+        |u: &User| u.pid,
+        |p: &Province| p.pid,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::InputSession;
+
+    use super::*;
+    use crate::dataset::Update;
+    use crate::delta_join::Variant;
+    use crate::gen::generate;
+    use crate::harness;
+
+    const WORKERS: usize = 4;
+
+    // 在 `WORKERS` 个 worker 上以 `hash` 对用户建立 arrangement, 返回每个 worker 上保存的用户数量
+    fn users_per_worker(hash: fn(&Uid) -> u64, users: Vec<User>) -> Vec<usize> {
+        let counts = Arc::new(Mutex::new(vec![0; WORKERS]));
+        let shared = counts.clone();
+        timely::execute(timely::Config::process(WORKERS), move |worker| {
+            let index = worker.index();
+            let counts = shared.clone();
+            let mut input = InputSession::new();
+            worker.dataflow::<u64, _, _>(|scope| {
+                input
+                    .to_collection(scope)
+                    .map(move |u: User| {
+                        (
+                            HashedKey {
+                                hash: hash(&u.uid),
+                                key: u.uid,
+                            },
+                            u,
+                        )
+                    })
+                    .arrange_by_key()
+                    .as_collection(|_, u| u.clone())
+                    .inspect(move |_| counts.lock().unwrap()[index] += 1);
+            });
+            if index == 0 {
+                for u in users.clone() {
+                    input.insert(u);
+                }
+            }
+        })
+        .unwrap();
+        let counts = counts.lock().unwrap().clone();
+        counts
+    }
+
+    #[test]
+    fn hash_balances_users_across_workers() {
+        let users = generate(0, 8, 0).users;
+        // uid 为 0..8, 以 uid 本身作为 hash 正好每个 worker 两个
+        assert_eq!(
+            users_per_worker(|uid| uid.0, users.clone()),
+            vec![2; WORKERS]
+        );
+        // 所有 uid 的 hash 相同时全部落在同一个 worker 上
+        assert_eq!(users_per_worker(|_| 0, users), vec![8, 0, 0, 0]);
+    }
+
+    #[test]
+    fn matches_delta_join_on_multiple_workers() {
+        let dataset = generate(1, 20, 0);
+        let orders: Vec<Update<Order>> = dataset.orders.into_iter().map(|o| (o, 0, 1)).collect();
+        let users: Vec<Update<User>> = dataset.users.into_iter().map(|u| (u, 0, 1)).collect();
+        let provinces: Vec<Update<Province>> =
+            dataset.provinces.into_iter().map(|p| (p, 0, 1)).collect();
+        let expected = harness::run(
+            Variant::Delta,
+            orders.clone(),
+            users.clone(),
+            provinces.clone(),
+        );
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let shared = output.clone();
+        timely::execute(timely::Config::process(WORKERS), move |worker| {
+            let index = worker.index();
+            let output = shared.clone();
+            let mut o = InputSession::new();
+            let mut u = InputSession::new();
+            let mut p = InputSession::new();
+            worker.dataflow(|scope| {
+                delta_join_with_hash(
+                    &o.to_collection(scope),
+                    &u.to_collection(scope),
+                    &p.to_collection(scope),
+                    |uid: &Uid| uid.0,
+                )
+                .inspect(move |x| output.lock().unwrap().push(x.clone()));
+            });
+            if index == 0 {
+                for x in orders.clone() {
+                    o.update_at(x.0, x.1, x.2);
+                }
+                for x in users.clone() {
+                    u.update_at(x.0, x.1, x.2);
+                }
+                for x in provinces.clone() {
+                    p.update_at(x.0, x.1, x.2);
+                }
+            }
+        })
+        .unwrap();
+
+        let mut output = output.lock().unwrap().clone();
+        consolidate_updates(&mut output);
+        assert_eq!(output, expected);
+    }
+}
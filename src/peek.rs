@@ -0,0 +1,189 @@
+use differential_dataflow::trace::cursor::Cursor;
+use differential_dataflow::trace::TraceReader;
+use differential_dataflow::ExchangeData;
+use timely::order::PartialOrder;
+
+use crate::delta_join::Trace;
+use crate::epoch::{Epoch, Epochs};
+
+// 读取 trace 在时刻 `time` 的状态, 也就是所有时间小于等于 `time` 的更新累积之后的结果, 只保留累积 diff 非零的元素。
+// 要求 trace 的 logical compaction frontier 不超过 `time`, 否则历史已经被合并, 得到的结果是不准确的
+pub fn state_at<K, V>(trace: &mut Trace<K, V>, time: u64) -> Vec<((K, V), isize)>
+where
+    K: ExchangeData,
+    V: ExchangeData,
+{
+    let mut state = Vec::new();
+    let (mut cursor, storage) = trace.cursor();
+    while cursor.key_valid(&storage) {
+        while cursor.val_valid(&storage) {
+            let mut sum = 0;
+            cursor.map_times(&storage, |t, r| {
+                if t.less_equal(&time) {
+                    sum += *r;
+                }
+            });
+            if sum != 0 {
+                let key = cursor.key(&storage).clone();
+                let val = cursor.val(&storage).clone();
+                state.push(((key, val), sum));
+            }
+            cursor.step_val(&storage);
+        }
+        cursor.step_key(&storage);
+    }
+    state
+}
+
+// 读取 trace 中时间在 `[lo, hi]` 之间的更新累积之后的结果, 也就是这段时间内新出现 (diff 为正) 或者消失 (diff 为负)
+// 的元素, 只保留累积 diff 非零的元素。trace 保留了完整的历史, 所以 lo/hi 可以在每次查询时任意改变,
+// 例如把 join 的输出 `arrange_by_key` 之后, 根据界面上滑块的位置查询。
+// 与 `state_at` 一样, 要求 trace 的 logical compaction frontier 不超过 `lo`
+pub fn range<K, V>(trace: &mut Trace<K, V>, lo: u64, hi: u64) -> Vec<((K, V), isize)>
+where
+    K: ExchangeData,
+    V: ExchangeData,
+{
+    let mut updates = Vec::new();
+    let (mut cursor, storage) = trace.cursor();
+    while cursor.key_valid(&storage) {
+        while cursor.val_valid(&storage) {
+            let mut sum = 0;
+            cursor.map_times(&storage, |t, r| {
+                if lo <= *t && *t <= hi {
+                    sum += *r;
+                }
+            });
+            if sum != 0 {
+                let key = cursor.key(&storage).clone();
+                let val = cursor.val(&storage).clone();
+                updates.push(((key, val), sum));
+            }
+            cursor.step_val(&storage);
+        }
+        cursor.step_key(&storage);
+    }
+    updates
+}
+
+// 读取 trace 在 `epoch` 结束时的状态, 也就是这个 epoch 以及之前的所有 epoch 中的更新累积之后的结果。
+// `epoch` 还没有结束时返回 None; 与 `state_at` 一样, 要求 trace 的 logical compaction 没有越过这个 epoch
+pub fn at_epoch<K, V>(
+    trace: &mut Trace<K, V>,
+    epochs: &Epochs,
+    epoch: Epoch,
+) -> Option<Vec<((K, V), isize)>>
+where
+    K: ExchangeData,
+    V: ExchangeData,
+{
+    match epochs.end_of(epoch)? {
+        0 => Some(Vec::new()),
+        end => Some(state_at(trace, end - 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use differential_dataflow::input::Input;
+    use differential_dataflow::operators::arrange::ArrangeByKey;
+
+    use super::*;
+    use crate::delta_join::{delta_join, Oid, Order, Pid, Province, Uid, User};
+
+    #[test]
+    fn range_returns_only_changes_within_bounds() {
+        let order = |oid| Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(1),
+        };
+        let ranges = timely::execute_directly(move |worker| {
+            let (mut o, mut u, mut p, mut trace) = worker.dataflow(|scope| {
+                let (o, orders) = scope.new_collection();
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                let trace = delta_join(&orders, &users, &provinces)
+                    .map(|(o, _, p)| (o.oid, p.pid))
+                    .arrange_by_key()
+                    .trace;
+                (o, u, p, trace)
+            });
+            u.insert(User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            });
+            p.insert(Province {
+                pid: Pid(1),
+                name: "p1".to_string(),
+            });
+            // 订单 1 在 t=0 出现, t=4 撤回; 订单 2 在 t=2 出现; 订单 3 在 t=6 出现
+            for (t, oid, diff) in [(0, 1, 1), (2, 2, 1), (4, 1, -1), (6, 3, 1)] {
+                o.advance_to(t);
+                u.advance_to(t);
+                p.advance_to(t);
+                o.update(order(oid), diff);
+            }
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            [(0, 3), (2, 4), (5, 5), (0, 6)].map(|(lo, hi)| range(&mut trace, lo, hi))
+        });
+        let row = |oid| (Oid(oid), Pid(1));
+        assert_eq!(ranges[0], vec![(row(1), 1), (row(2), 1)]);
+        assert_eq!(ranges[1], vec![(row(1), -1), (row(2), 1)]);
+        assert!(ranges[2].is_empty());
+        assert_eq!(ranges[3], vec![(row(2), 1), (row(3), 1)]);
+    }
+
+    #[test]
+    fn at_epoch_returns_cumulative_state() {
+        use crate::source::Inputs;
+
+        let order = |oid| Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(1),
+        };
+        let states = timely::execute_directly(move |worker| {
+            let mut inputs = Inputs::new();
+            let mut trace = worker.dataflow(|scope| {
+                let (o, u, p) = inputs.to_collections(scope);
+                delta_join(&o, &u, &p)
+                    .map(|(o, _, p)| (o.oid, p.pid))
+                    .arrange_by_key()
+                    .trace
+            });
+            let mut epochs = Epochs::new();
+            inputs.user.insert(User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            });
+            inputs.province.insert(Province {
+                pid: Pid(1),
+                name: "p1".to_string(),
+            });
+            inputs.order.insert(order(1));
+            epochs.advance(&mut inputs);
+            inputs.order.insert(order(2));
+            epochs.advance(&mut inputs);
+            inputs.order.remove(order(1));
+            inputs.order.insert(order(3));
+            epochs.advance(&mut inputs);
+            inputs.close();
+            while worker.step() {}
+            (0..4)
+                .map(|e| at_epoch(&mut trace, &epochs, Epoch(e)))
+                .collect::<Vec<_>>()
+        });
+        let rows = |oids: &[u64]| Some(oids.iter().map(|oid| ((Oid(*oid), Pid(1)), 1)).collect());
+        assert_eq!(states[0], rows(&[1]));
+        assert_eq!(states[1], rows(&[1, 2]));
+        assert_eq!(states[2], rows(&[2, 3]));
+        // 第四个 epoch 还没有结束
+        assert_eq!(states[3], None);
+    }
+}
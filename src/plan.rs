@@ -0,0 +1,96 @@
+use std::mem::size_of;
+
+use crate::delta_join::{Order, Uid, Variant};
+
+/// 关系的规模估计
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sizes {
+    pub orders: usize,
+    pub users: usize,
+    pub provinces: usize,
+    /// 单个 User 的平均字节数, 包括堆上的数据
+    pub user_bytes: usize,
+    /// 单个 Province 的平均字节数, 包括堆上的数据
+    pub province_bytes: usize,
+}
+
+/// 执行计划的代价估计
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Cost {
+    /// 所有 arrangement 中 tuple 的数量
+    pub arrangement_tuples: usize,
+    /// 所有 arrangement 估计的字节数
+    pub arrangement_bytes: usize,
+    /// 每个 input 都更新一条记录时需要经过的 join (或者 half_join) 的次数
+    pub join_stages: usize,
+}
+
+impl Cost {
+    fn arrange(mut self, tuples: usize, bytes_per_tuple: usize) -> Self {
+        self.arrangement_tuples += tuples;
+        self.arrangement_bytes += tuples * bytes_per_tuple;
+        self
+    }
+}
+
+// 根据各个关系的规模估计 join 的代价, 方便在运行之前选择合适的实现。
+// 假设所有外键都有效, 也就是说 join 的结果数量等于订单数量。各个实现创建的 arrangement 参考 `delta_join` 中的注释:
+// - `regular_join`/`regular_join_core`: 三个 input 的 arrangement 外加一个 (pid, (order, user)) 的中间结果
+// - `delta_join`: 没有中间结果, 但是 user 被 arrange 了两次
+// - `delta_join_late_materialization`: 第二个 user arrangement 只存 uid, 代价是多一次 half_join
+pub fn estimate_cost(variant: Variant, sizes: Sizes) -> Cost {
+    let order_bytes = size_of::<Order>();
+    let uid_bytes = size_of::<Uid>();
+    // 每个 tuple 还需要存储 key, time, diff
+    let overhead = size_of::<u64>() * 2 + size_of::<isize>();
+
+    let base = Cost::default()
+        .arrange(sizes.orders, order_bytes + overhead)
+        .arrange(sizes.users, sizes.user_bytes + overhead)
+        .arrange(sizes.provinces, sizes.province_bytes + overhead);
+    match variant {
+        Variant::Regular | Variant::RegularCore => Cost {
+            join_stages: 2,
+            ..base.arrange(sizes.orders, order_bytes + sizes.user_bytes + overhead)
+        },
+        Variant::Delta => Cost {
+            join_stages: 6,
+            ..base.arrange(sizes.users, sizes.user_bytes + overhead)
+        },
+        Variant::DeltaLateMaterialization => Cost {
+            join_stages: 7,
+            ..base.arrange(sizes.users, uid_bytes + overhead)
+        },
+    }
+}
+
+// 内存占用最少的实现
+pub fn cheapest_by_memory(sizes: Sizes) -> Variant {
+    Variant::ALL
+        .into_iter()
+        .min_by_key(|v| estimate_cost(*v, sizes).arrangement_bytes)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_materialization_is_cheaper_with_large_users() {
+        let sizes = Sizes {
+            orders: 100_000,
+            users: 1_000_000,
+            provinces: 34,
+            user_bytes: 1024,
+            province_bytes: 32,
+        };
+        let delta = estimate_cost(Variant::Delta, sizes);
+        let late = estimate_cost(Variant::DeltaLateMaterialization, sizes);
+        assert_eq!(late.arrangement_tuples, delta.arrangement_tuples);
+        assert!(late.arrangement_bytes < delta.arrangement_bytes);
+        // 节省的内存来自多一次 half_join
+        assert_eq!(late.join_stages, delta.join_stages + 1);
+        assert_eq!(cheapest_by_memory(sizes), Variant::DeltaLateMaterialization);
+    }
+}
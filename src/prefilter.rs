@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap};
+
+use differential_dataflow::consolidation::consolidate_updates;
+use differential_dataflow::{AsCollection, Collection};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Broadcast, Operator};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{Order, Uid, User};
+
+// splitmix64 的混合函数, 用来从 uid 得到两个独立的 hash
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// 容量固定的 bloom filter
+#[derive(Clone, Debug)]
+struct Bloom {
+    bits: Vec<u64>,
+    hashes: u64,
+    capacity: usize,
+    len: usize,
+}
+
+impl Bloom {
+    fn new(capacity: usize, fp_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity as f64) * fp_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let hashes = ((bits as f64 / capacity as f64) * ln2).round().max(1.0) as u64;
+        Bloom {
+            bits: vec![0; bits.div_ceil(64) as usize],
+            hashes,
+            capacity,
+            len: 0,
+        }
+    }
+
+    fn positions(&self, uid: Uid) -> impl Iterator<Item = (usize, u64)> {
+        let (h1, h2) = (mix(uid.0), mix(uid.0 ^ 0x5555_5555_5555_5555) | 1);
+        let m = self.bits.len() as u64 * 64;
+        (0..self.hashes).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % m;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+
+    fn insert(&mut self, uid: Uid) {
+        let positions: Vec<_> = self.positions(uid).collect();
+        for (word, mask) in positions {
+            self.bits[word] |= mask;
+        }
+        self.len += 1;
+    }
+
+    fn contains(&self, uid: Uid) -> bool {
+        self.positions(uid)
+            .all(|(word, mask)| self.bits[word] & mask != 0)
+    }
+}
+
+/// 可以不断插入的 bloom filter (scalable bloom filter): 当前的 filter 满了之后新建一个容量翻倍,
+/// 误判率减半的 filter, 所以总的误判率不超过 `fp_rate`。只支持插入, 不支持删除
+#[derive(Clone, Debug)]
+pub struct BloomFilter {
+    filters: Vec<Bloom>,
+    fp_rate: f64,
+}
+
+impl BloomFilter {
+    const INITIAL_CAPACITY: usize = 1024;
+
+    pub fn new(fp_rate: f64) -> Self {
+        assert!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "fp_rate must be in (0, 1), got {}",
+            fp_rate
+        );
+        BloomFilter {
+            filters: vec![Bloom::new(Self::INITIAL_CAPACITY, fp_rate / 2.0)],
+            fp_rate,
+        }
+    }
+
+    pub fn insert(&mut self, uid: Uid) {
+        if self.contains(uid) {
+            return;
+        }
+        let last = self.filters.last().unwrap();
+        if last.len >= last.capacity {
+            let i = self.filters.len() as i32;
+            let filter = Bloom::new(last.capacity * 2, self.fp_rate / 2f64.powi(i + 1));
+            self.filters.push(filter);
+        }
+        self.filters.last_mut().unwrap().insert(uid);
+    }
+
+    // 返回 false 表示 `uid` 一定没有被插入过, 返回 true 表示可能被插入过
+    pub fn contains(&self, uid: Uid) -> bool {
+        self.filters.iter().any(|f| f.contains(uid))
+    }
+}
+
+// 在进入 join 之前用 user 的 uid 构建 bloom filter 来过滤订单, 一定找不到用户的订单不会进入后面的 join,
+// 可能找到用户的订单 (包括误判率为 `fp_rate` 的误判) 原样输出。
+// 用户被广播到所有的 worker, 每个 worker 维护一个完整的 bloom filter, 订单不需要重新分区。
+// 被过滤掉的订单不会被丢弃, 而是暂存在算子中, 之后对应的用户出现时会在那个时刻输出, 所以下游 join 的结果不变。
+// 为了保证同一时刻的用户先于订单进入 filter, 每个时刻的数据要等到两个 input 的 frontier 都越过它之后才输出。
+// 用户被撤回之后 bloom filter 中仍然保留它的 uid, 这只会增加误判, 不会影响正确性
+pub fn bloom_orders<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    fp_rate: f64,
+) -> Collection<S, Order>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let mut bloom = BloomFilter::new(fp_rate);
+    // 按照数据本身的时间暂存, 一个 batch 中可能包含同一个 capability 之后的多个时刻
+    let mut order_stash: HashMap<u64, Vec<(Order, isize)>> = HashMap::new();
+    let mut user_stash: HashMap<u64, Vec<(User, isize)>> = HashMap::new();
+    // 被过滤掉的订单, 连同订单自己的时间, 累积之后的 multiplicity
+    let mut parked: BTreeMap<Uid, Vec<(Order, u64, isize)>> = BTreeMap::new();
+
+    order
+        .inner
+        .binary_notify(
+            &user.inner.broadcast(),
+            Pipeline,
+            Pipeline,
+            "BloomOrders",
+            None,
+            move |orders, users, output, notificator| {
+                orders.for_each(|time, data| {
+                    for (o, t, r) in data.iter().cloned() {
+                        order_stash
+                            .entry(t)
+                            .or_insert_with(|| {
+                                notificator.notify_at(time.delayed(&t));
+                                Vec::new()
+                            })
+                            .push((o, r));
+                    }
+                });
+                users.for_each(|time, data| {
+                    for (u, t, r) in data.iter().cloned() {
+                        user_stash
+                            .entry(t)
+                            .or_insert_with(|| {
+                                notificator.notify_at(time.delayed(&t));
+                                Vec::new()
+                            })
+                            .push((u, r));
+                    }
+                });
+                notificator.for_each(|time, _, _| {
+                    let t = *time.time();
+                    let mut session = output.session(&time);
+                    for (u, r) in user_stash.remove(&t).unwrap_or_default() {
+                        if r > 0 {
+                            bloom.insert(u.uid);
+                            if let Some(orders) = parked.remove(&u.uid) {
+                                // 订单不能被移动到比它自己的时间更早的时刻
+                                session.give_iterator(
+                                    orders.into_iter().map(|(o, ot, r)| (o, ot.max(t), r)),
+                                );
+                            }
+                        }
+                    }
+                    for (o, r) in order_stash.remove(&t).unwrap_or_default() {
+                        if bloom.contains(o.uid) {
+                            session.give((o, t, r));
+                        } else {
+                            let orders = parked.entry(o.uid).or_default();
+                            orders.push((o.clone(), t, r));
+                            consolidate_updates(orders);
+                            if orders.is_empty() {
+                                parked.remove(&o.uid);
+                            }
+                        }
+                    }
+                });
+            },
+        )
+        .as_collection()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta_join::{regular_join, Oid, Variant};
+    use crate::gen::generate;
+    use crate::harness::{self, run_join};
+
+    #[test]
+    fn no_false_negatives() {
+        let dataset = generate(3, 50, 0);
+        // 订单和用户分布在不同的时刻, 一部分订单比它的用户先到达
+        let mut orders: Vec<_> = dataset
+            .orders
+            .iter()
+            .enumerate()
+            .map(|(i, o)| (o.clone(), (i % 5) as u64, 1))
+            .collect();
+        for (i, o) in dataset.orders.iter().enumerate().step_by(9) {
+            orders.push((o.clone(), (i % 5) as u64 + 3, -1));
+        }
+        // 一定找不到用户的订单
+        for i in 0..20 {
+            let o = Order {
+                oid: Oid(10_000 + i),
+                price: 1,
+                uid: Uid(10_000 + i),
+            };
+            orders.push((o, i % 4, 1));
+        }
+        let users: Vec<_> = dataset
+            .users
+            .iter()
+            .enumerate()
+            .map(|(i, u)| (u.clone(), (i % 7) as u64, 1))
+            .collect();
+        let provinces: Vec<_> = dataset
+            .provinces
+            .iter()
+            .map(|p| (p.clone(), 0, 1))
+            .collect();
+
+        let expected = harness::run(
+            Variant::Regular,
+            orders.clone(),
+            users.clone(),
+            provinces.clone(),
+        );
+        assert!(!expected.is_empty());
+        let filtered = run_join(
+            |o, u, p| regular_join(&bloom_orders(o, u, 0.01), u, p),
+            orders,
+            users,
+            provinces,
+        );
+        assert_eq!(filtered, expected);
+    }
+}
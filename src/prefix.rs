@@ -0,0 +1,95 @@
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::ArrangeByKey;
+use differential_dataflow::operators::JoinCore;
+use differential_dataflow::Collection;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{Pid, User};
+
+/// 地区 ID
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Copy)]
+pub struct Rid(pub u64);
+
+/// 按地区划分的省份, 主键为 (rid, pid), 不同地区的省份可以有相同的 pid
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RegionalProvince {
+    pub rid: Rid,
+    pub pid: Pid,
+    pub name: String,
+}
+
+// 用户只知道 pid, 而省份的 arrangement 以 (rid, pid) 为 key, 无法直接按 pid 查找。
+// 这里额外创建一个以 pid 为 key 的 secondary arrangement, 它的 value 只是完整的 key (rid, pid),
+// 先通过它匹配 pid 前缀, 再通过完整的 key 关联到省份。所有 rid 下 pid 相同的省份都会匹配到同一个用户
+pub fn join_prefix<S>(
+    user: &Collection<S, User>,
+    province: &Collection<S, RegionalProvince>,
+) -> Collection<S, (User, RegionalProvince)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    let user = user.map(|u| (u.pid, u)).arrange_by_key();
+    let province_by_key = province.map(|p| ((p.rid, p.pid), p)).arrange_by_key();
+    // secondary arrangement: pid -> (rid, pid)
+    let province_by_pid = province.map(|p| (p.pid, (p.rid, p.pid))).arrange_by_key();
+
+    user.join_core(&province_by_pid, |_, u, key| Some((*key, u.clone())))
+        .arrange_by_key()
+        .join_core(&province_by_key, |_, u, p| Some((u.clone(), p.clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::Uid;
+
+    #[test]
+    fn provinces_sharing_a_pid_in_two_regions_both_match() {
+        let user = User {
+            uid: Uid(1),
+            pid: Pid(1),
+            padding: String::new(),
+        };
+        let province = |rid, pid| RegionalProvince {
+            rid: Rid(rid),
+            pid: Pid(pid),
+            name: format!("r{}p{}", rid, pid),
+        };
+        let provinces = vec![province(1, 1), province(2, 1), province(1, 2)];
+        let u1 = user.clone();
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut u, mut p) = worker.dataflow::<u64, _, _>(|scope| {
+                let (u, users) = scope.new_collection();
+                let (p, provinces) = scope.new_collection();
+                join_prefix(&users, &provinces).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (u, p)
+            });
+            u.insert(u1);
+            for province in provinces {
+                p.insert(province);
+            }
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((user.clone(), province(1, 1)), 0, 1),
+                ((user, province(2, 1)), 0, 1),
+            ]
+        );
+    }
+}
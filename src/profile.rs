@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use differential_dataflow::input::InputSession;
+use timely::logging::{StartStop, TimelyEvent};
+
+use crate::dataset::Dataset;
+use crate::delta_join::Variant;
+
+#[derive(Default)]
+struct Timings {
+    names: HashMap<usize, String>,
+    started: HashMap<usize, Duration>,
+    total: HashMap<usize, Duration>,
+}
+
+// 单 worker 运行 `variant`, 在时刻 0 输入整个数据集, 运行到结束, 通过 timely 日志中的 Schedule 事件统计
+// 每个算子被调度的总时间。同名的算子 (例如 delta join 中的多个 half_join) 会合并到一起,
+// 返回 (算子名, 总时间), 按照时间从多到少排序, 可以看出 half_join 和 arrange 哪个占了主要的时间
+pub fn operator_timings(variant: Variant, dataset: Dataset) -> Vec<(String, Duration)> {
+    let timings = timely::execute_directly(move |worker| {
+        let timings = Rc::new(RefCell::new(Timings::default()));
+        let state = timings.clone();
+        worker
+            .log_register()
+            .insert::<TimelyEvent, _>("timely", move |_, data| {
+                let mut state = state.borrow_mut();
+                for (time, _, event) in data.iter() {
+                    match event {
+                        TimelyEvent::Operates(e) => {
+                            state.names.insert(e.id, e.name.clone());
+                        }
+                        TimelyEvent::Schedule(e) => match e.start_stop {
+                            StartStop::Start => {
+                                state.started.insert(e.id, *time);
+                            }
+                            StartStop::Stop => {
+                                if let Some(start) = state.started.remove(&e.id) {
+                                    *state.total.entry(e.id).or_default() += *time - start;
+                                }
+                            }
+                        },
+                        _ => {}
+                    }
+                }
+            });
+
+        let mut order_input = InputSession::new();
+        let mut user_input = InputSession::new();
+        let mut province_input = InputSession::new();
+        let probe = worker.dataflow(|scope| {
+            variant
+                .join(
+                    &order_input.to_collection(scope),
+                    &user_input.to_collection(scope),
+                    &province_input.to_collection(scope),
+                )
+                .probe()
+        });
+
+        dataset
+            .orders
+            .into_iter()
+            .for_each(|o| order_input.insert(o));
+        dataset.users.into_iter().for_each(|u| user_input.insert(u));
+        dataset
+            .provinces
+            .into_iter()
+            .for_each(|p| province_input.insert(p));
+        order_input.close();
+        user_input.close();
+        province_input.close();
+        while !probe.done() {
+            worker.step();
+        }
+        // 移除 logger, 让缓存中剩余的事件都交给上面的回调处理
+        worker.log_register().remove("timely");
+        while worker.step() {}
+
+        let timings = timings.borrow();
+        let mut per_name: HashMap<String, Duration> = HashMap::new();
+        for (id, total) in &timings.total {
+            let name = timings
+                .names
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| format!("operator-{}", id));
+            *per_name.entry(name).or_default() += *total;
+        }
+        per_name
+    });
+
+    let mut timings: Vec<_> = timings.into_iter().collect();
+    timings.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    timings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen::generate;
+
+    // 名字中包含 `pattern` 的所有算子的总时间, 比较时忽略大小写和下划线, 例如 `half_join` 和 `ArrangeByKey`
+    fn total(timings: &[(String, Duration)], pattern: &str) -> Duration {
+        timings
+            .iter()
+            .filter(|(name, _)| name.to_lowercase().replace('_', "").contains(pattern))
+            .map(|(_, d)| *d)
+            .sum()
+    }
+
+    #[test]
+    fn delta_join_reports_half_join_and_arrange() {
+        let timings = operator_timings(Variant::Delta, generate(0, 200, 16));
+        assert!(
+            total(&timings, "halfjoin") > Duration::ZERO,
+            "{:?}",
+            timings
+        );
+        assert!(total(&timings, "arrange") > Duration::ZERO, "{:?}", timings);
+    }
+}
@@ -0,0 +1,101 @@
+use differential_dataflow::operators::JoinCore;
+use differential_dataflow::Collection;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{DeltaArrangements, Order, Province, User};
+
+/// 多个查询的结果, 不同的查询输出类型不同, 通过 enum 合并到同一个 collection 中
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum QueryResult {
+    /// 订单与用户的 join 结果
+    OrderUser(Order, User),
+    /// 用户与省份的 join 结果
+    UserProvince(User, Province),
+}
+
+// 在同一组共享的 arrangement 上执行两个不同的查询, 没有为任何一个查询创建新的 arrangement:
+// - 订单 join 用户: 使用 `order_by_uid` 和 `user_by_uid`
+// - 用户 join 省份: 使用 `user_by_pid` 和 `province_by_pid`
+pub fn multi_query<S>(shared: &DeltaArrangements<S, User>) -> Collection<S, QueryResult>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let order_user = shared
+        .order_by_uid
+        .join_core(&shared.user_by_uid, |_, o, u| {
+            Some(QueryResult::OrderUser(o.clone(), u.clone()))
+        });
+    let user_province = shared
+        .user_by_pid
+        .join_core(&shared.province_by_pid, |_, u, p| {
+            Some(QueryResult::UserProvince(u.clone(), p.clone()))
+        });
+    order_user.concat(&user_province)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::{Oid, Pid, Uid};
+
+    #[test]
+    fn both_queries_share_arrangements() {
+        let order = Order {
+            oid: Oid(1),
+            price: 10,
+            uid: Uid(1),
+        };
+        let user = |uid, pid| User {
+            uid: Uid(uid),
+            pid: Pid(pid),
+            padding: String::new(),
+        };
+        let province = Province {
+            pid: Pid(1),
+            name: "p1".to_string(),
+        };
+        let (o1, p1) = (order.clone(), province.clone());
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                let (o, order) = scope.new_collection();
+                let (u, user) = scope.new_collection();
+                let (p, province) = scope.new_collection();
+                let shared = DeltaArrangements::new(&order, &user, &province);
+                multi_query(&shared).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (o, u, p)
+            });
+            o.insert(o1);
+            // 用户 2 没有订单, 只出现在第二个查询中
+            u.insert(user(1, 1));
+            u.insert(user(2, 1));
+            p.insert(p1);
+            o.close();
+            u.close();
+            p.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                (QueryResult::OrderUser(order, user(1, 1)), 0, 1),
+                (
+                    QueryResult::UserProvince(user(1, 1), province.clone()),
+                    0,
+                    1
+                ),
+                (QueryResult::UserProvince(user(2, 1), province), 0, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use crate::dataset::Update;
+use crate::delta_join::{Oid, Order, Province, User, Variant};
+use crate::harness;
+
+// 最简单的入口: 单 worker, 所有数据都在时刻 0 输入, 运行结束后返回以 oid 为 key 的最终 join 结果。
+// 适合不关心增量计算的脚本场景
+pub fn join_once(
+    orders: Vec<Order>,
+    users: Vec<User>,
+    provinces: Vec<Province>,
+) -> HashMap<Oid, (User, Province)> {
+    harness::run(
+        Variant::Delta,
+        orders.into_iter().map(at_zero).collect(),
+        users.into_iter().map(at_zero).collect(),
+        provinces.into_iter().map(at_zero).collect(),
+    )
+    .into_iter()
+    .filter(|(_, _, diff)| *diff > 0)
+    .map(|((o, u, p), _, _)| (o.oid, (u, p)))
+    .collect()
+}
+
+fn at_zero<D>(d: D) -> Update<D> {
+    (d, 0, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta_join::{Pid, Uid};
+
+    #[test]
+    fn join_once_returns_final_rows_by_oid() {
+        let order = |oid, uid| Order {
+            oid: Oid(oid),
+            price: oid * 10,
+            uid: Uid(uid),
+        };
+        let user = |uid, pid| User {
+            uid: Uid(uid),
+            pid: Pid(pid),
+            padding: String::new(),
+        };
+        let province = |pid| Province {
+            pid: Pid(pid),
+            name: format!("p{}", pid),
+        };
+        // 订单 3 的用户不存在, 用户 2 的省份不存在
+        let joined = join_once(
+            vec![order(1, 1), order(2, 1), order(3, 9), order(4, 2)],
+            vec![user(1, 1), user(2, 5)],
+            vec![province(1), province(2)],
+        );
+        let expected: HashMap<_, _> = [
+            (Oid(1), (user(1, 1), province(1))),
+            (Oid(2), (user(1, 1), province(1))),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(joined, expected);
+    }
+}
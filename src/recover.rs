@@ -0,0 +1,200 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use differential_dataflow::consolidation::consolidate;
+use differential_dataflow::input::InputSession;
+use differential_dataflow::{AsCollection, Collection, Data};
+use timely::dataflow::operators::capture::{Capture, Event, EventLink, Replay};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{regular_join, Order, Province, User};
+
+/// 捕获到内存中的更新流, 包含数据 `(data, time, diff)` 以及 frontier 的推进
+pub type Captured<D> = Rc<EventLink<u64, Vec<(D, u64, isize)>>>;
+
+// 把 collection 的更新流捕获到内存 buffer 中, 返回 buffer 的头部, 之后可以从头重放
+pub fn capture<S, D>(collection: &Collection<S, D>) -> Captured<D>
+where
+    S: Scope<Timestamp = u64>,
+    D: Data,
+{
+    let link = Rc::new(EventLink::new());
+    collection.inner.capture_into(link.clone());
+    link
+}
+
+// 把捕获的更新重放到一个新的 dataflow 中，重放的内容包括 frontier, 所以下游看到的进度和原来的 dataflow 一致
+pub fn replay<S, D>(scope: &mut S, captured: &Captured<D>) -> Collection<S, D>
+where
+    S: Scope<Timestamp = u64>,
+    D: Data,
+{
+    Some(captured.clone()).replay_into(scope).as_collection()
+}
+
+// 模拟 worker 在计算中途重启: 第一个 dataflow 运行 `regular_join` 并捕获输出, 时刻 0 的数据 (一半的订单) 输入完成之后,
+// 剩下的订单还没有输入, input 也没有关闭, 计算就 "中断" 了;
+// 第二个 dataflow 不接触任何 input, 只从 buffer 中重放, 恢复出 join 在时刻 0 结束时的状态。
+// 返回 (中断前的状态, 恢复后的状态), 两者都已经 consolidate, 正确的情况下两者相等,
+// 并且等于只输入时刻 0 的数据时不中断运行得到的结果
+pub fn regular_join_recovery(
+    orders: Vec<Order>,
+    users: Vec<User>,
+    provinces: Vec<Province>,
+) -> (
+    Vec<((Order, User, Province), isize)>,
+    Vec<((Order, User, Province), isize)>,
+) {
+    timely::execute_directly(move |worker| {
+        let before = Rc::new(RefCell::new(Vec::new()));
+        let after = Rc::new(RefCell::new(Vec::new()));
+
+        let mut order_input = InputSession::new();
+        let mut user_input = InputSession::new();
+        let mut province_input = InputSession::new();
+
+        let sink = before.clone();
+        let (captured, probe) = worker.dataflow(|scope| {
+            let order = order_input.to_collection(scope);
+            let user = user_input.to_collection(scope);
+            let province = province_input.to_collection(scope);
+            let joined = regular_join(&order, &user, &province);
+            let probe = joined
+                .inspect(move |(d, _, r)| sink.borrow_mut().push((d.clone(), *r)))
+                .probe();
+            (capture(&joined), probe)
+        });
+
+        // 只输入一半的订单, 另一半在 "中断" 时还没有到达
+        orders
+            .into_iter()
+            .step_by(2)
+            .for_each(|o| order_input.insert(o));
+        users.into_iter().for_each(|u| user_input.insert(u));
+        provinces.into_iter().for_each(|p| province_input.insert(p));
+        order_input.advance_to(1);
+        user_input.advance_to(1);
+        province_input.advance_to(1);
+        order_input.flush();
+        user_input.flush();
+        province_input.flush();
+        worker.step_while(|| probe.less_than(&1));
+
+        // "重启": 新的 dataflow 只依赖于 buffer, 第一个 dataflow 的 input 没有关闭,
+        // 所以 buffer 中的 frontier 停在 1, 重放到这里为止
+        let sink = after.clone();
+        let replayed = worker.dataflow(|scope| {
+            replay(scope, &captured)
+                .inspect(move |(d, _, r)| sink.borrow_mut().push((d.clone(), *r)))
+                .probe()
+        });
+        worker.step_while(|| replayed.less_than(&1));
+
+        let mut before = before.take();
+        let mut after = after.take();
+        consolidate(&mut before);
+        consolidate(&mut after);
+        (before, after)
+    })
+}
+
+// 把捕获的更新按照时间分组并 consolidate, 按照时间从新到旧返回 (time, 这个时刻的更新),
+// 用于可以拖动时间轴的回放界面。consolidate 之后为空的时刻不会出现在结果中
+pub fn collect_batches_desc<D: Data>(captured: &Captured<D>) -> Vec<(u64, Vec<(D, isize)>)> {
+    let mut batches: BTreeMap<u64, Vec<(D, isize)>> = BTreeMap::new();
+    let mut link = captured.clone();
+    loop {
+        if let Some(Event::Messages(_, data)) = &link.event {
+            for (d, t, r) in data.iter().cloned() {
+                batches.entry(t).or_default().push((d, r));
+            }
+        }
+        let next = link.next.borrow().clone();
+        match next {
+            Some(next) => link = next,
+            None => break,
+        }
+    }
+    batches
+        .into_iter()
+        .rev()
+        .filter_map(|(t, mut updates)| {
+            consolidate(&mut updates);
+            (!updates.is_empty()).then_some((t, updates))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::delta_join::Variant;
+    use crate::gen::generate;
+    use crate::harness;
+
+    #[test]
+    fn replay_matches_uninterrupted_run() {
+        let dataset = generate(7, 20, 0);
+        let (before, after) = regular_join_recovery(
+            dataset.orders.clone(),
+            dataset.users.clone(),
+            dataset.provinces.clone(),
+        );
+        assert!(!after.is_empty());
+        assert_eq!(before, after);
+
+        // 不中断地运行, 只输入中断之前已经到达的数据
+        let orders = dataset
+            .orders
+            .iter()
+            .step_by(2)
+            .map(|o| (o.clone(), 0, 1))
+            .collect();
+        let users = dataset.users.iter().map(|u| (u.clone(), 0, 1)).collect();
+        let provinces = dataset
+            .provinces
+            .iter()
+            .map(|p| (p.clone(), 0, 1))
+            .collect();
+        let mut expected: Vec<_> = harness::run(Variant::Regular, orders, users, provinces)
+            .into_iter()
+            .map(|(d, _, r)| (d, r))
+            .collect();
+        consolidate(&mut expected);
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn batches_are_newest_first_and_consolidated() {
+        let batches = timely::execute_directly(|worker| {
+            let (mut input, captured) = worker.dataflow(|scope| {
+                let (input, collection) = scope.new_collection::<u64, isize>();
+                (input, capture(&collection))
+            });
+            input.update_at(1, 0, 1);
+            input.update_at(2, 0, 1);
+            input.update_at(3, 1, 1);
+            input.update_at(4, 1, 1);
+            input.update_at(4, 1, -1);
+            input.update_at(1, 2, -1);
+            input.update_at(5, 2, 1);
+            // 时刻 3 的更新相互抵消
+            input.update_at(6, 3, 1);
+            input.update_at(6, 3, -1);
+            input.close();
+            while worker.step() {}
+            collect_batches_desc(&captured)
+        });
+        assert_eq!(
+            batches,
+            vec![
+                (2, vec![(1, -1), (5, 1)]),
+                (1, vec![(3, 1)]),
+                (0, vec![(1, 1), (2, 1)]),
+            ]
+        );
+    }
+}
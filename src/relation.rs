@@ -0,0 +1,109 @@
+use crate::delta_join::{Order, Pid, Province, Uid, User};
+
+/// 声明一个以 `$field` 为 key 的关系, 生成一个同名的类型, 包含 join 时常用的几个关联函数:
+/// - `key`: 从一条记录中取出 key
+/// - `keyed`: 把 collection 转换成 `(key, 记录)`, 也就是手写的 `.map(|o| (o.uid, o))`
+/// - `arrange`: 以 key 建立 arrangement, 可以直接交给 `half_join` 使用
+///
+/// 例如:
+/// ```
+/// use dd_examples::delta_join::{Order, Uid};
+/// use dd_examples::relation;
+/// use differential_dataflow::input::Input;
+///
+/// relation!(pub OrderByUid: Order => uid: Uid);
+///
+/// timely::execute_directly(|worker| {
+///     worker.dataflow::<u64, _, _>(|scope| {
+///         let (_input, order) = scope.new_collection::<Order, isize>();
+///         let _order_by_uid = OrderByUid::arrange(&order);
+///     });
+/// });
+/// ```
+#[macro_export]
+macro_rules! relation {
+    ($vis:vis $name:ident : $relation:ty => $field:ident : $key:ty) => {
+        #[derive(Clone, Copy, Debug, Default)]
+        $vis struct $name;
+
+        impl $name {
+            pub fn key(d: &$relation) -> $key {
+                d.$field.clone()
+            }
+
+            pub fn keyed<S>(
+                collection: &::differential_dataflow::Collection<S, $relation>,
+            ) -> ::differential_dataflow::Collection<S, ($key, $relation)>
+            where
+                S: ::timely::dataflow::Scope,
+            {
+                collection.map(|d| (d.$field.clone(), d))
+            }
+
+            pub fn arrange<S>(
+                collection: &::differential_dataflow::Collection<S, $relation>,
+            ) -> $crate::delta_join::Arrangement<S, $key, $relation>
+            where
+                S: ::timely::dataflow::Scope<Timestamp = u64>,
+            {
+                use ::differential_dataflow::operators::arrange::ArrangeByKey;
+                Self::keyed(collection).arrange_by_key()
+            }
+        }
+    };
+}
+
+// delta join 中用到的关系
+relation!(pub OrderByUid: Order => uid: Uid);
+relation!(pub UserByUid: User => uid: Uid);
+relation!(pub UserByPid: User => pid: Pid);
+relation!(pub ProvinceByPid: Province => pid: Pid);
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use crate::delta_join::{Oid, Order, Uid};
+
+    relation!(OrdersByUid: Order => uid: Uid);
+
+    fn order(oid: u64, uid: u64) -> Order {
+        Order {
+            oid: Oid(oid),
+            price: oid * 10,
+            uid: Uid(uid),
+        }
+    }
+
+    #[test]
+    fn declared_relation_builds_order_by_uid_arrangement() {
+        assert_eq!(OrdersByUid::key(&order(1, 7)), Uid(7));
+
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut input = worker.dataflow(|scope| {
+                let (input, orders) = scope.new_collection();
+                OrdersByUid::arrange(&orders)
+                    .as_collection(|k, v| (*k, v.clone()))
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                input
+            });
+            input.insert(order(1, 7));
+            input.insert(order(2, 3));
+            input.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![((Uid(3), order(2, 3)), 0, 1), ((Uid(7), order(1, 7)), 0, 1)]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use differential_dataflow::consolidation::consolidate_updates;
+
+use crate::dataset::{InputOp, Update};
+use crate::delta_join::{delta_join, Order, Province, User};
+use crate::source::Inputs;
+
+// 按照时间戳回放带时间戳的更新, 用来演示 join 随着数据到达实时更新的过程。
+// 时间戳被当作毫秒, 相邻两个时刻在真实时间中的间隔与时间戳的差成正比; 每个时刻的更新输入之后会一直运行到
+// join 的输出追上这个时刻, 然后等待下一个时刻到来。返回 join 所有的输出, 已经 consolidate 并且按照 (data, time) 排序
+pub fn realtime(updates: Vec<InputOp>) -> Vec<Update<(Order, User, Province)>> {
+    accelerated(updates, 1.0)
+}
+
+// 与 `realtime` 相同, 但是时刻之间的间隔被压缩为原来的 `1 / speedup`, 例如 `speedup` 为 60 时一分钟的数据一秒钟回放完。
+// 输入之前会按照时间戳排序 (时间相同的更新保持原来的顺序), 所以更新总是按照时间戳的顺序进入 join
+pub fn accelerated(
+    mut updates: Vec<InputOp>,
+    speedup: f64,
+) -> Vec<Update<(Order, User, Province)>> {
+    assert!(speedup > 0.0, "speedup must be positive");
+    updates.sort_by_key(InputOp::time);
+
+    timely::execute_directly(move |worker| {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut inputs = Inputs::new();
+
+        let sink = output.clone();
+        let probe = worker.dataflow(|scope| {
+            let (order, user, province) = inputs.to_collections(scope);
+            delta_join(&order, &user, &province)
+                .inspect(move |x| sink.borrow_mut().push(x.clone()))
+                .probe()
+        });
+
+        let start = Instant::now();
+        let first = updates.first().map(InputOp::time).unwrap_or_default();
+        let mut updates = updates.into_iter().peekable();
+        while let Some(time) = updates.peek().map(InputOp::time) {
+            // 等待这个时刻在压缩之后的真实时间到来
+            let due = Duration::from_secs_f64((time - first) as f64 / 1000.0 / speedup);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+
+            inputs.advance_to(time);
+            while let Some(op) = updates.next_if(|op| op.time() == time) {
+                inputs.apply(op);
+            }
+            inputs.advance_to(time + 1);
+            inputs.flush();
+            while probe.less_than(&inputs.time()) {
+                worker.step();
+            }
+        }
+        inputs.close();
+        while !probe.done() {
+            worker.step();
+        }
+
+        let mut output = output.take();
+        consolidate_updates(&mut output);
+        output
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::split_ops;
+    use crate::delta_join::Variant;
+    use crate::gen::{generate, with_timestamps};
+    use crate::harness;
+
+    #[test]
+    fn accelerated_matches_direct_feeding() {
+        let mut ops = with_timestamps(&generate(5, 30, 0), 5, 1000);
+        // 一部分订单在之后被撤回, 撤回必须在插入之后回放
+        let retracted: Vec<_> = ops
+            .iter()
+            .filter_map(|op| match op {
+                InputOp::Order(o, t, _) if o.oid.0 % 5 == 0 => {
+                    Some(InputOp::Order(o.clone(), t + 100, -1))
+                }
+                _ => None,
+            })
+            .collect();
+        ops.extend(retracted);
+        let (o, u, p) = split_ops(ops.clone());
+        let expected = harness::run(Variant::Delta, o, u, p);
+
+        // 打乱输入的顺序, `accelerated` 会按照时间戳排序之后再回放
+        ops.reverse();
+        let replayed = accelerated(ops, 1e9);
+        assert!(replayed.iter().any(|(_, _, r)| *r < 0));
+        assert_eq!(replayed, expected);
+    }
+}
@@ -0,0 +1,150 @@
+use differential_dataflow::operators::{Count, Join, Threshold};
+use differential_dataflow::Collection;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::{delta_join, Oid, Order, Pid, Province, Uid, User};
+
+/// 数据中的问题
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum JoinError {
+    /// 订单的 uid 找不到对应的用户
+    DanglingUid(Order),
+    /// 用户的 pid 找不到对应的省份
+    DanglingPid(User),
+    /// 多个订单使用了同一个 oid, 第二个字段是数量
+    DuplicateOrder(Oid, isize),
+    /// 多个用户使用了同一个 uid
+    DuplicateUser(Uid, isize),
+    /// 多个省份使用了同一个 pid
+    DuplicateProvince(Pid, isize),
+}
+
+// 在 `delta_join` 的基础上把数据问题作为错误输出, 而不是悄悄丢弃或者让结果翻倍:
+// 正常的 join 结果依然会输出为 `Ok`, 同时每个问题会输出一条 `Err`, 下游可以直接把两者分开。
+// 所有的错误都是增量维护的, 问题被修正之后 (例如补上了缺失的用户) 对应的 `Err` 会被撤回
+pub fn delta_join_safe<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, Result<(Order, User, Province), JoinError>>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let joined = delta_join(order, user, province).map(Ok);
+
+    let uids = user.map(|u| u.uid).distinct();
+    let pids = province.map(|p| p.pid).distinct();
+    let dangling_uid = order
+        .map(|o| (o.uid, o))
+        .antijoin(&uids)
+        .map(|(_, o)| Err(JoinError::DanglingUid(o)));
+    let dangling_pid = user
+        .map(|u| (u.pid, u))
+        .antijoin(&pids)
+        .map(|(_, u)| Err(JoinError::DanglingPid(u)));
+
+    let duplicate_order = order
+        .map(|o| o.oid)
+        .count()
+        .filter(|(_, count)| *count > 1)
+        .map(|(oid, count)| Err(JoinError::DuplicateOrder(oid, count)));
+    let duplicate_user = user
+        .map(|u| u.uid)
+        .count()
+        .filter(|(_, count)| *count > 1)
+        .map(|(uid, count)| Err(JoinError::DuplicateUser(uid, count)));
+    let duplicate_province = province
+        .map(|p| p.pid)
+        .count()
+        .filter(|(_, count)| *count > 1)
+        .map(|(pid, count)| Err(JoinError::DuplicateProvince(pid, count)));
+
+    joined
+        .concat(&dangling_uid)
+        .concat(&dangling_pid)
+        .concat(&duplicate_order)
+        .concat(&duplicate_user)
+        .concat(&duplicate_province)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+
+    fn order(oid: u64, uid: u64) -> Order {
+        Order {
+            oid: Oid(oid),
+            price: oid * 10,
+            uid: Uid(uid),
+        }
+    }
+
+    fn user(uid: u64) -> User {
+        User {
+            uid: Uid(uid),
+            pid: Pid(1),
+            padding: String::new(),
+        }
+    }
+
+    fn province() -> Province {
+        Province {
+            pid: Pid(1),
+            name: "p1".to_string(),
+        }
+    }
+
+    #[test]
+    fn errors_are_reported_in_band_and_retracted_once_fixed() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut order_input, mut user_input, mut province_input) = worker.dataflow(|scope| {
+                let (order_input, order) = scope.new_collection();
+                let (user_input, user) = scope.new_collection();
+                let (province_input, province) = scope.new_collection();
+                delta_join_safe(&order, &user, &province)
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (order_input, user_input, province_input)
+            });
+            province_input.insert(province());
+            user_input.insert(user(1));
+            // 用户 2 重复出现, 订单 2 的用户 9 不存在
+            user_input.insert(user(2));
+            user_input.insert(user(2));
+            order_input.insert(order(1, 1));
+            order_input.insert(order(2, 9));
+            // 时刻 1 删除重复的用户, 时刻 2 补上缺失的用户
+            user_input.update_at(user(2), 1, -1);
+            user_input.update_at(user(9), 2, 1);
+            order_input.close();
+            user_input.close();
+            province_input.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        consolidate_updates(&mut output);
+
+        let duplicate = JoinError::DuplicateUser(Uid(2), 2);
+        let dangling = JoinError::DanglingUid(order(2, 9));
+        assert_eq!(
+            output,
+            vec![
+                (Ok((order(1, 1), user(1), province())), 0, 1),
+                (Ok((order(2, 9), user(9), province())), 2, 1),
+                (Err(dangling.clone()), 0, 1),
+                (Err(dangling), 2, -1),
+                (Err(duplicate.clone()), 0, 1),
+                (Err(duplicate), 1, -1),
+            ]
+        );
+    }
+}
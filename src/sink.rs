@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use differential_dataflow::consolidation::consolidate;
+use differential_dataflow::{AsCollection, Collection, ExchangeData};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::dataset::Update;
+use crate::delta_join::{Oid, Order, Province, User};
+use crate::upsert::changes_by_key;
+
+#[cfg(feature = "flight")]
+pub mod flight;
+
+/// 可以接收 join 结果的 key-value 存储
+pub trait JoinSink {
+    fn put(&mut self, key: Oid, value: (User, Province));
+    fn delete(&mut self, key: Oid);
+}
+
+impl JoinSink for BTreeMap<Oid, (User, Province)> {
+    fn put(&mut self, key: Oid, value: (User, Province)) {
+        self.insert(key, value);
+    }
+
+    fn delete(&mut self, key: Oid) {
+        self.remove(&key);
+    }
+}
+
+// 按时间顺序把 join 的 diff 流应用到 `sink` 上, 每个时刻先 consolidate, 再对每一行执行 delete 或者 put:
+// 累积 diff 为正的行执行 put, 为负的行执行 delete。同一时刻同一个 oid 的 retract + insert 最终会留下 insert 的结果。
+// 假设每个 oid 在同一时刻最多只有一条有效的 join 结果
+pub fn apply<J: JoinSink>(updates: Vec<Update<(Order, User, Province)>>, sink: &mut J) {
+    let mut by_time: BTreeMap<u64, Vec<_>> = BTreeMap::new();
+    for (row, time, diff) in updates {
+        by_time.entry(time).or_default().push((row, diff));
+    }
+    for (_, mut batch) in by_time {
+        consolidate(&mut batch);
+        // 先删除后插入, 避免插入的结果被同一时刻的删除覆盖
+        batch.sort_by_key(|(_, diff)| *diff > 0);
+        for ((o, u, p), diff) in batch {
+            if diff > 0 {
+                sink.put(o.oid, (u, p));
+            } else {
+                sink.delete(o.oid);
+            }
+        }
+    }
+}
+
+// 把 join 的 diff 流应用到内存中的 map, 得到每个 oid 最新的 join 结果
+pub fn apply_to_map(
+    updates: Vec<Update<(Order, User, Province)>>,
+    map: &mut BTreeMap<Oid, (User, Province)>,
+) {
+    apply(updates, map)
+}
+
+// 把 join 的 diff 流写成 ndjson, 每行一个更新。 `time_formatter` 为 `None` 时时间戳输出为原始的 u64,
+// 否则输出为格式化之后的字符串, 例如 `time_formatter = Some(&rfc3339)`
+pub fn write_ndjson<W: Write>(
+    writer: &mut W,
+    updates: &[Update<(Order, User, Province)>],
+    time_formatter: Option<&dyn Fn(u64) -> String>,
+) -> io::Result<()> {
+    for ((o, u, p), time, diff) in updates {
+        let time = match time_formatter {
+            Some(f) => Value::String(f(*time)),
+            None => json!(time),
+        };
+        let line = json!({
+            "order": o,
+            "user": u,
+            "province": p,
+            "time": time,
+            "diff": diff,
+        });
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+// 把 join 的 diff 流写成便于阅读的 changelog, 每行的格式为 `<time> <diff> oid=.. uid=.. pid=.. province=..`,
+// `time_formatter` 的含义与 `write_ndjson` 相同
+pub fn write_changelog<W: Write>(
+    writer: &mut W,
+    updates: &[Update<(Order, User, Province)>],
+    time_formatter: Option<&dyn Fn(u64) -> String>,
+) -> io::Result<()> {
+    for ((o, u, p), time, diff) in updates {
+        let time = match time_formatter {
+            Some(f) => f(*time),
+            None => time.to_string(),
+        };
+        writeln!(
+            writer,
+            "{} {:+} oid={} uid={} pid={} province={}",
+            time, diff, o.oid.0, u.uid.0, p.pid.0, p.name
+        )?;
+    }
+    Ok(())
+}
+
+// 把自 epoch 以来的秒数格式化为 RFC3339 (UTC), 例如 `0` ->  `1970-01-01T00:00:00Z`
+pub fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // 参考 http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// 带心跳的输出: `Row` 是原始的数据, `Heartbeat(t)` 表示时刻 `t` 已经结束并且这个时刻没有任何数据变化
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Heartbeat<D> {
+    Row(D),
+    Heartbeat(u64),
+}
+
+// 在 collection 中插入心跳: 每当 frontier 推进, [上一个 frontier, 新的 frontier) 之间每个没有任何数据的时刻都输出一个 `Heartbeat`。
+// frontier 一次跳过很多时刻时心跳的数量也很多, 适合时间是连续的整数 (例如秒) 的情况。
+// 这样下游可以区分 "没有变化" 和 "计算卡住了"。
+// 心跳只是一个标记, 不会被撤回, 下游累积 collection 时应该忽略它们
+pub fn with_heartbeats<S, D>(collection: &Collection<S, D>) -> Collection<S, Heartbeat<D>>
+where
+    S: Scope<Timestamp = u64>,
+    D: ExchangeData,
+{
+    collection
+        .inner
+        .unary_frontier(Pipeline, "Heartbeat", |cap, _| {
+            let mut cap = Some(cap);
+            // 当前 frontier 之后有数据的时刻
+            let mut seen = BTreeSet::new();
+            move |input, output| {
+                input.for_each(|time, data| {
+                    // 一个 batch 中可能有多个时刻的数据, 记录的是数据本身的时间而不是 capability 的时间
+                    seen.extend(data.iter().map(|(_, t, _)| *t));
+                    output.session(&time).give_iterator(
+                        data.iter()
+                            .cloned()
+                            .map(|(d, t, r)| (Heartbeat::Row(d), t, r)),
+                    );
+                });
+                let frontier = input.frontier().frontier().first().copied();
+                if let Some(c) = cap.as_mut() {
+                    let prev = *c.time();
+                    match frontier {
+                        Some(f) if f > prev => {
+                            // frontier 一次越过多个时刻时, 其中每个没有数据的时刻都输出一个心跳
+                            let mut session = output.session(c);
+                            for t in (prev..f).filter(|t| !seen.contains(t)) {
+                                session.give((Heartbeat::Heartbeat(t), t, 1));
+                            }
+                            drop(session);
+                            seen = seen.split_off(&f);
+                            c.downgrade(&f);
+                        }
+                        Some(_) => {}
+                        None => cap = None,
+                    }
+                }
+            }
+        })
+        .as_collection()
+}
+
+// 把 join 的输出转换成复制到外部 SQL 数据库的语句: 插入的行对应 `INSERT INTO joined ...`,
+// 撤回的行对应 `DELETE FROM joined WHERE oid = ...`。每个有变化的时刻输出一个 (时刻, 语句列表), 按照列表的顺序执行即可:
+// 同一时刻先执行所有的 DELETE 再执行 INSERT, 这样修改一行时不会把新插入的行删掉。
+// multiplicity 大于 1 的插入会重复对应的 INSERT; DELETE 会删除这个 oid 的所有行, 每个 oid 只出现一次,
+// 所以假设每个 oid 最多只有一行 join 结果。同一时刻相互抵消的插入和撤回不会产生语句。
+// 语句的顺序是输出的一部分, 所以返回的是每个时刻一条记录的 stream 而不是 collection,
+// 所有的更新都在一个 worker 上汇总
+pub fn to_sql_statements<S>(
+    join_output: &Collection<S, (Order, User, Province)>,
+) -> Stream<S, (u64, Vec<String>)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    changes_by_key(
+        &join_output.map(|row| ((), row)),
+        "SqlStatements",
+        |time, by_key| {
+            let mut rows: Vec<_> = by_key.into_values().flatten().collect();
+            consolidate(&mut rows);
+            let mut deleted: Vec<_> = rows
+                .iter()
+                .filter(|(_, r)| *r < 0)
+                .map(|((o, _, _), _)| o.oid)
+                .collect();
+            deleted.dedup();
+            let mut statements: Vec<_> = deleted
+                .into_iter()
+                .map(|oid| format!("DELETE FROM joined WHERE oid = {};", oid.0))
+                .collect();
+            for ((o, u, p), r) in rows.into_iter().filter(|(_, r)| *r > 0) {
+                let statement = format!(
+                    "INSERT INTO joined (oid, price, uid, pid, province_name) VALUES ({}, {}, {}, {}, '{}');",
+                    o.oid.0,
+                    o.price,
+                    u.uid.0,
+                    p.pid.0,
+                    p.name.replace('\'', "''")
+                );
+                statements.extend(std::iter::repeat(statement).take(r as usize));
+            }
+            (!statements.is_empty()).then_some((time, statements))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::input::InputSession;
+    use timely::dataflow::operators::{Inspect, Probe};
+
+    use super::*;
+    use crate::delta_join::{Pid, Uid, Variant};
+    use crate::gen::{generate, PROVINCES};
+    use crate::harness;
+
+    fn row(oid: u64, price: u64) -> (Order, User, Province) {
+        (
+            Order {
+                oid: Oid(oid),
+                price,
+                uid: Uid(1),
+            },
+            User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            },
+            Province {
+                pid: Pid(1),
+                name: "Xi'an".to_string(),
+            },
+        )
+    }
+
+    #[test]
+    fn heartbeats_on_idle_times() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut input = InputSession::new();
+            let probe = worker.dataflow(|scope| {
+                with_heartbeats(&input.to_collection(scope))
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()))
+                    .probe()
+            });
+            input.insert(10u64);
+            // 时刻 1, 2 没有数据
+            input.advance_to(3);
+            input.flush();
+            worker.step_while(|| probe.less_than(&3));
+            input.insert(30);
+            // 时刻 4 没有数据
+            input.advance_to(5);
+            input.flush();
+            worker.step_while(|| probe.less_than(&5));
+            input.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        output.sort_by_key(|(_, t, _)| *t);
+        assert_eq!(
+            output,
+            vec![
+                (Heartbeat::Row(10), 0, 1),
+                (Heartbeat::Heartbeat(1), 1, 1),
+                (Heartbeat::Heartbeat(2), 2, 1),
+                (Heartbeat::Row(30), 3, 1),
+                (Heartbeat::Heartbeat(4), 4, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn sql_statements_delete_before_insert() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut input = InputSession::new();
+            worker.dataflow(|scope| {
+                to_sql_statements(&input.to_collection(scope))
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()));
+            });
+            input.update_at(row(1, 10), 0, 1);
+            // 修改订单 1 的价格, 同时插入订单 2
+            input.update_at(row(1, 10), 1, -1);
+            input.update_at(row(1, 11), 1, 1);
+            input.update_at(row(2, 20), 1, 1);
+            // 同一时刻插入又撤回, 不产生语句
+            input.update_at(row(3, 30), 1, 1);
+            input.update_at(row(3, 30), 1, -1);
+            input.update_at(row(1, 11), 2, -1);
+            input.update_at(row(2, 20), 2, -1);
+            input.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        output.sort();
+
+        let insert = |oid: u64, price: u64| {
+            format!(
+                "INSERT INTO joined (oid, price, uid, pid, province_name) VALUES ({}, {}, 1, 1, 'Xi''an');",
+                oid, price
+            )
+        };
+        let delete = |oid: u64| format!("DELETE FROM joined WHERE oid = {};", oid);
+        assert_eq!(
+            output,
+            vec![
+                (0, vec![insert(1, 10)]),
+                (1, vec![delete(1), insert(1, 11), insert(2, 20)]),
+                (2, vec![delete(1), delete(2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_matches_consolidated_join() {
+        let dataset = generate(11, 40, 0);
+        // 订单分散在 0..3 插入, 每 4 个订单撤回一个; 每 5 个用户在时刻 2 换到下一个省份
+        let mut orders = Vec::new();
+        for (i, o) in dataset.orders.iter().enumerate() {
+            orders.push((o.clone(), (i % 3) as u64, 1));
+            if i % 4 == 0 {
+                orders.push((o.clone(), 3, -1));
+            }
+        }
+        let mut users = Vec::new();
+        for (i, u) in dataset.users.iter().enumerate() {
+            users.push((u.clone(), 0, 1));
+            if i % 5 == 0 {
+                let moved = User {
+                    pid: Pid((u.pid.0 + 1) % PROVINCES),
+                    ..u.clone()
+                };
+                users.push((u.clone(), 2, -1));
+                users.push((moved, 2, 1));
+            }
+        }
+        let provinces = dataset
+            .provinces
+            .iter()
+            .map(|p| (p.clone(), 0, 1))
+            .collect();
+        let output = harness::run(Variant::Delta, orders, users, provinces);
+
+        let mut map = BTreeMap::new();
+        apply(output.clone(), &mut map);
+
+        let mut rows: Vec<_> = output.into_iter().map(|(row, _, r)| (row, r)).collect();
+        consolidate(&mut rows);
+        let expected: BTreeMap<_, _> = rows
+            .into_iter()
+            .map(|((o, u, p), r)| {
+                assert_eq!(r, 1);
+                (o.oid, (u, p))
+            })
+            .collect();
+        assert!(!expected.is_empty());
+        assert!(expected.len() < dataset.orders.len());
+        assert_eq!(map, expected);
+    }
+
+    #[test]
+    fn rfc3339_known_values() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951782400), "2000-02-29T00:00:00Z");
+        // 2100 年不是闰年, 2 月 28 日之后就是 3 月 1 日
+        assert_eq!(rfc3339(4107546061), "2100-03-01T01:01:01Z");
+    }
+
+    #[test]
+    fn time_formatter_applies_to_ndjson_and_changelog() {
+        let updates = vec![(row(1, 10), 951782400, 1)];
+
+        let mut ndjson = Vec::new();
+        write_ndjson(&mut ndjson, &updates, Some(&rfc3339)).unwrap();
+        let ndjson = String::from_utf8(ndjson).unwrap();
+        assert!(
+            ndjson.contains(r#""time":"2000-02-29T00:00:00Z""#),
+            "{}",
+            ndjson
+        );
+
+        let mut raw = Vec::new();
+        write_ndjson(&mut raw, &updates, None).unwrap();
+        let raw = String::from_utf8(raw).unwrap();
+        assert!(raw.contains(r#""time":951782400"#), "{}", raw);
+
+        let mut changelog = Vec::new();
+        write_changelog(&mut changelog, &updates, Some(&rfc3339)).unwrap();
+        assert_eq!(
+            String::from_utf8(changelog).unwrap(),
+            "2000-02-29T00:00:00Z +1 oid=1 uid=1 pid=1 province=Xi'an\n"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Int64Array, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::dataset::Update;
+use crate::delta_join::{Order, Province, User};
+use crate::export::to_columns;
+
+// 每个时刻结束时 join 结果的快照, 也就是所有时间小于等于这个时刻的更新累积之后的结果, 只保留 multiplicity 非零的行。
+// 只有出现过更新的时刻才有快照
+#[allow(clippy::type_complexity)]
+pub fn snapshots(
+    updates: Vec<Update<(Order, User, Province)>>,
+) -> BTreeMap<u64, Vec<((Order, User, Province), isize)>> {
+    let mut by_time: BTreeMap<u64, Vec<_>> = BTreeMap::new();
+    for (row, time, diff) in updates {
+        by_time.entry(time).or_default().push((row, diff));
+    }
+    let mut state: BTreeMap<(Order, User, Province), isize> = BTreeMap::new();
+    let mut snapshots = BTreeMap::new();
+    for (time, batch) in by_time {
+        for (row, diff) in batch {
+            let count = state.entry(row.clone()).or_insert(0);
+            *count += diff;
+            if *count == 0 {
+                state.remove(&row);
+            }
+        }
+        let snapshot = state.iter().map(|(row, r)| (row.clone(), *r)).collect();
+        snapshots.insert(time, snapshot);
+    }
+    snapshots
+}
+
+// 快照的 schema, 列依次为 oid, price, uid, pid, province_name, multiplicity
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("oid", DataType::UInt64, false),
+        Field::new("price", DataType::UInt64, false),
+        Field::new("uid", DataType::UInt64, false),
+        Field::new("pid", DataType::UInt64, false),
+        Field::new("province_name", DataType::Utf8, false),
+        Field::new("multiplicity", DataType::Int64, false),
+    ]))
+}
+
+// 基于 `export::to_columns` 把一个快照转换成 Arrow 的 RecordBatch
+pub fn to_record_batch(
+    rows: &[((Order, User, Province), isize)],
+) -> Result<RecordBatch, ArrowError> {
+    let columns = to_columns(rows);
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(columns.oid)),
+        Arc::new(UInt64Array::from(columns.price)),
+        Arc::new(UInt64Array::from(columns.uid)),
+        Arc::new(UInt64Array::from(columns.pid)),
+        Arc::new(StringArray::from(columns.province_name)),
+        Arc::new(Int64Array::from(columns.diff)),
+    ];
+    RecordBatch::try_new(schema(), arrays)
+}
+
+/// 通过 Arrow Flight 提供 join 每个时刻的快照:
+/// `list_flights` 列出所有的时刻, 每个时刻一个 flight, ticket 为十进制的时刻, 例如 `b"3"`;
+/// `do_get` 返回这个时刻的快照。 Python 中可以用 `pyarrow.flight` 读取, 然后通过 `to_pandas` 转换成 DataFrame
+#[derive(Clone)]
+pub struct SnapshotService {
+    snapshots: Arc<BTreeMap<u64, RecordBatch>>,
+}
+
+impl SnapshotService {
+    // 根据运行结束之后收集到的 join 输出构建所有的快照
+    pub fn new(updates: Vec<Update<(Order, User, Province)>>) -> Result<Self, ArrowError> {
+        let snapshots = snapshots(updates)
+            .into_iter()
+            .map(|(time, rows)| Ok((time, to_record_batch(&rows)?)))
+            .collect::<Result<_, ArrowError>>()?;
+        Ok(SnapshotService {
+            snapshots: Arc::new(snapshots),
+        })
+    }
+
+    fn flight_info(&self, time: u64) -> Result<FlightInfo, Status> {
+        let batch = self
+            .snapshots
+            .get(&time)
+            .ok_or_else(|| Status::not_found(format!("no snapshot at time {}", time)))?;
+        let info = FlightInfo::new()
+            .try_with_schema(&schema())
+            .map_err(|e| Status::internal(e.to_string()))?
+            .with_descriptor(FlightDescriptor::new_path(vec![time.to_string()]))
+            .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(time.to_string())))
+            .with_total_records(batch.num_rows() as i64);
+        Ok(info)
+    }
+}
+
+// 从 ticket 或者 descriptor 中解析出时刻
+fn parse_time(bytes: &[u8]) -> Result<u64, Status> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| Status::invalid_argument("expected a decimal timestamp"))
+}
+
+fn descriptor_time(descriptor: &FlightDescriptor) -> Result<u64, Status> {
+    match descriptor.path.first() {
+        Some(path) => parse_time(path.as_bytes()),
+        None => parse_time(&descriptor.cmd),
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for SnapshotService {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("handshake"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let infos: Vec<_> = self
+            .snapshots
+            .keys()
+            .map(|time| self.flight_info(*time))
+            .collect();
+        Ok(Response::new(stream::iter(infos).boxed()))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let time = descriptor_time(request.get_ref())?;
+        Ok(Response::new(self.flight_info(time)?))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("poll_flight_info"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("get_schema"))
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let time = parse_time(&request.get_ref().ticket)?;
+        let batch = self
+            .snapshots
+            .get(&time)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("no snapshot at time {}", time)))?;
+        let stream = FlightDataEncoderBuilder::new()
+            .build(stream::iter([Ok(batch)]))
+            .map_err(Status::from);
+        Ok(Response::new(stream.boxed()))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("do_put"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("do_action"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("list_actions"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("do_exchange"))
+    }
+}
+
+// 在 `addr` 上启动 Flight server, 一直运行到出错为止, 需要在 tokio runtime 中调用
+pub async fn serve(
+    service: SnapshotService,
+    addr: SocketAddr,
+) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(FlightServiceServer::new(service))
+        .serve(addr)
+        .await
+}
@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use differential_dataflow::operators::Reduce;
+use differential_dataflow::{Collection, ExchangeData};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+/// 快照的序列化格式
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// 可读性好, 体积最大
+    Json,
+    /// 体积比 JSON 小很多, 适合比较大的数据集
+    Bincode,
+    /// 体积同样比 JSON 小, 整数使用变长编码
+    MessagePack,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Json, Format::Bincode, Format::MessagePack];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Bincode => "bincode",
+            Format::MessagePack => "msgpack",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Bincode(bincode::Error),
+    MessagePackEncode(rmp_serde::encode::Error),
+    MessagePackDecode(rmp_serde::decode::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "io error: {}", e),
+            SnapshotError::Json(e) => write!(f, "json error: {}", e),
+            SnapshotError::Bincode(e) => write!(f, "bincode error: {}", e),
+            SnapshotError::MessagePackEncode(e) => write!(f, "msgpack encode error: {}", e),
+            SnapshotError::MessagePackDecode(e) => write!(f, "msgpack decode error: {}", e),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+// 把 `value` (例如一个 `Dataset`) 以 `format` 格式保存到 `path`, 已经存在的文件会被覆盖
+pub fn save_with<T: Serialize>(
+    path: impl AsRef<Path>,
+    format: Format,
+    value: &T,
+) -> Result<(), SnapshotError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Json => serde_json::to_writer(&mut writer, value).map_err(SnapshotError::Json)?,
+        Format::Bincode => {
+            bincode::serialize_into(&mut writer, value).map_err(SnapshotError::Bincode)?
+        }
+        Format::MessagePack => rmp_serde::encode::write(&mut writer, value)
+            .map_err(SnapshotError::MessagePackEncode)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// 读取 `save_with` 以 `format` 格式保存的快照
+pub fn load_with<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    format: Format,
+) -> Result<T, SnapshotError> {
+    let reader = BufReader::new(File::open(path)?);
+    match format {
+        Format::Json => serde_json::from_reader(reader).map_err(SnapshotError::Json),
+        Format::Bincode => bincode::deserialize_from(reader).map_err(SnapshotError::Bincode),
+        Format::MessagePack => {
+            rmp_serde::decode::from_read(reader).map_err(SnapshotError::MessagePackDecode)
+        }
+    }
+}
+
+// 以 JSON 格式保存
+pub fn save<T: Serialize>(path: impl AsRef<Path>, value: &T) -> Result<(), SnapshotError> {
+    save_with(path, Format::Json, value)
+}
+
+// 读取 JSON 格式的快照
+pub fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, SnapshotError> {
+    load_with(path, Format::Json)
+}
+
+/// `periodic` 输出的完整快照, 其中的行已经 consolidate 并且排好序
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Snapshot<D> {
+    pub rows: Vec<(D, isize)>,
+}
+
+// 每 `every` 个时刻输出一次 collection 的完整内容, 用于只能消费全量刷新而不是增量的下游系统。
+// 时刻 t 的更新会被推迟到不早于 t 的第一个 `every` 的整数倍的时刻, 在这些时刻上撤回上一个快照并输出新的快照,
+// 所以任何时候 collection 中最多只有一个快照。内容没有变化的时刻不会产生新的快照; 内容为空时不输出快照。
+// 所有的行都在一个 worker 上汇总, 只适合结果比较小的情况
+pub fn periodic<S, D>(join_output: &Collection<S, D>, every: u64) -> Collection<S, Snapshot<D>>
+where
+    S: Scope<Timestamp = u64>,
+    D: ExchangeData,
+{
+    assert!(every > 0, "every must be positive");
+    join_output
+        .delay(move |t| t.div_ceil(every).saturating_mul(every))
+        .map(|d| ((), d))
+        .reduce(|_, input, output| {
+            let rows: Vec<_> = input.iter().map(|(d, r)| ((*d).clone(), *r)).collect();
+            if !rows.is_empty() {
+                output.push((Snapshot { rows }, 1));
+            }
+        })
+        .map(|(_, snapshot)| snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+
+    use super::*;
+    use crate::dataset::{Dataset, Update};
+    use crate::delta_join::{Order, Province, User, Variant};
+    use crate::gen::generate;
+    use crate::harness;
+
+    fn at_zero<D: Clone>(rows: &[D]) -> Vec<Update<D>> {
+        rows.iter().cloned().map(|d| (d, 0, 1)).collect()
+    }
+
+    fn join(dataset: &Dataset) -> Vec<Update<(Order, User, Province)>> {
+        harness::run(
+            Variant::Delta,
+            at_zero(&dataset.orders),
+            at_zero(&dataset.users),
+            at_zero(&dataset.provinces),
+        )
+    }
+
+    #[test]
+    fn round_trip_every_format() {
+        let dir = std::env::temp_dir().join(format!("dd_examples_snapshot_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dataset = generate(0, 50, 16);
+        let expected = join(&dataset);
+
+        let mut sizes = Vec::new();
+        for format in Format::ALL {
+            let path = dir.join(format!("dataset.{}", format.name()));
+            save_with(&path, format, &dataset).unwrap();
+            let reloaded: Dataset = load_with(&path, format).unwrap();
+            assert_eq!(reloaded, dataset, "{}", format.name());
+            assert_eq!(join(&reloaded), expected, "{}", format.name());
+            let size = fs::metadata(&path).unwrap().len();
+            println!("{:<8} {:>8} bytes", format.name(), size);
+            sizes.push((format, size));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let size = |f| sizes.iter().find(|(format, _)| *format == f).unwrap().1;
+        assert!(size(Format::Bincode) < size(Format::Json));
+        assert!(size(Format::MessagePack) < size(Format::Json));
+    }
+
+    #[test]
+    fn periodic_snapshots_at_multiples_of_every() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut input = worker.dataflow(|scope| {
+                let (input, collection) = scope.new_collection::<u64, isize>();
+                periodic(&collection, 2).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                input
+            });
+            input.update_at(1, 0, 1);
+            input.update_at(2, 1, 1);
+            input.update_at(3, 3, 1);
+            input.update_at(1, 4, -1);
+            input.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        consolidate_updates(&mut output);
+
+        let snapshot = |rows: &[u64]| Snapshot {
+            rows: rows.iter().map(|d| (*d, 1)).collect(),
+        };
+        // 时刻 1 和 3 的更新分别推迟到 2 和 4, 每个新的快照都会撤回上一个快照
+        assert_eq!(
+            output,
+            vec![
+                (snapshot(&[1]), 0, 1),
+                (snapshot(&[1]), 2, -1),
+                (snapshot(&[1, 2]), 2, 1),
+                (snapshot(&[1, 2]), 4, -1),
+                (snapshot(&[2, 3]), 4, 1),
+            ]
+        );
+        assert!(output.iter().all(|(_, t, _)| t % 2 == 0));
+    }
+}
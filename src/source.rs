@@ -0,0 +1,197 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use differential_dataflow::input::InputSession;
+use differential_dataflow::Collection;
+use futures::stream::{Stream, StreamExt};
+use timely::dataflow::operators::Input;
+use timely::dataflow::Scope;
+
+use crate::dataset::InputOp;
+use crate::delta_join::{Order, Province, User};
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+/// join 的三个 input
+pub struct Inputs {
+    pub order: InputSession<u64, Order, isize>,
+    pub user: InputSession<u64, User, isize>,
+    pub province: InputSession<u64, Province, isize>,
+}
+
+impl Default for Inputs {
+    fn default() -> Self {
+        Inputs {
+            order: InputSession::new(),
+            user: InputSession::new(),
+            province: InputSession::new(),
+        }
+    }
+}
+
+impl Inputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn to_collections<G>(
+        &mut self,
+        scope: &mut G,
+    ) -> (
+        Collection<G, Order>,
+        Collection<G, User>,
+        Collection<G, Province>,
+    )
+    where
+        G: Input + Scope<Timestamp = u64>,
+    {
+        (
+            self.order.to_collection(scope),
+            self.user.to_collection(scope),
+            self.province.to_collection(scope),
+        )
+    }
+
+    // 当前的时间, 三个 input 总是一起推进, 所以时间相同
+    pub fn time(&self) -> u64 {
+        *self.order.time()
+    }
+
+    // 应用一个更新, 更新的时间早于当前时间时会被推迟到当前时间
+    pub fn apply(&mut self, op: InputOp) {
+        let now = self.time();
+        match op {
+            InputOp::Order(d, t, r) => self.order.update_at(d, t.max(now), r),
+            InputOp::User(d, t, r) => self.user.update_at(d, t.max(now), r),
+            InputOp::Province(d, t, r) => self.province.update_at(d, t.max(now), r),
+        }
+    }
+
+    pub fn advance_to(&mut self, time: u64) {
+        self.order.advance_to(time);
+        self.user.advance_to(time);
+        self.province.advance_to(time);
+    }
+
+    pub fn flush(&mut self) {
+        self.order.flush();
+        self.user.flush();
+        self.province.flush();
+    }
+
+    pub fn close(self) {
+        self.order.close();
+        self.user.close();
+        self.province.close();
+    }
+}
+
+/// `from_stream` 返回的异步数据源, 在 worker 线程中调用 `feed` 把已经到达的更新输入到 `Inputs`
+pub struct StreamSource {
+    receiver: Receiver<InputOp>,
+}
+
+// 在一个单独的线程中驱动异步的 `stream`, 通过 channel 把其中的更新转交给 timely worker 所在的线程。
+// 要求 stream 中更新的时间不递减, 否则较早的更新会被推迟到当前时间
+pub fn from_stream<St>(stream: St) -> StreamSource
+where
+    St: Stream<Item = InputOp> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        futures::executor::block_on(stream.for_each(|op| {
+            // worker 一侧已经 drop 了 StreamSource, 剩余的更新直接丢弃
+            let _ = sender.send(op);
+            futures::future::ready(())
+        }))
+    });
+    StreamSource { receiver }
+}
+
+impl StreamSource {
+    // 把已经到达的所有更新输入到 `inputs`, 并把 input 推进到最后一个更新的时间 (这个时刻还可能有更新到达)。
+    // 不会阻塞, 返回 stream 是否还没有结束, 结束之后调用方可以关闭 input
+    pub fn feed(&mut self, inputs: &mut Inputs) -> bool {
+        let mut latest = None;
+        let alive = loop {
+            match self.receiver.try_recv() {
+                Ok(op) => {
+                    latest = latest.max(Some(op.time()));
+                    inputs.apply(op);
+                }
+                Err(TryRecvError::Empty) => break true,
+                Err(TryRecvError::Disconnected) => break false,
+            }
+        };
+        if let Some(time) = latest {
+            if time > inputs.time() {
+                inputs.advance_to(time);
+            }
+        }
+        inputs.flush();
+        alive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+
+    use super::*;
+    use crate::delta_join::{delta_join, Oid, Pid, Uid};
+
+    #[test]
+    fn join_consumes_async_stream() {
+        let order = |oid| Order {
+            oid: Oid(oid),
+            price: oid * 10,
+            uid: Uid(1),
+        };
+        let user = User {
+            uid: Uid(1),
+            pid: Pid(1),
+            padding: String::new(),
+        };
+        let province = Province {
+            pid: Pid(1),
+            name: "p1".to_string(),
+        };
+        let ops = vec![
+            InputOp::Province(province.clone(), 0, 1),
+            InputOp::User(user.clone(), 0, 1),
+            InputOp::Order(order(1), 0, 1),
+            InputOp::Order(order(2), 1, 1),
+            InputOp::Order(order(1), 2, -1),
+        ];
+        let mut output = timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let mut inputs = Inputs::new();
+            let sink = output.clone();
+            worker.dataflow(|scope| {
+                let (o, u, p) = inputs.to_collections(scope);
+                delta_join(&o, &u, &p).inspect(move |x| sink.borrow_mut().push(x.clone()));
+            });
+
+            let mut source = from_stream(futures::stream::iter(ops));
+            while source.feed(&mut inputs) {
+                worker.step();
+            }
+            inputs.close();
+            while worker.step() {}
+            output.take()
+        });
+        consolidate_updates(&mut output);
+        let row = |oid| (order(oid), user.clone(), province.clone());
+        assert_eq!(
+            output,
+            vec![(row(1), 0, 1), (row(1), 2, -1), (row(2), 1, 1)]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::Inputs;
+use crate::dataset::InputOp;
+use crate::delta_join::{Order, Province, User};
+
+/// 从 topic 中读到的一条消息
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub offset: i64,
+    pub payload: Vec<u8>,
+}
+
+/// 消息的来源, 真实的 Kafka consumer 是 rdkafka 的 `BaseConsumer`, 测试时可以使用 `VecSource`
+pub trait MessageSource {
+    // 返回下一条消息, 暂时没有消息时返回 None
+    fn poll(&mut self) -> Option<Message>;
+}
+
+/// 内存中的消息队列, 用来代替 Kafka
+#[derive(Clone, Debug, Default)]
+pub struct VecSource {
+    messages: std::collections::VecDeque<Message>,
+}
+
+impl VecSource {
+    pub fn new(messages: Vec<Message>) -> Self {
+        VecSource {
+            messages: messages.into(),
+        }
+    }
+}
+
+impl MessageSource for VecSource {
+    fn poll(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+}
+
+/// 时间戳的来源
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Timestamping {
+    /// 使用消息的 offset 作为时间戳, 只适用于每个 topic 只有一个 partition 的情况。
+    /// 注意三个 topic 的 offset 是互相独立的, 并不在同一个时间域中: 用户 topic 中 offset 很小的消息
+    /// 可能是在订单 topic 中 offset 很大的消息之后才产生的, 这时 join 会把它当作更早的更新。
+    /// 所以只有当不同 topic 之间的先后顺序无关紧要时才能使用, 例如用户和省份在订单之前已经全部写入,
+    /// 之后只有订单 topic 有新的消息; 否则应该使用 `EventTime`
+    Offset,
+    /// 使用消息中的 `event_time` 字段, 迟到的消息会被推迟到当前时间
+    EventTime,
+}
+
+/// Kafka 的配置
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    pub group_id: String,
+    pub order_topic: String,
+    pub user_topic: String,
+    pub province_topic: String,
+    pub timestamping: Timestamping,
+}
+
+/// 消息的内容, 例如 `{"data": {"oid": 1, "price": 100, "uid": 1}, "diff": 1, "event_time": 3}`
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Event<D> {
+    pub data: D,
+    /// 默认为 1, 也就是插入
+    #[serde(default = "one")]
+    pub diff: isize,
+    #[serde(default)]
+    pub event_time: Option<u64>,
+}
+
+fn one() -> isize {
+    1
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    UnknownTopic(String),
+    Json(serde_json::Error),
+    MissingEventTime { topic: String, offset: i64 },
+    NegativeOffset { topic: String, offset: i64 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownTopic(t) => write!(f, "unknown topic {:?}", t),
+            DecodeError::Json(e) => write!(f, "invalid message: {}", e),
+            DecodeError::MissingEventTime { topic, offset } => {
+                write!(f, "message {}@{} has no event_time", topic, offset)
+            }
+            DecodeError::NegativeOffset { topic, offset } => {
+                write!(f, "message {}@{} has a negative offset", topic, offset)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+impl From<serde_json::Error> for DecodeError {
+    fn from(e: serde_json::Error) -> Self {
+        DecodeError::Json(e)
+    }
+}
+
+impl KafkaConfig {
+    fn time(&self, message: &Message, event_time: Option<u64>) -> Result<u64, DecodeError> {
+        match self.timestamping {
+            Timestamping::Offset => {
+                u64::try_from(message.offset).map_err(|_| DecodeError::NegativeOffset {
+                    topic: message.topic.clone(),
+                    offset: message.offset,
+                })
+            }
+            Timestamping::EventTime => event_time.ok_or_else(|| DecodeError::MissingEventTime {
+                topic: message.topic.clone(),
+                offset: message.offset,
+            }),
+        }
+    }
+
+    // 根据 topic 把消息解码成对应 input 的更新
+    pub fn decode(&self, message: &Message) -> Result<InputOp, DecodeError> {
+        if message.topic == self.order_topic {
+            let e: Event<Order> = serde_json::from_slice(&message.payload)?;
+            Ok(InputOp::Order(
+                e.data,
+                self.time(message, e.event_time)?,
+                e.diff,
+            ))
+        } else if message.topic == self.user_topic {
+            let e: Event<User> = serde_json::from_slice(&message.payload)?;
+            Ok(InputOp::User(
+                e.data,
+                self.time(message, e.event_time)?,
+                e.diff,
+            ))
+        } else if message.topic == self.province_topic {
+            let e: Event<Province> = serde_json::from_slice(&message.payload)?;
+            Ok(InputOp::Province(
+                e.data,
+                self.time(message, e.event_time)?,
+                e.diff,
+            ))
+        } else {
+            Err(DecodeError::UnknownTopic(message.topic.clone()))
+        }
+    }
+}
+
+// 从 `source` 中读取消息并输入到 `inputs`, 直到暂时没有新的消息为止, 返回处理的消息数量。
+// 每处理完一批消息, input 会被推进到这批消息中最早的时间 (已经输入的更新不会早于它), 并调用 `on_batch`,
+// 调用方可以在其中驱动 worker, 例如 `worker.step()`
+pub fn consume<C: MessageSource>(
+    source: &mut C,
+    config: &KafkaConfig,
+    inputs: &mut Inputs,
+    batch_size: usize,
+    mut on_batch: impl FnMut(&mut Inputs),
+) -> Result<usize, DecodeError> {
+    let mut count = 0;
+    loop {
+        let mut batch = Vec::with_capacity(batch_size);
+        while batch.len() < batch_size.max(1) {
+            match source.poll() {
+                Some(message) => batch.push(config.decode(&message)?),
+                None => break,
+            }
+        }
+        if batch.is_empty() {
+            return Ok(count);
+        }
+        count += batch.len();
+        let earliest = batch.iter().map(InputOp::time).min().unwrap();
+        batch.into_iter().for_each(|op| inputs.apply(op));
+        if earliest > inputs.time() {
+            inputs.advance_to(earliest);
+        }
+        inputs.flush();
+        on_batch(inputs);
+    }
+}
+
+mod consumer {
+    use std::time::Duration;
+
+    use rdkafka::consumer::{BaseConsumer, Consumer};
+    use rdkafka::error::KafkaResult;
+    use rdkafka::{ClientConfig, Message as _};
+
+    use super::{KafkaConfig, Message, MessageSource};
+
+    impl KafkaConfig {
+        // 创建订阅了三个 topic 的 consumer
+        pub fn consumer(&self) -> KafkaResult<BaseConsumer> {
+            let consumer: BaseConsumer = ClientConfig::new()
+                .set("bootstrap.servers", &self.brokers)
+                .set("group.id", &self.group_id)
+                .set("enable.auto.commit", "false")
+                .set("auto.offset.reset", "earliest")
+                .create()?;
+            consumer.subscribe(&[&self.order_topic, &self.user_topic, &self.province_topic])?;
+            Ok(consumer)
+        }
+    }
+
+    impl MessageSource for BaseConsumer {
+        fn poll(&mut self) -> Option<Message> {
+            match BaseConsumer::poll(self, Duration::from_millis(100))? {
+                Ok(m) => Some(Message {
+                    topic: m.topic().to_string(),
+                    offset: m.offset(),
+                    payload: m.payload().unwrap_or_default().to_vec(),
+                }),
+                Err(e) => {
+                    eprintln!("kafka error: {}", e);
+                    None
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::Inputs;
+use crate::dataset::InputOp;
+use crate::delta_join::Province;
+
+/// `watch_dir` 返回的数据源, 目录中的每个 `.json` 文件是一个省份, 例如 `{"pid": 1, "name": "Beijing"}`
+pub struct DirSource {
+    // 持有 watcher 才会继续收到事件
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    // 还没有同步到 input 的文件, 第一次 `feed` 时包括目录中已经存在的所有文件
+    pending: Vec<PathBuf>,
+    // 每个文件当前对应的省份, 也就是已经输入到 join 中的省份
+    current: BTreeMap<PathBuf, Province>,
+}
+
+// 监听目录 `path` 中的省份文件: 文件的新增, 修改和删除会在 `feed` 时变成对省份 input 的插入和撤回,
+// 使省份这个维度表与文件系统保持一致
+pub fn watch_dir(path: impl AsRef<Path>) -> notify::Result<DirSource> {
+    let path = path.as_ref();
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(path, RecursiveMode::NonRecursive)?;
+    let pending = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    Ok(DirSource {
+        _watcher: watcher,
+        events,
+        pending,
+        current: BTreeMap::new(),
+    })
+}
+
+// 读取一个省份文件, 文件不存在, 不是 `.json` 文件或者无法解析时返回 None, 相当于这个文件中没有省份
+fn read_province(path: &Path) -> Option<Province> {
+    if path.extension().is_none_or(|ext| ext != "json") {
+        return None;
+    }
+    let content = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(province) => Some(province),
+        Err(e) => {
+            eprintln!("ignore invalid province file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+impl DirSource {
+    // 把到目前为止发生变化的文件同步到 `inputs` 的当前时刻: 撤回文件原来的省份, 插入文件现在的省份。
+    // 不会阻塞, 也不会推进 input 的时间, 返回输入的更新数量
+    pub fn feed(&mut self, inputs: &mut Inputs) -> usize {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) => self.pending.extend(event.paths),
+                Err(e) => eprintln!("watch error: {}", e),
+            }
+        }
+        let mut paths = std::mem::take(&mut self.pending);
+        paths.sort();
+        paths.dedup();
+
+        let time = inputs.time();
+        let mut count = 0;
+        for path in paths {
+            let new = read_province(&path);
+            if self.current.get(&path) == new.as_ref() {
+                continue;
+            }
+            if let Some(old) = self.current.remove(&path) {
+                inputs.apply(InputOp::Province(old, time, -1));
+                count += 1;
+            }
+            if let Some(new) = new {
+                self.current.insert(path, new.clone());
+                inputs.apply(InputOp::Province(new, time, 1));
+                count += 1;
+            }
+        }
+        count
+    }
+}
@@ -0,0 +1,279 @@
+use differential_dataflow::input::InputSession;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::{Join, Reduce};
+use differential_dataflow::{Collection, ExchangeData};
+use serde::{Deserialize, Serialize};
+use timely::dataflow::operators::{Filter, Input, Map};
+use timely::dataflow::{Scope, Stream};
+
+use crate::delta_join::{JoinedRow, Order, Pid, Province, User};
+
+/// 事件时间: 事件在现实中发生的时间, 只作为数据的一部分, 从不作为 dataflow 的时间戳
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct EventTime(pub u64);
+
+/// 系统时间: 数据进入系统的时间, 也就是 dataflow 的时间戳
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SystemTime(pub u64);
+
+impl From<SystemTime> for u64 {
+    fn from(t: SystemTime) -> u64 {
+        t.0
+    }
+}
+
+/// 携带事件时间的记录
+#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Timed<D> {
+    pub data: D,
+    pub event_time: EventTime,
+}
+
+/// 只接受类型化时间的 input, 两个时间的类型不同, 传反了会在编译时报错:
+/// ```compile_fail
+/// use dd_examples::delta_join::{Oid, Order, Uid};
+/// use dd_examples::time::{EventTime, SystemTime, TimedInput};
+///
+/// let mut input = TimedInput::<Order>::default();
+/// let order = Order { oid: Oid(1), price: 1, uid: Uid(1) };
+/// input.insert(order, SystemTime(1), EventTime(5)); // expected `EventTime`, found `SystemTime`
+/// ```
+/// 顺序正确时可以编译:
+/// ```no_run
+/// use dd_examples::delta_join::{Oid, Order, Uid};
+/// use dd_examples::time::{EventTime, SystemTime, TimedInput};
+///
+/// let mut input = TimedInput::<Order>::default();
+/// let order = Order { oid: Oid(1), price: 1, uid: Uid(1) };
+/// input.insert(order, EventTime(5), SystemTime(1));
+/// ```
+pub struct TimedInput<D: ExchangeData> {
+    session: InputSession<u64, Timed<D>, isize>,
+}
+
+impl<D: ExchangeData> Default for TimedInput<D> {
+    fn default() -> Self {
+        TimedInput {
+            session: InputSession::new(),
+        }
+    }
+}
+
+impl<D: ExchangeData> TimedInput<D> {
+    pub fn to_collection<G>(&mut self, scope: &mut G) -> Collection<G, Timed<D>>
+    where
+        G: Input + Scope<Timestamp = u64>,
+    {
+        self.session.to_collection(scope)
+    }
+
+    // 在系统时间 `system_time` 插入一条事件时间为 `event_time` 的记录
+    pub fn insert(&mut self, data: D, event_time: EventTime, system_time: SystemTime) {
+        self.update(data, event_time, system_time, 1)
+    }
+
+    pub fn remove(&mut self, data: D, event_time: EventTime, system_time: SystemTime) {
+        self.update(data, event_time, system_time, -1)
+    }
+
+    pub fn update(&mut self, data: D, event_time: EventTime, system_time: SystemTime, diff: isize) {
+        self.session
+            .update_at(Timed { data, event_time }, system_time.into(), diff)
+    }
+
+    pub fn advance_to(&mut self, system_time: SystemTime) {
+        self.session.advance_to(system_time.into());
+    }
+
+    pub fn flush(&mut self) {
+        self.session.flush();
+    }
+
+    pub fn close(self) {
+        self.session.close();
+    }
+}
+
+// 与 `regular_join` 相同, 订单额外携带了事件时间, 输出中保留了订单的事件时间, dataflow 的时间戳依然是系统时间
+pub fn regular_join_timed<S>(
+    order: &Collection<S, Timed<Order>>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, ((Order, User, Province), EventTime)>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    order
+        .map(|o| (o.data.uid, o))
+        .join_map(&user.map(|u| (u.uid, u)), |_, o, u| {
+            (u.pid, (o.clone(), u.clone()))
+        })
+        .join_map(&province.map(|p| (p.pid, p)), |_, (o, u), p| {
+            ((o.data.clone(), u.clone(), p.clone()), o.event_time)
+        })
+}
+
+// 在 `regular_join_timed` 的基础上计算每一行的延迟: 输出时的系统时间 (dataflow 时间戳) 减去订单的事件时间。
+// 延迟只在一行被插入时计算一次, 所以输出的是延迟事件的 `Stream` 而不是 collection: 撤回不产生事件,
+// 否则撤回发生在另一个时刻时会带着不同的延迟, 无法与插入相互抵消。
+// multiplicity 大于 1 的插入也只产生一个事件; 事件时间晚于系统时间时延迟记为 0
+pub fn join_with_latency<S>(
+    order: &Collection<S, Timed<Order>>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Stream<S, (JoinedRow, u64)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    regular_join_timed(order, user, province)
+        .inner
+        .filter(|(_, _, r)| *r > 0)
+        .map(|((row, event_time), t, _)| (JoinedRow::from(row), t.saturating_sub(event_time.0)))
+}
+
+// 每个省份订单事件时间的范围 (最早, 最晚), 用来监控每个省份数据的新鲜程度。
+// 事件时间作为数据的一部分参与 reduce, 撤回最早或者最晚的订单时范围会根据剩下的订单重新计算,
+// 省份的订单全部被撤回时, 这个省份会从结果中消失
+pub fn province_time_span<S>(
+    order: &Collection<S, Timed<Order>>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Pid, (u64, u64))>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+{
+    regular_join_timed(order, user, province)
+        .map(|((_, _, p), event_time)| (p.pid, event_time.0))
+        .reduce(|_, input, output| {
+            // input 按照事件时间从小到大排列
+            let earliest = *input.first().unwrap().0;
+            let latest = *input.last().unwrap().0;
+            output.push(((earliest, latest), 1));
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use timely::dataflow::operators::Inspect;
+
+    use super::*;
+    use crate::delta_join::{Oid, Uid};
+
+    fn order(oid: u64, uid: u64) -> Order {
+        Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(uid),
+        }
+    }
+
+    fn user(uid: u64) -> User {
+        User {
+            uid: Uid(uid),
+            pid: Pid(1),
+            padding: String::new(),
+        }
+    }
+
+    fn province() -> Province {
+        Province {
+            pid: Pid(1),
+            name: "p".to_string(),
+        }
+    }
+
+    #[test]
+    fn latency_is_output_time_minus_event_time() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut orders = TimedInput::default();
+            let mut users = InputSession::new();
+            let mut provinces = InputSession::new();
+            worker.dataflow(|scope| {
+                join_with_latency(
+                    &orders.to_collection(scope),
+                    &users.to_collection(scope),
+                    &provinces.to_collection(scope),
+                )
+                .inspect_time(move |t, x| sink.borrow_mut().push((*t, x.clone())));
+            });
+            users.update_at(user(1), 0, 1);
+            provinces.update_at(province(), 0, 1);
+            orders.insert(order(1, 1), EventTime(2), SystemTime(5));
+            // 事件时间晚于系统时间
+            orders.insert(order(2, 1), EventTime(9), SystemTime(6));
+            // 撤回不产生延迟事件
+            orders.remove(order(1, 1), EventTime(2), SystemTime(8));
+            // 用户在时刻 4 才出现, 订单在时刻 4 才输出
+            orders.insert(order(3, 2), EventTime(0), SystemTime(1));
+            users.update_at(user(2), 4, 1);
+            orders.close();
+            users.close();
+            provinces.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        output.sort_by_key(|(t, _)| *t);
+        let row = |oid, uid| JoinedRow::from((order(oid, uid), user(uid), province()));
+        assert_eq!(
+            output,
+            vec![
+                (4, (row(3, 2), 4)),
+                (5, (row(1, 1), 3)),
+                (6, (row(2, 1), 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn time_span_recomputed_when_earliest_retracts() {
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut orders = TimedInput::default();
+            let mut users = InputSession::new();
+            let mut provinces = InputSession::new();
+            worker.dataflow(|scope| {
+                province_time_span(
+                    &orders.to_collection(scope),
+                    &users.to_collection(scope),
+                    &provinces.to_collection(scope),
+                )
+                .inspect(move |x| sink.borrow_mut().push(x.clone()));
+            });
+            users.update_at(user(1), 0, 1);
+            provinces.update_at(province(), 0, 1);
+            for (oid, event_time) in [(1, 3), (2, 7), (3, 5)] {
+                orders.insert(order(oid, 1), EventTime(event_time), SystemTime(0));
+            }
+            // 时刻 1: 撤回最早的订单, 范围根据剩下的订单重新计算
+            orders.remove(order(1, 1), EventTime(3), SystemTime(1));
+            // 时刻 2: 订单全部撤回, 省份从结果中消失
+            orders.remove(order(2, 1), EventTime(7), SystemTime(2));
+            orders.remove(order(3, 1), EventTime(5), SystemTime(2));
+            orders.close();
+            users.close();
+            provinces.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        differential_dataflow::consolidation::consolidate_updates(&mut output);
+        assert_eq!(
+            output,
+            vec![
+                ((Pid(1), (3, 7)), 0, 1),
+                ((Pid(1), (3, 7)), 1, -1),
+                ((Pid(1), (5, 7)), 1, 1),
+                ((Pid(1), (5, 7)), 2, -1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::operators::arrange::ArrangeByKey;
+use differential_dataflow::{Collection, ExchangeData};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::Data;
+
+use crate::delta_join::{Oid, Order, Province, User};
+
+// 把 join 的 diff 流转换成以 oid 为 key 的 upsert 流: 每个时刻每个有变化的 oid 只产生一条记录,
+// `Some(row)` 表示 oid 对应的最新结果, `None` 表示 oid 在这个时刻被删除。
+// 同一时刻的 retract + insert (比如订单所属的省份发生了变化) 会被合并成一条 `Some`。
+// 正常情况下每个 oid 在同一时刻最多只有一条有效的 join 结果; 如果有多条, 取其中最大的一条,
+// 这样结果与更新到达的顺序无关
+pub fn to_upserts<S>(
+    join_output: &Collection<S, (Order, User, Province)>,
+) -> Stream<S, (Oid, Option<(Order, User, Province)>)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    changes_by_key(
+        &join_output.map(|row| (row.0.oid, row)),
+        "Upsert",
+        |_, by_oid| {
+            by_oid.into_iter().map(|(oid, rows)| {
+                let upsert = rows
+                    .into_iter()
+                    .filter(|(_, diff)| *diff > 0)
+                    .map(|(row, _)| row)
+                    .max();
+                (oid, upsert)
+            })
+        },
+    )
+}
+
+// 以 key 做 arrange, 一方面同一时间的更新会被 consolidate, 另一方面保证同一个 key 的数据都在同一个 worker 上。
+// 每个时刻完成之后, 把这个时刻所有的更新按照 key 分组 (value 和 diff), 交给 `logic` 生成输出,
+// `to_upserts` 和 `cdc` 中的变化流都是基于这个算子实现的
+pub fn changes_by_key<S, K, V, D, I, L>(
+    collection: &Collection<S, (K, V)>,
+    name: &str,
+    mut logic: L,
+) -> Stream<S, D>
+where
+    S: Scope<Timestamp = u64>,
+    K: ExchangeData + Hashable,
+    V: ExchangeData,
+    D: Data,
+    I: IntoIterator<Item = D>,
+    L: FnMut(u64, BTreeMap<K, Vec<(V, isize)>>) -> I + 'static,
+{
+    let consolidated = collection
+        .arrange_by_key()
+        .as_collection(|k, v| (k.clone(), v.clone()));
+
+    // 按照每个更新自己的时间分组, 一个 batch 中可能包含同一个 capability 之后的多个时刻
+    let mut stash: HashMap<u64, BTreeMap<K, Vec<(V, isize)>>> = HashMap::new();
+    consolidated
+        .inner
+        .unary_notify(Pipeline, name, None, move |input, output, notificator| {
+            input.for_each(|time, data| {
+                for ((k, v), t, diff) in data.iter().cloned() {
+                    // 每个时刻只在第一次出现时注册一次通知
+                    stash
+                        .entry(t)
+                        .or_insert_with(|| {
+                            notificator.notify_at(time.delayed(&t));
+                            BTreeMap::new()
+                        })
+                        .entry(k)
+                        .or_default()
+                        .push((v, diff));
+                }
+            });
+            notificator.for_each(|time, _, _| {
+                if let Some(by_key) = stash.remove(time.time()) {
+                    output
+                        .session(&time)
+                        .give_iterator(logic(*time.time(), by_key).into_iter());
+                }
+            });
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::input::Input;
+    use timely::dataflow::operators::Inspect;
+
+    use super::*;
+    use crate::delta_join::{Pid, Uid};
+
+    fn row(pid: u64) -> (Order, User, Province) {
+        (
+            Order {
+                oid: Oid(1),
+                price: 10,
+                uid: Uid(1),
+            },
+            User {
+                uid: Uid(1),
+                pid: Pid(pid),
+                padding: String::new(),
+            },
+            Province {
+                pid: Pid(pid),
+                name: format!("p{}", pid),
+            },
+        )
+    }
+
+    // 依次输入 `rows` 中的更新, 返回 (时间, upsert)。
+    // 中间没有 flush, 所有的更新在 close 时一起发送, 同一个 capability 下会包含多个时刻的更新
+    #[allow(clippy::type_complexity)]
+    fn upserts(
+        rows: Vec<((Order, User, Province), u64, isize)>,
+    ) -> Vec<(u64, (Oid, Option<(Order, User, Province)>))> {
+        timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let mut input = worker.dataflow(|scope| {
+                let (input, rows) = scope.new_collection();
+                to_upserts(&rows).inspect_time(move |t, x| sink.borrow_mut().push((*t, x.clone())));
+                input
+            });
+            for (row, time, diff) in rows.clone() {
+                input.advance_to(time);
+                input.update(row, diff);
+            }
+            input.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        })
+    }
+
+    #[test]
+    fn province_change_is_a_single_upsert() {
+        let output = upserts(vec![
+            (row(1), 0, 1),
+            (row(1), 1, -1),
+            (row(2), 1, 1),
+            (row(2), 2, -1),
+        ]);
+        assert_eq!(
+            output,
+            vec![
+                (0, (Oid(1), Some(row(1)))),
+                (1, (Oid(1), Some(row(2)))),
+                (2, (Oid(1), None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicting_rows_pick_the_largest() {
+        let expected = vec![(0, (Oid(1), Some(row(2))))];
+        assert_eq!(upserts(vec![(row(1), 0, 1), (row(2), 0, 1)]), expected);
+        assert_eq!(upserts(vec![(row(2), 0, 1), (row(1), 0, 1)]), expected);
+    }
+}
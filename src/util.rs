@@ -0,0 +1,191 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::Threshold;
+use differential_dataflow::{Collection, ExchangeData};
+use serde::{Deserialize, Serialize};
+use timely::dataflow::Scope;
+
+use crate::delta_join::Province;
+
+// 在进入 join 之前合并同一时刻的更新, 例如同一时刻插入之后又撤回的同一条订单会直接相互抵消,
+// 不会再进入下游的 arrangement 和 half_join。
+// `consolidate` 需要等到 frontier 越过某个时刻之后才会输出这个时刻的结果, 所以会增加一个时刻的延迟
+pub fn coalesce<S, D>(collection: &Collection<S, D>) -> Collection<S, D>
+where
+    S: Scope<Timestamp = u64>,
+    D: ExchangeData + Hashable,
+{
+    collection.consolidate()
+}
+
+// 省份名称的规范形式: 去掉首尾空白, 首字母大写, 其余字母小写, 例如 "beijing" 和 "BEIJING" 都变为 "Beijing"
+pub fn canonical_name(name: &str) -> String {
+    let mut chars = name.trim().chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+// 在进入 join 之前用 `normalizer` 规范化省份名称, 例如 `normalize_province_names(&province, canonical_name)`。
+// 规范化之后完全相同的省份会被去重, 所以同一个省份以不同的大小写多次出现时, join 和下游的聚合只会看到一行
+pub fn normalize_province_names<S, F>(
+    province: &Collection<S, Province>,
+    normalizer: F,
+) -> Collection<S, Province>
+where
+    S: Scope,
+    S::Timestamp: Lattice,
+    F: Fn(&str) -> String + 'static,
+{
+    province
+        .map(move |p| Province {
+            name: normalizer(&p.name),
+            ..p
+        })
+        .distinct()
+}
+
+/// 可以放进 collection 中的 f64, collection 中的数据需要满足 `Ord`, 这里按照 `f64::total_cmp` 排序
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct OrdF64(pub f64);
+
+impl PartialEq for OrdF64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OrdF64 {}
+
+impl PartialOrd for OrdF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrdF64 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for OrdF64 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::input::Input;
+    use differential_dataflow::operators::arrange::ArrangeBySelf;
+
+    use super::*;
+    use crate::delta_join::{Oid, Order, Uid};
+
+    fn order(oid: u64) -> Order {
+        Order {
+            oid: Oid(oid),
+            price: 10,
+            uid: Uid(1),
+        }
+    }
+
+    // 记录进入 arrangement 的所有更新, `coalesce` 为 true 时在 arrange 之前先合并
+    fn arranged_updates(coalesced: bool) -> Vec<(Order, u64, isize)> {
+        timely::execute_directly(move |worker| {
+            let seen = Rc::new(RefCell::new(Vec::new()));
+            let sink = seen.clone();
+            let mut input = worker.dataflow(|scope| {
+                let (input, order) = scope.new_collection();
+                let order = if coalesced { coalesce(&order) } else { order };
+                order
+                    .inspect(move |x| sink.borrow_mut().push(x.clone()))
+                    .arrange_by_self();
+                input
+            });
+            // 同一时刻插入之后又撤回, 分在两次 flush 中, 所以不会在 input 中被合并
+            input.insert(order(1));
+            input.insert(order(2));
+            input.flush();
+            worker.step();
+            input.remove(order(1));
+            input.close();
+            while worker.step() {}
+            let seen = seen.borrow().clone();
+            seen
+        })
+    }
+
+    #[test]
+    fn coalesce_hides_cancelled_updates_from_arrangement() {
+        let mut raw = arranged_updates(false);
+        raw.sort();
+        assert_eq!(
+            raw,
+            vec![(order(1), 0, -1), (order(1), 0, 1), (order(2), 0, 1)]
+        );
+        assert_eq!(arranged_updates(true), vec![(order(2), 0, 1)]);
+    }
+
+    #[test]
+    fn mixed_case_provinces_aggregate_as_one() {
+        use crate::aggregate::province_names_per_user;
+        use crate::delta_join::{Pid, User};
+
+        let run = |normalized: bool| {
+            timely::execute_directly(move |worker| {
+                let output = Rc::new(RefCell::new(Vec::new()));
+                let sink = output.clone();
+                let (mut o, mut u, mut p) = worker.dataflow(|scope| {
+                    let (o, order) = scope.new_collection();
+                    let (u, user) = scope.new_collection();
+                    let (p, province) = scope.new_collection();
+                    let province = if normalized {
+                        normalize_province_names(&province, canonical_name)
+                    } else {
+                        province
+                    };
+                    province_names_per_user(&order, &user, &province)
+                        .inspect(move |x| sink.borrow_mut().push(x.clone()));
+                    (o, u, p)
+                });
+                // 同一个省份以不同的大小写出现了两次
+                for name in ["beijing", " BEIJING "] {
+                    p.insert(Province {
+                        pid: Pid(1),
+                        name: name.to_string(),
+                    });
+                }
+                u.insert(User {
+                    uid: Uid(1),
+                    pid: Pid(1),
+                    padding: String::new(),
+                });
+                o.insert(order(1));
+                o.insert(order(2));
+                o.close();
+                u.close();
+                p.close();
+                while worker.step() {}
+                let output = output.borrow().clone();
+                output
+            })
+        };
+        assert_eq!(canonical_name(" BEIJING "), "Beijing");
+        let names = |names: &[&str]| (Uid(1), names.iter().map(|n| n.to_string()).collect());
+        // 不规范化时两种写法被当作两个不同的省份名称
+        assert_eq!(run(false), vec![(names(&[" BEIJING ", "beijing"]), 0, 1)]);
+        assert_eq!(run(true), vec![(names(&["Beijing"]), 0, 1)]);
+    }
+}
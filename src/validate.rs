@@ -0,0 +1,443 @@
+use differential_dataflow::hashable::Hashable;
+use differential_dataflow::operators::Count;
+use differential_dataflow::{AsCollection, Collection, Data, ExchangeData};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::Scope;
+use timely::order::PartialOrder;
+use timely::progress::{Antichain, Timestamp};
+
+use crate::delta_join::{delta_join, regular_join, Order, Province, User, Variant};
+
+/// 检查 frontier 的推进是否单调, 以及是否有更新出现在已经结束的时刻
+#[derive(Clone, Debug)]
+pub struct FrontierMonitor {
+    frontier: Antichain<u64>,
+}
+
+impl Default for FrontierMonitor {
+    fn default() -> Self {
+        FrontierMonitor {
+            frontier: Antichain::from_elem(0),
+        }
+    }
+}
+
+impl FrontierMonitor {
+    // 观察到新的 frontier, 如果比之前的 frontier 小则 panic
+    pub fn observe_frontier(&mut self, frontier: &[u64]) {
+        let frontier = Antichain::from(frontier.to_vec());
+        assert!(
+            PartialOrder::less_equal(&self.frontier, &frontier),
+            "frontier regressed from {:?} to {:?}",
+            self.frontier,
+            frontier
+        );
+        self.frontier = frontier;
+    }
+
+    // 观察到时刻 `time` 的更新, 如果这个时刻已经结束 (不在 frontier 之后) 则 panic
+    pub fn observe_update(&self, time: &u64) {
+        assert!(
+            self.frontier.less_equal(time),
+            "update at closed time {} (frontier {:?})",
+            time,
+            self.frontier
+        );
+    }
+}
+
+// 原样输出 collection, 同时检查它的 frontier 从不后退, 并且一个时刻的输出结束之后不会再有同一时刻的更新,
+// 违反时 panic
+pub fn assert_frontier_monotone<S, D>(collection: &Collection<S, D>) -> Collection<S, D>
+where
+    S: Scope<Timestamp = u64>,
+    D: Data,
+{
+    let mut monitor = FrontierMonitor::default();
+    collection
+        .inner
+        .unary_frontier(Pipeline, "AssertFrontierMonotone", move |_, _| {
+            move |input, output| {
+                input.for_each(|time, data| {
+                    monitor.observe_update(time.time());
+                    for (_, t, _) in data.iter() {
+                        monitor.observe_update(t);
+                    }
+                    output.session(&time).give_iterator(data.iter().cloned());
+                });
+                monitor.observe_frontier(&input.frontier().frontier());
+            }
+        })
+        .as_collection()
+}
+
+// 在同一份 input 上同时运行 `delta_join` 和 `regular_join`, 每个时刻都检查两者的差 (delta - regular) 为空,
+// 否则 panic。返回 `delta_join` 的输出, 并且输出的 frontier 会等待检查完成, 所以下游看到某个时刻的结果时,
+// 这个时刻已经检查过了
+pub fn live_agreement<S>(
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, (Order, User, Province)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    let delta = delta_join(order, user, province);
+    let regular = regular_join(order, user, province);
+    let check = delta
+        .concat(&regular.negate())
+        .consolidate()
+        .inspect(|(row, time, diff)| {
+            panic!(
+                "delta_join and regular_join disagree at time {}: {:?} with diff {}",
+                time, row, diff
+            )
+        })
+        .filter(|_| false);
+    delta.concat(&check)
+}
+
+// 在同一份 input 上运行 `a` 和 `b` 两种实现, 输出两者不一致的行以及差值 (a 中的 multiplicity 减去 b 中的),
+// 两者一致时输出为空。与 `live_agreement` 只报告是否一致不同, 这里可以看到具体是哪些行不同
+pub fn diff_variants<S>(
+    a: Variant,
+    b: Variant,
+    order: &Collection<S, Order>,
+    user: &Collection<S, User>,
+    province: &Collection<S, Province>,
+) -> Collection<S, ((Order, User, Province), isize)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    diff_joins(
+        &a.join(order, user, province),
+        &b.join(order, user, province),
+    )
+}
+
+// 与 `diff_variants` 相同, 但是比较的是两个已经构建好的 join 输出, 例如不在 `Variant` 中的 `DeltaJoinBuilder`
+pub fn diff_joins<S>(
+    a: &Collection<S, (Order, User, Province)>,
+    b: &Collection<S, (Order, User, Province)>,
+) -> Collection<S, ((Order, User, Province), isize)>
+where
+    S: Scope<Timestamp = u64>,
+{
+    a.concat(&b.negate()).count()
+}
+
+// 原样输出 collection, 同时检查 consolidate 之后每一行的 multiplicity 都不超过 `max`, 否则 panic,
+// 用来及时发现 key 配置错误导致的笛卡尔积爆炸。与 `live_agreement` 一样, 输出的 frontier 会等待检查完成
+pub fn cap_multiplicity<S, D>(collection: &Collection<S, D>, max: isize) -> Collection<S, D>
+where
+    S: Scope<Timestamp = u64>,
+    D: ExchangeData + Hashable,
+{
+    let check = collection
+        .count()
+        .inspect(move |((row, count), time, _)| {
+            assert!(
+                count.abs() <= max,
+                "multiplicity {} exceeds {} at time {}: {:?}",
+                count,
+                max,
+                time,
+                row
+            );
+        })
+        .filter(|_| false)
+        .map(|(row, _)| row);
+    collection.concat(&check)
+}
+
+// 检查 half_join 的 `frontier_func` 对 `times` 中的每个时间 t 都是 sound 的: 产生的 antichain 非空,
+// 并且其中的每个元素都严格小于 t (t 为最小的时间时没有更小的时间, 允许等于 t)。
+// 例如 `delta_join::step_back` 和 `delta_join::step_back_product`, times 应该包含 0 和 u64::MAX 这样的边界值,
+// 参考 `tests/frontier_func.rs` 中的 property test。
+// 返回第一个不满足条件的时间以及它产生的 antichain
+pub fn check_frontier_func<T, F>(
+    frontier_func: F,
+    times: impl IntoIterator<Item = T>,
+) -> Result<(), (T, Vec<T>)>
+where
+    T: Timestamp,
+    F: Fn(&T, &mut Antichain<T>),
+{
+    for time in times {
+        let mut antichain = Antichain::new();
+        frontier_func(&time, &mut antichain);
+        let elements = antichain.elements();
+        let sound = !elements.is_empty()
+            && elements.iter().all(|e| {
+                if time == T::minimum() {
+                    *e == time
+                } else {
+                    e.less_than(&time)
+                }
+            });
+        if !sound {
+            return Err((time, elements.to_vec()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use differential_dataflow::consolidation::consolidate_updates;
+    use differential_dataflow::input::Input;
+    use timely::dataflow::operators::Map;
+
+    use super::*;
+    use crate::dataset::Update;
+    use crate::delta_join::{delta_join, Oid, Pid, Uid};
+    use crate::gen::generate;
+    use crate::harness::{self, HarnessScope};
+
+    type Row = (Order, User, Province);
+
+    // 订单, 用户, 省份分散在 0..5 这些时刻插入, 三分之一的订单在插入两个时刻之后被撤回
+    #[allow(clippy::type_complexity)]
+    fn updates() -> (Vec<Update<Order>>, Vec<Update<User>>, Vec<Update<Province>>) {
+        let dataset = generate(5, 40, 0);
+        let mut orders = Vec::new();
+        for (i, o) in dataset.orders.into_iter().enumerate() {
+            let t = (i % 5) as u64;
+            if i % 3 == 0 {
+                orders.push((o.clone(), t + 2, -1));
+            }
+            orders.push((o, t, 1));
+        }
+        let users = dataset
+            .users
+            .into_iter()
+            .enumerate()
+            .map(|(i, u)| (u, ((i + 1) % 5) as u64, 1))
+            .collect();
+        let provinces = dataset
+            .provinces
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| (p, ((i + 2) % 5) as u64, 1))
+            .collect();
+        (orders, users, provinces)
+    }
+
+    // 按照时间逐个输入更新, 每个时刻输入完之后都运行到 join 的输出越过这个时刻,
+    // 所以检查会在每一个中间的 frontier 上发生, 而不是只在所有数据都输入之后
+    fn stream<F>(
+        join: F,
+        orders: Vec<Update<Order>>,
+        users: Vec<Update<User>>,
+        provinces: Vec<Update<Province>>,
+    ) -> Vec<Update<Row>>
+    where
+        F: for<'a> Fn(
+                &Collection<HarnessScope<'a>, Order>,
+                &Collection<HarnessScope<'a>, User>,
+                &Collection<HarnessScope<'a>, Province>,
+            ) -> Collection<HarnessScope<'a>, Row>
+            + Send
+            + Sync
+            + 'static,
+    {
+        timely::execute_directly(move |worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut order_input, mut user_input, mut province_input, probe) =
+                worker.dataflow(|scope| {
+                    let (order_input, order) = scope.new_collection();
+                    let (user_input, user) = scope.new_collection();
+                    let (province_input, province) = scope.new_collection();
+                    let probe = join(&order, &user, &province)
+                        .inspect(move |x| sink.borrow_mut().push(x.clone()))
+                        .probe();
+                    (order_input, user_input, province_input, probe)
+                });
+            let end = orders.iter().map(|(_, t, _)| *t).max().unwrap_or(0) + 1;
+            for time in 0..end {
+                orders
+                    .iter()
+                    .filter(|(_, t, _)| *t == time)
+                    .for_each(|(d, t, r)| order_input.update_at(d.clone(), *t, *r));
+                users
+                    .iter()
+                    .filter(|(_, t, _)| *t == time)
+                    .for_each(|(d, t, r)| user_input.update_at(d.clone(), *t, *r));
+                provinces
+                    .iter()
+                    .filter(|(_, t, _)| *t == time)
+                    .for_each(|(d, t, r)| province_input.update_at(d.clone(), *t, *r));
+                order_input.advance_to(time + 1);
+                user_input.advance_to(time + 1);
+                province_input.advance_to(time + 1);
+                order_input.flush();
+                user_input.flush();
+                province_input.flush();
+                worker.step_while(|| probe.less_than(&(time + 1)));
+            }
+            order_input.close();
+            user_input.close();
+            province_input.close();
+            while worker.step() {}
+            let mut output = output.borrow().clone();
+            consolidate_updates(&mut output);
+            output
+        })
+    }
+
+    #[test]
+    fn assert_frontier_monotone_passes_on_streaming_join() {
+        let (orders, users, provinces) = updates();
+        let output = stream(
+            |o, u, p| assert_frontier_monotone(&delta_join(o, u, p)),
+            orders.clone(),
+            users.clone(),
+            provinces.clone(),
+        );
+        let expected = harness::run(Variant::Delta, orders, users, provinces);
+        assert!(!expected.is_empty());
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "update at closed time")]
+    fn assert_frontier_monotone_catches_update_at_closed_time() {
+        timely::execute_directly(|worker| {
+            let (mut input, probe) = worker.dataflow(|scope| {
+                let (input, collection) = scope.new_collection::<u64, isize>();
+                // 把所有更新的时间改成 0, 时刻 0 结束之后到达的更新就变成了对已经结束的时刻的修改
+                let rewound = collection.inner.map(|(d, _, r)| (d, 0, r)).as_collection();
+                let probe = assert_frontier_monotone(&rewound).probe();
+                (input, probe)
+            });
+            input.insert(1);
+            input.advance_to(1);
+            input.flush();
+            worker.step_while(|| probe.less_than(&1));
+            for _ in 0..10 {
+                worker.step();
+            }
+            input.insert(2);
+            input.advance_to(2);
+            input.flush();
+            worker.step_while(|| probe.less_than(&2));
+        });
+    }
+
+    #[test]
+    fn live_agreement_holds_over_streaming_updates() {
+        let (orders, users, provinces) = updates();
+        let output = stream(
+            |o, u, p| live_agreement(o, u, p),
+            orders.clone(),
+            users.clone(),
+            provinces.clone(),
+        );
+        let expected = harness::run(Variant::Regular, orders, users, provinces);
+        assert!(!expected.is_empty());
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds")]
+    fn cap_multiplicity_panics_on_cartesian_blow_up() {
+        timely::execute_directly(|worker| {
+            let (mut order_input, mut user_input, mut province_input) = worker.dataflow(|scope| {
+                let (order_input, order) = scope.new_collection();
+                let (user_input, user) = scope.new_collection();
+                let (province_input, province) = scope.new_collection();
+                cap_multiplicity(&regular_join(&order, &user, &province), 2);
+                (order_input, user_input, province_input)
+            });
+            order_input.insert(Order {
+                oid: Oid(1),
+                price: 10,
+                uid: Uid(1),
+            });
+            // 同一个用户出现了 3 次, join 结果的 multiplicity 为 3
+            for _ in 0..3 {
+                user_input.insert(User {
+                    uid: Uid(1),
+                    pid: Pid(1),
+                    padding: String::new(),
+                });
+            }
+            province_input.insert(Province {
+                pid: Pid(1),
+                name: "p".to_string(),
+            });
+        });
+    }
+
+    #[test]
+    fn diff_joins_reports_broken_comparator() {
+        use crate::builder::{DeltaJoinBuilder, Relation};
+
+        let mut output = timely::execute_directly(|worker| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            let sink = output.clone();
+            let (mut order_input, mut user_input, mut province_input) = worker.dataflow(|scope| {
+                let (order_input, order) = scope.new_collection();
+                let (user_input, user) = scope.new_collection();
+                let (province_input, province) = scope.new_collection();
+                // order 的更新也可以看到同一时刻的 user, 与 user 一侧的 `<=` 一起, 同一时刻的 order 和 user
+                // 会互相看到对方, 这一行被计算两次
+                let broken = DeltaJoinBuilder::new()
+                    .edge(Relation::Order, "uid", Relation::User)
+                    .edge(Relation::User, "pid", Relation::Province)
+                    .comparator((Relation::Order, Relation::User), |t1, t2| t1 <= t2)
+                    .build(&order, &user, &province)
+                    .unwrap();
+                let delta = Variant::Delta.join(&order, &user, &province);
+                diff_joins(&broken, &delta).inspect(move |x| sink.borrow_mut().push(x.clone()));
+                (order_input, user_input, province_input)
+            });
+            province_input.insert(Province {
+                pid: Pid(1),
+                name: "p".to_string(),
+            });
+            order_input.advance_to(1);
+            user_input.advance_to(1);
+            province_input.advance_to(1);
+            order_input.insert(Order {
+                oid: Oid(1),
+                price: 10,
+                uid: Uid(1),
+            });
+            user_input.insert(User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            });
+            order_input.close();
+            user_input.close();
+            province_input.close();
+            while worker.step() {}
+            let output = output.borrow().clone();
+            output
+        });
+        consolidate_updates(&mut output);
+        let row = (
+            Order {
+                oid: Oid(1),
+                price: 10,
+                uid: Uid(1),
+            },
+            User {
+                uid: Uid(1),
+                pid: Pid(1),
+                padding: String::new(),
+            },
+            Province {
+                pid: Pid(1),
+                name: "p".to_string(),
+            },
+        );
+        assert_eq!(output, vec![((row, 1), 1, 1)]);
+    }
+}
@@ -0,0 +1,77 @@
+#![cfg(feature = "flight")]
+
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use arrow::record_batch::RecordBatch;
+use arrow_flight::{FlightClient, Ticket};
+use dd_examples::dataset::Update;
+use dd_examples::delta_join::{Oid, Order, Pid, Province, Uid, User};
+use dd_examples::sink::flight::{serve, snapshots, to_record_batch, SnapshotService};
+use futures::stream::TryStreamExt;
+use tonic::transport::{Channel, Endpoint};
+
+fn row(oid: u64) -> (Order, User, Province) {
+    (
+        Order {
+            oid: Oid(oid),
+            price: oid * 10,
+            uid: Uid(1),
+        },
+        User {
+            uid: Uid(1),
+            pid: Pid(1),
+            padding: String::new(),
+        },
+        Province {
+            pid: Pid(1),
+            name: "p1".to_string(),
+        },
+    )
+}
+
+fn updates() -> Vec<Update<(Order, User, Province)>> {
+    vec![(row(1), 0, 1), (row(2), 1, 1), (row(1), 2, -1)]
+}
+
+// 找一个空闲的端口, 关闭之后交给 server 使用
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// server 在后台启动, 连接成功之前重试
+async fn connect(addr: SocketAddr) -> Channel {
+    let endpoint = Endpoint::from_shared(format!("http://{}", addr)).unwrap();
+    for _ in 0..50 {
+        if let Ok(channel) = endpoint.connect().await {
+            return channel;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("flight server at {} did not start", addr);
+}
+
+#[tokio::test]
+async fn do_get_returns_snapshot() {
+    let addr = free_addr();
+    let service = SnapshotService::new(updates()).unwrap();
+    tokio::spawn(serve(service, addr));
+
+    let mut client = FlightClient::new(connect(addr).await);
+    let batches: Vec<RecordBatch> = client
+        .do_get(Ticket::new("1"))
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    let expected = to_record_batch(&snapshots(updates())[&1]).unwrap();
+    assert_eq!(batches, vec![expected]);
+
+    let missing = client.do_get(Ticket::new("5")).await;
+    assert!(missing.is_err());
+}
@@ -0,0 +1,38 @@
+use dd_examples::delta_join::{step_back, step_back_product};
+use dd_examples::validate::check_frontier_func;
+use proptest::prelude::*;
+use timely::order::Product;
+use timely::progress::Antichain;
+
+// 时间的分布偏向边界值, 这样 shrink 时更容易找到 `saturating_sub` 的 off-by-one
+fn time() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        Just(0),
+        Just(1),
+        Just(u64::MAX),
+        Just(u64::MAX - 1),
+        any::<u64>()
+    ]
+}
+
+proptest! {
+    #[test]
+    fn step_back_is_sound(t in time()) {
+        prop_assert_eq!(check_frontier_func(step_back, [t]), Ok(()));
+    }
+
+    #[test]
+    fn step_back_product_is_sound(outer in time(), inner in time()) {
+        let t = Product::new(outer, inner);
+        prop_assert_eq!(check_frontier_func(step_back_product, [t]), Ok(()));
+    }
+}
+
+#[test]
+fn off_by_one_is_detected() {
+    // 没有后退的 frontier_func 会让 half_join 在同一时刻的更新到齐之前就输出
+    let broken = |t: &u64, a: &mut Antichain<u64>| {
+        a.insert(*t);
+    };
+    assert_eq!(check_frontier_func(broken, [0, 5]), Err((5, vec![5])));
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "kafka")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use dd_examples::dataset::InputOp;
+use dd_examples::delta_join::{delta_join, Oid, Order, Pid, Province, Uid, User};
+use dd_examples::source::kafka::{consume, Event, KafkaConfig, Message, Timestamping, VecSource};
+use dd_examples::source::Inputs;
+use differential_dataflow::consolidation::consolidate_updates;
+use serde::Serialize;
+
+fn config(timestamping: Timestamping) -> KafkaConfig {
+    KafkaConfig {
+        brokers: "localhost:9092".to_string(),
+        group_id: "test".to_string(),
+        order_topic: "orders".to_string(),
+        user_topic: "users".to_string(),
+        province_topic: "provinces".to_string(),
+        timestamping,
+    }
+}
+
+fn message<D: Serialize>(topic: &str, offset: i64, data: D, diff: isize, time: u64) -> Message {
+    let event = Event {
+        data,
+        diff,
+        event_time: Some(time),
+    };
+    Message {
+        topic: topic.to_string(),
+        offset,
+        payload: serde_json::to_vec(&event).unwrap(),
+    }
+}
+
+fn order(oid: u64, uid: u64) -> Order {
+    Order {
+        oid: Oid(oid),
+        price: oid * 10,
+        uid: Uid(uid),
+    }
+}
+
+fn user(uid: u64, pid: u64) -> User {
+    User {
+        uid: Uid(uid),
+        pid: Pid(pid),
+        padding: String::new(),
+    }
+}
+
+fn province(pid: u64) -> Province {
+    Province {
+        pid: Pid(pid),
+        name: format!("p{}", pid),
+    }
+}
+
+#[test]
+fn decode_uses_configured_timestamping() {
+    let m = message("orders", 7, order(1, 1), 1, 3);
+    assert_eq!(
+        config(Timestamping::EventTime).decode(&m).unwrap(),
+        InputOp::Order(order(1, 1), 3, 1)
+    );
+    assert_eq!(
+        config(Timestamping::Offset).decode(&m).unwrap(),
+        InputOp::Order(order(1, 1), 7, 1)
+    );
+    let unknown = Message {
+        topic: "other".to_string(),
+        ..m
+    };
+    assert!(config(Timestamping::EventTime).decode(&unknown).is_err());
+}
+
+#[test]
+fn join_processes_a_batch_of_messages() {
+    let messages = vec![
+        message("provinces", 0, province(1), 1, 0),
+        message("users", 0, user(1, 1), 1, 0),
+        message("orders", 0, order(1, 1), 1, 1),
+        message("orders", 1, order(2, 1), 1, 2),
+        message("orders", 2, order(1, 1), -1, 3),
+    ];
+    let output = timely::execute_directly(move |worker| {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut inputs = Inputs::new();
+        let out = output.clone();
+        worker.dataflow(|scope| {
+            let (o, u, p) = inputs.to_collections(scope);
+            delta_join(&o, &u, &p).inspect(move |x| out.borrow_mut().push(x.clone()));
+        });
+
+        let mut source = VecSource::new(messages.clone());
+        let count = consume(
+            &mut source,
+            &config(Timestamping::EventTime),
+            &mut inputs,
+            2,
+            |_| {
+                worker.step();
+            },
+        )
+        .unwrap();
+        assert_eq!(count, 5);
+        inputs.close();
+        while worker.step() {}
+
+        let mut output = output.borrow().clone();
+        consolidate_updates(&mut output);
+        output
+    });
+
+    let row = |oid| (order(oid, 1), user(1, 1), province(1));
+    assert_eq!(
+        output,
+        vec![(row(1), 1, 1), (row(1), 3, -1), (row(2), 2, 1)]
+    );
+}
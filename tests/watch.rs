@@ -0,0 +1,97 @@
+#![cfg(feature = "watch")]
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dd_examples::dataset::InputOp;
+use dd_examples::delta_join::{delta_join, Oid, Order, Pid, Province, Uid, User};
+use dd_examples::source::watch::{watch_dir, DirSource};
+use dd_examples::source::Inputs;
+use differential_dataflow::consolidation::consolidate_updates;
+
+fn order(oid: u64, uid: u64) -> Order {
+    Order {
+        oid: Oid(oid),
+        price: oid * 10,
+        uid: Uid(uid),
+    }
+}
+
+fn user(uid: u64, pid: u64) -> User {
+    User {
+        uid: Uid(uid),
+        pid: Pid(pid),
+        padding: String::new(),
+    }
+}
+
+fn province(pid: u64) -> Province {
+    Province {
+        pid: Pid(pid),
+        name: format!("p{}", pid),
+    }
+}
+
+// 文件系统的事件是异步到达的, 一直 feed 直到有更新被输入
+fn feed_until_changed(source: &mut DirSource, inputs: &mut Inputs) {
+    let start = Instant::now();
+    while source.feed(inputs) == 0 {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "no file event within 5s"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn province_file_joins_and_retracts_on_delete() {
+    let dir = std::env::temp_dir().join(format!("dd_examples_watch_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("1.json");
+    let root = dir.clone();
+
+    let (joined, retracted) = timely::execute_directly(move |worker| {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut inputs = Inputs::new();
+        let out = output.clone();
+        let probe = worker.dataflow(|scope| {
+            let (o, u, p) = inputs.to_collections(scope);
+            delta_join(&o, &u, &p)
+                .inspect(move |x| out.borrow_mut().push(x.clone()))
+                .probe()
+        });
+        let mut source = watch_dir(&dir).unwrap();
+        inputs.apply(InputOp::Order(order(1, 1), 0, 1));
+        inputs.apply(InputOp::User(user(1, 1), 0, 1));
+        inputs.advance_to(1);
+
+        fs::write(&file, serde_json::to_string(&province(1)).unwrap()).unwrap();
+        feed_until_changed(&mut source, &mut inputs);
+        inputs.advance_to(2);
+        inputs.flush();
+        worker.step_while(|| probe.less_than(&2));
+        let joined = output.borrow().clone();
+
+        fs::remove_file(&file).unwrap();
+        feed_until_changed(&mut source, &mut inputs);
+        inputs.advance_to(3);
+        inputs.flush();
+        worker.step_while(|| probe.less_than(&3));
+        let mut retracted = output.borrow().clone();
+        consolidate_updates(&mut retracted);
+
+        inputs.close();
+        while worker.step() {}
+        (joined, retracted)
+    });
+    let _ = fs::remove_dir_all(&root);
+
+    let row = (order(1, 1), user(1, 1), province(1));
+    assert_eq!(joined, vec![(row.clone(), 1, 1)]);
+    assert_eq!(retracted, vec![(row.clone(), 1, 1), (row, 2, -1)]);
+}